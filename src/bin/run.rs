@@ -0,0 +1,215 @@
+//runs a project without a frontend: scripted input in, screenshots and final state out
+//makecode-arcade-run <project> [--frames N | --seconds S] [--input TIMELINE | --input @FILE]
+//                    [--screenshot-every N] [--out DIR] [--seed N] [--record FILE | --replay FILE]
+//runs are deterministic, the same seed (0 by default) and input give the same frames.
+//--record saves the run as a replay, --replay plays one back instead of the timeline, for its length by default
+//a timeline is a list of FRAME:KEYS entries, KEYS held from that frame on, e.g. "0:right 30:right,a 45:"
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use makecode_arcade_emu::{
+    emulator::game::{KeyCode, BMP_HEIGHT, BMP_WIDTH},
+    engine::Frame,
+    replay::Replay,
+    Emulator, GameEvent, InputState, FPS,
+};
+
+struct Options {
+    project: PathBuf,
+    //None: 10 seconds, or the length of the replay
+    frames: Option<u32>,
+    //(first frame, keys held) sorted by frame
+    timeline: Vec<(u32, InputState)>,
+    //0 for the final frame only
+    screenshot_every: u32,
+    out: PathBuf,
+    seed: u64,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: makecode-arcade-run <project> [--frames N | --seconds S] [--input TIMELINE | --input @FILE] [--screenshot-every N] [--out DIR] [--seed N] [--record FILE | --replay FILE]"
+    );
+    process::exit(2)
+}
+
+fn parse_key(name: &str) -> Result<KeyCode, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "up" => Ok(KeyCode::Up),
+        "down" => Ok(KeyCode::Down),
+        "left" => Ok(KeyCode::Left),
+        "right" => Ok(KeyCode::Right),
+        "a" => Ok(KeyCode::A),
+        "b" => Ok(KeyCode::B),
+        "x" => Ok(KeyCode::X),
+        "y" => Ok(KeyCode::Y),
+        _ => Err(format!("unknown key {}", name)),
+    }
+}
+
+//entries split by whitespace, "#" comments to the end of the line
+fn parse_timeline(text: &str) -> Result<Vec<(u32, InputState)>, String> {
+    let mut timeline = vec![];
+    for entry in text.lines().flat_map(|l| l.split('#').next().unwrap_or("").split_whitespace()) {
+        let (frame, keys) = entry.split_once(':').ok_or(format!("expected FRAME:KEYS, got {}", entry))?;
+        let frame = frame.parse::<u32>().map_err(|_| format!("bad frame in {}", entry))?;
+        let keys = keys
+            .split(',')
+            .filter(|k| !k.is_empty())
+            .map(parse_key)
+            .collect::<Result<Vec<_>, _>>()?;
+        timeline.push((frame, InputState::pressed(&keys)));
+    }
+    timeline.sort_by_key(|(frame, _)| *frame);
+    Ok(timeline)
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut project = None;
+    let mut frames = None;
+    let mut timeline = vec![];
+    let mut screenshot_every = 0;
+    let mut out = PathBuf::from(".");
+    let mut seed = 0;
+    let mut record = None;
+    let mut replay = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--frames" => frames = Some(value(&arg)?.parse().map_err(|_| "bad --frames")?),
+            "--seconds" => {
+                let secs: f64 = value(&arg)?.parse().map_err(|_| "bad --seconds")?;
+                frames = Some((secs * FPS as f64).round() as u32);
+            }
+            "--input" => {
+                let v = value(&arg)?;
+                let text = match v.strip_prefix('@') {
+                    Some(file) => fs::read_to_string(file).map_err(|e| format!("read {}: {}", file, e))?,
+                    None => v,
+                };
+                timeline = parse_timeline(&text)?;
+            }
+            "--screenshot-every" => {
+                screenshot_every = value(&arg)?.parse().map_err(|_| "bad --screenshot-every")?
+            }
+            "--out" => out = PathBuf::from(value(&arg)?),
+            "--seed" => seed = value(&arg)?.parse().map_err(|_| "bad --seed")?,
+            "--record" => record = Some(PathBuf::from(value(&arg)?)),
+            "--replay" => replay = Some(PathBuf::from(value(&arg)?)),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if project.is_none() => project = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    Ok(Options {
+        project: project.ok_or("no project given")?,
+        frames,
+        timeline,
+        screenshot_every,
+        out,
+        seed,
+        record,
+        replay,
+    })
+}
+
+fn write_png(path: &Path, frame: &Frame) -> Result<(), String> {
+    let mut rgb = Vec::with_capacity(frame.pixels.len() * 3);
+    for xrgb in frame.pixels.iter() {
+        rgb.extend_from_slice(&xrgb.to_be_bytes()[1..]);
+    }
+    let file = fs::File::create(path).map_err(|e| format!("create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), BMP_WIDTH as u32, BMP_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut w| w.write_image_data(&rgb))
+        .map_err(|e| format!("write {}: {}", path.display(), e))
+}
+
+fn run(opts: Options) -> Result<(), String> {
+    fs::create_dir_all(&opts.out).map_err(|e| format!("create {}: {}", opts.out.display(), e))?;
+    let mut emulator = Emulator::new();
+    emulator.subscribe(|evt| match evt {
+        GameEvent::Loaded(meta) => println!("loaded {}", meta.name),
+        GameEvent::GameOver { win } => println!("game over, win: {}", win),
+        _ => {}
+    });
+    let mut frames = opts.frames.unwrap_or(FPS * 10);
+    if let Some(path) = opts.replay.as_ref() {
+        let replay = Replay::load(path).map_err(|e| format!("load {}: {}", path.display(), e))?;
+        frames = opts.frames.unwrap_or(replay.frames);
+        emulator.start_replay(replay);
+    } else if opts.record.is_some() {
+        emulator.start_recording(opts.seed);
+    } else {
+        emulator.set_deterministic(Some(opts.seed));
+    }
+    emulator
+        .load_path(&opts.project)
+        .map_err(|e| format!("load {}: {}", opts.project.display(), e))?;
+
+    let dt = Duration::from_micros(1_000_000 / FPS as u64);
+    let mut input = InputState::default();
+    let mut timeline = opts.timeline.iter().peekable();
+    for n in 0..frames {
+        while let Some((_, held)) = timeline.next_if(|(at, _)| *at <= n) {
+            input = *held;
+        }
+        let frame = emulator.step(dt, &input);
+        if opts.screenshot_every > 0 && (n + 1) % opts.screenshot_every == 0 {
+            write_png(&opts.out.join(format!("frame-{:06}.png", n + 1)), frame)?;
+        }
+    }
+
+    let frame = emulator.frame();
+    write_png(&opts.out.join("final.png"), frame)?;
+    let state = serde_json::json!({
+        "frames": frames,
+        "score": frame.score,
+        "gameOver": frame.game_over.is_some(),
+        "win": frame.game_over,
+    });
+    let path = opts.out.join("final.json");
+    fs::write(&path, state.to_string()).map_err(|e| format!("write {}: {}", path.display(), e))?;
+    match (opts.record.as_ref(), emulator.stop_recording()) {
+        (Some(path), Some(replay)) => replay.save(path).map_err(|e| format!("write {}: {}", path.display(), e)),
+        _ => Ok(()),
+    }
+}
+
+fn main() {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", e);
+            usage()
+        }
+    };
+    if let Err(e) = run(opts) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[test]
+fn test_parse_timeline() {
+    let timeline = parse_timeline("30:right,A # jump\n0:right\n45:").unwrap();
+    let frames: Vec<u32> = timeline.iter().map(|(f, _)| *f).collect();
+    assert_eq!(frames, vec![0, 30, 45]);
+    assert_eq!(timeline[1].1, InputState { right: true, a: true, ..Default::default() });
+    assert_eq!(timeline[2].1, InputState::default());
+    assert!(parse_timeline("10:jump").is_err());
+    assert!(parse_timeline("right").is_err());
+
+    let opts = parse_args(["game.mkcd", "--seconds", "2", "--input", "0:a"].iter().map(|s| s.to_string())).unwrap();
+    assert_eq!((opts.frames, opts.timeline.len(), opts.seed), (Some(FPS * 2), 1, 0));
+    assert!(parse_args(["--frames", "3"].iter().map(|s| s.to_string())).is_err());
+}
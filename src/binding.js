@@ -170,24 +170,50 @@
     };
 
 
+    //milliSec has the fraction of the frame time, the time past an interval counts for the next one
     THIZ._221149842913game_loop = function (milliSec) {
         game._updateCbs.forEach(f => f());
         game._intervalCbs.forEach(item => {
-            let { f, interval, elasped } = item;
-            elasped += milliSec;
-            //_log(`js:elasped ${elasped}`);
-            let wait = interval - elasped;
-            if (wait <= 0) {
-                f();
-                elasped = 0;
+            item.elasped += milliSec;
+            //_log(`js:elasped ${item.elasped}`);
+            if (item.interval <= 0) {
+                item.elasped = 0;
+                item.f();
+                return;
+            }
+            while (item.elasped >= item.interval) {
+                item.elasped -= item.interval;
+                item.f();
             }
-            item.elasped = elasped;
         });
     };
 
     const game = {
-        over(win) {
-            ffi.game_over(win ? 1 : 0);
+        //message and effect name set for a win (true) and a loss (false), the engine has defaults
+        _gameOver: { true: {}, false: {} },
+        _gameOverHandler: null,
+        over(win, effect) {
+            if (effect) game.setGameOverEffect(!!win, effect);
+            game.gameOver(win);
+        },
+        gameOver(win) {
+            win = !!win;
+            //a handler replaces the game over screen
+            if (game._gameOverHandler) {
+                game._gameOverHandler(win);
+                return;
+            }
+            const { message, effect } = game._gameOver[win];
+            ffi.game_over(win ? 1 : 0, message || '', effect || '');
+        },
+        setGameOverMessage(win, message) {
+            game._gameOver[!!win].message = `${message}`;
+        },
+        setGameOverEffect(win, effect) {
+            game._gameOver[!!win].effect = effect && effect.name;
+        },
+        onGameOver(f) {
+            game._gameOverHandler = f;
         },
         _updateCbs: [],
         onUpdate(f) {
@@ -205,7 +231,8 @@
 
     const scene = {
         setBackgroundColor(c) {
-            ffi.scene_set_background_color(`${c}`);
+            //palette index, as one hex digit like img`` uses
+            ffi.scene_set_background_color(typeof c == 'number' ? c.toString(16) : `${c}`);
         },
         screenHeight() {
             return 120;
@@ -237,12 +264,17 @@
 
     const effects = {
         blizzard:new Effect("blizzard"),
+        confetti:new Effect("confetti"),
+        melt:new Effect("melt"),
     };
 
     function img(s) {
         return new Image(s[0]);
     }
 
+    //one seeded generator for the game and the engine, see effect::random
+    Math.random = ffi.random;
+
     function randint(min, max) {
         return Math.floor(Math.random() * (max - min) + min);
     }
@@ -268,10 +300,157 @@
             }
             return new Image(lines.join('\n'));
         },
-    };        
+        ofBuffer(buf) {
+            const bmp = ffi.image_of_buffer(buf);
+            return bmp === undefined ? null : new Image(bmp);
+        },
+    };
+
+    //buffers are hex strings on this side
+    function hex(s) {
+        return s[0];
+    }
+
+    //the .g.ts files register a factory per asset kind, returning the asset of a name
+    const helpers = {
+        _factories: {},
+        _registerFactory(kind, factory) {
+            this._factories[kind] ??= [];
+            this._factories[kind].push(factory);
+        },
+        _getFactoryInstance(kind, name) {
+            for (const factory of this._factories[kind] || []) {
+                const v = factory(name);
+                if (v) return v;
+            }
+            return null;
+        },
+        stringTrim(s) {
+            return s.trim();
+        },
+    };
+
+    //projects without .g.ts factories, or assets only in .jres, are found in the rust asset table
+    function assetImage(kind, name) {
+        const v = helpers._getFactoryInstance(kind, name);
+        if (v) return v;
+        const bmp = ffi.asset_image(name);
+        return bmp === undefined ? null : new Image(bmp);
+    }
+
+    const TileScale = {
+        Eight: 3,
+        Sixteen: 4,
+        ThirtyTwo: 5,
+    };
+
+    class TileMapData {
+        constructor(data, layer, tileset, scale) {
+            this.data = data;
+            this.layer = layer;
+            this.tileset = tileset;
+            this.scale = scale;
+        }
+    }
+
+    const tiles = {
+        createTilemap(data, layer, tileset, scale) {
+            return new TileMapData(data, layer, tileset, scale ?? TileScale.Sixteen);
+        },
+        setCurrentTilemap(tilemap) {
+            if (!tilemap) return;
+            ffi.scene_set_tilemap(tilemap.data, tilemap.tileset.map(t => t ? t.bmp : ''), tilemap.scale);
+        },
+        setTilemap(tilemap) {
+            this.setCurrentTilemap(tilemap);
+        },
+    };
+
+    function tilemap(s) {
+        const v = helpers._getFactoryInstance('tilemap', s[0]);
+        if (v) return v;
+        const t = ffi.asset_tilemap(s[0]);
+        return t === undefined ? null : tiles.createTilemap(t[0], null, t[1].map(bmp => new Image(bmp)), t[2]);
+    }
+
+    const assets = {
+        image: (s) => assetImage('image', s[0]),
+        tile: (s) => assetImage('tile', s[0]),
+        animation(s) {
+            const v = helpers._getFactoryInstance('animation', s[0]);
+            if (v) return v;
+            const frames = ffi.asset_animation(s[0]);
+            return frames === undefined ? null : frames.map(bmp => new Image(bmp));
+        },
+        tilemap,
+    };
+
+    //save states: functions can't be saved, the scripts keep the ones they made and only their data is put back.
+    //the data is the top level variables rust registers after the scripts ran, and the timers
+    function isRecord(v) {
+        const proto = v !== null && typeof v == 'object' && Object.getPrototypeOf(v);
+        return proto === Object.prototype || proto === null;
+    }
+
+    function plain(v) {
+        if (v === null || ['number', 'string', 'boolean'].includes(typeof v)) return true;
+        if (v instanceof Sprite || v instanceof Image) return true;
+        if (Array.isArray(v)) return v.every(plain);
+        return isRecord(v) && Object.values(v).every(plain);
+    }
+
+    //variable name to [getter, setter]
+    const registry = {};
+
+    THIZ._221149842913register_state = function (vars) {
+        Object.assign(registry, vars);
+    };
+
+    THIZ._221149842913save_state = function () {
+        const vars = {};
+        for (const [name, [get]] of Object.entries(registry)) {
+            try {
+                const v = get();
+                if (plain(v)) vars[name] = v;
+            } catch (e) {
+                //not initialized yet, or cyclic
+            }
+        }
+        const timers = game._intervalCbs.map(item => item.elasped);
+        return JSON.stringify({ vars, timers }, (k, v) =>
+            v instanceof Sprite ? { $sprite: v.i } : v instanceof Image ? { $image: v.bmp } : v);
+    };
+
+    THIZ._221149842913load_state = function (json) {
+        const state = JSON.parse(json, (k, v) =>
+            isRecord(v) && '$sprite' in v ? Sprite.new(v.$sprite) : isRecord(v) && '$image' in v ? new Image(v.$image) : v);
+        const vars = state.vars || {};
+        for (const [name, [, set]] of Object.entries(registry)) {
+            if (!(name in vars)) continue;
+            try {
+                set(vars[name]);
+            } catch (e) {
+                //its script stopped before declaring it
+            }
+        }
+        (state.timers || []).forEach((elasped, i) => {
+            if (game._intervalCbs[i]) game._intervalCbs[i].elasped = elasped;
+        });
+    };
+
+    //consts keep their object, only what is in it is restored
+    THIZ._221149842913assign = function (target, v) {
+        if (Array.isArray(target) && Array.isArray(v)) {
+            target.splice(0, target.length, ...v);
+        } else if (isRecord(target) && isRecord(v)) {
+            Object.keys(target).forEach(k => delete target[k]);
+            Object.assign(target, v);
+        }
+    };
 
     Object.assign(THIZ, {
-        img, image,
+        img, image, hex,
+        assets, helpers, tiles, tilemap, TileScale,
         sprites, SpriteKind,
         controller, ControllerButtonEvent,
         animation, info, game, scene, effects,
@@ -0,0 +1,371 @@
+//drive games from rust, without a libretro frontend: for tools and tests
+use std::{path::Path, time::Duration};
+
+use crate::{
+    emulator::{
+        game::KeyCode,
+        savestate::StateError,
+    },
+    engine::{Engine, Event, Frame, Project, ProjectMeta, Tick},
+    loader::{self, LoadError},
+    replay::{Player, Recorder, Replay},
+};
+
+//buttons held down during a step
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputState {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub a: bool,
+    pub b: bool,
+    pub x: bool,
+    pub y: bool,
+}
+
+impl InputState {
+    pub fn pressed(keys: &[KeyCode]) -> Self {
+        let mut input = InputState::default();
+        for key in keys {
+            if let Some(held) = input.key_mut(*key) {
+                *held = true;
+            }
+        }
+        input
+    }
+
+    pub fn set(&mut self, key: KeyCode, held: bool) {
+        if let Some(h) = self.key_mut(key) {
+            *h = held;
+        }
+    }
+
+    pub fn is_pressed(&self, key: KeyCode) -> bool {
+        self.keys().iter().any(|(k, held)| *k == key && *held)
+    }
+
+    fn keys(&self) -> [(KeyCode, bool); 8] {
+        [
+            (KeyCode::Up, self.up),
+            (KeyCode::Down, self.down),
+            (KeyCode::Left, self.left),
+            (KeyCode::Right, self.right),
+            (KeyCode::A, self.a),
+            (KeyCode::B, self.b),
+            (KeyCode::X, self.x),
+            (KeyCode::Y, self.y),
+        ]
+    }
+
+    fn key_mut(&mut self, key: KeyCode) -> Option<&mut bool> {
+        match key {
+            KeyCode::Up => Some(&mut self.up),
+            KeyCode::Down => Some(&mut self.down),
+            KeyCode::Left => Some(&mut self.left),
+            KeyCode::Right => Some(&mut self.right),
+            KeyCode::A => Some(&mut self.a),
+            KeyCode::B => Some(&mut self.b),
+            KeyCode::X => Some(&mut self.x),
+            KeyCode::Y => Some(&mut self.y),
+            KeyCode::None => None,
+        }
+    }
+
+    //key events that turn the previous state into this one
+    pub fn changes(&self, previous: &InputState) -> Vec<Event> {
+        previous
+            .keys()
+            .iter()
+            .zip(self.keys().iter())
+            .filter_map(|((key, was), (_, now))| match (was, now) {
+                (false, true) => Some(Event::KeyDown(*key)),
+                (true, false) => Some(Event::KeyUp(*key)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+//what subscribers are told about after a step
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    Loaded(ProjectMeta),
+    KeyDown(KeyCode),
+    KeyUp(KeyCode),
+    ScoreChanged(i32),
+    GameOver { win: bool },
+}
+
+pub struct Emulator {
+    engine: Engine,
+    input: InputState,
+    frame: Frame,
+    listeners: Vec<Box<dyn FnMut(&GameEvent)>>,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+}
+
+//the frame time of deterministic runs
+const FRAME_US: u32 = 1_000_000 / crate::FPS;
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        Emulator {
+            engine: Engine::new(crate::FPS, crate::AUDIO_HZ),
+            input: InputState::default(),
+            frame: Frame::blank(),
+            listeners: vec![],
+            recorder: None,
+            player: None,
+        }
+    }
+
+    //any format loader::load accepts, name_hint as there
+    pub fn load(&mut self, data: &[u8], name_hint: Option<&str>) -> Result<(), LoadError> {
+        let prj = loader::load(data, name_hint)?;
+        self.load_project(prj);
+        Ok(())
+    }
+
+    pub fn load_path(&mut self, path: &Path) -> Result<(), LoadError> {
+        let prj = loader::load_path(path)?;
+        self.load_project(prj);
+        Ok(())
+    }
+
+    //the game starts running with the next step
+    pub fn load_project(&mut self, prj: Project) {
+        let meta = prj.meta.clone();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.restart(Some(&meta.name));
+        }
+        if let Some(player) = self.player.as_mut() {
+            if player.replay().project != meta.name {
+                println!("replay of {:?} played on {:?}", player.replay().project, meta.name);
+            }
+            player.rewind();
+        }
+        self.send(Event::Load(prj));
+        self.emit(&GameEvent::Loaded(meta));
+    }
+
+    //Some(seed) makes the games loaded or restarted after this replay the same for the same input:
+    //randomness comes from the seed and every step advances the game by 1/FPS, whatever its dt
+    pub fn set_deterministic(&mut self, seed: Option<u64>) {
+        self.send(Event::Deterministic(seed));
+    }
+
+    //debug controls: while paused steps draw frames and pass keys on, but the game's time stands still
+    pub fn set_paused(&mut self, paused: bool) {
+        self.send(Event::Pause(paused));
+    }
+
+    //while paused, the next step advances the game by one frame
+    pub fn step_frame(&mut self) {
+        self.send(Event::FrameStep);
+    }
+
+    //the game's time runs at scale (0.25 to 4) times the dt of the steps
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.send(Event::TimeScale(scale));
+    }
+
+    //start the loaded game over
+    pub fn restart(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.restart(None);
+        }
+        if let Some(player) = self.player.as_mut() {
+            player.rewind();
+        }
+        self.send(Event::Restart);
+    }
+
+    //drop the loaded game and its script context, steps show a blank screen until the next load
+    pub fn unload(&mut self) {
+        self.send(Event::Unload);
+    }
+
+    //record the keys and debug controls of the game loaded or restarted next, run deterministic with seed
+    pub fn start_recording(&mut self, seed: u64) {
+        self.set_deterministic(Some(seed));
+        self.recorder = Some(Recorder::new(seed, FRAME_US, ""));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Replay> {
+        self.recorder.take().map(Recorder::finish)
+    }
+
+    //play replay on the game loaded or restarted next, the input given to step is ignored until it ends
+    pub fn start_replay(&mut self, replay: Replay) {
+        if replay.frame_us != FRAME_US {
+            println!("replay recorded at {}us a frame, played at {}us", replay.frame_us, FRAME_US);
+        }
+        self.set_deterministic(Some(replay.seed));
+        self.player = Some(Player::new(replay));
+    }
+
+    //a replay is being played
+    pub fn replaying(&self) -> bool {
+        self.player.as_ref().map_or(false, |p| !p.done())
+    }
+
+    //run the game for dt with the buttons of input held, returns the frame drawn after it
+    pub fn step(&mut self, dt: Duration, input: &InputState) -> &Frame {
+        let events = match self.player.as_mut() {
+            Some(player) if !player.done() => player.next_frame(),
+            _ => input.changes(&self.input),
+        };
+        for evt in events {
+            let (key, held, game_event) = match evt {
+                Event::KeyDown(key) => (key, true, GameEvent::KeyDown(key)),
+                Event::KeyUp(key) => (key, false, GameEvent::KeyUp(key)),
+                //the debug controls of a replay
+                _ => {
+                    self.send(evt);
+                    continue;
+                }
+            };
+            self.input.set(key, held);
+            self.send(evt);
+            self.emit(&game_event);
+        }
+        if let Err(e) = self.engine.tick_tx.send(Tick::Run(dt.as_micros() as u64)) {
+            println!("send tick to engine:{}", e);
+            return &self.frame;
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.next_frame();
+        }
+        let frame = match self.engine.fb_rx.recv() {
+            Ok(frame) => frame,
+            Err(e) => {
+                println!("receive fb error:{}", e);
+                return &self.frame;
+            }
+        };
+        let previous = std::mem::replace(&mut self.frame, frame);
+        if previous.score != self.frame.score {
+            self.emit(&GameEvent::ScoreChanged(self.frame.score));
+        }
+        if let (None, Some(win)) = (previous.game_over, self.frame.game_over) {
+            self.emit(&GameEvent::GameOver { win });
+        }
+        &self.frame
+    }
+
+    //the state of the game between two steps, load_state goes back to it
+    pub fn save_state(&mut self) -> Vec<u8> {
+        self.engine.save_state().unwrap_or_default()
+    }
+
+    //the next step goes on from state, a save state of the loaded project
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        self.engine.restore_state(state)
+    }
+
+    //the frame of the last step
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    pub fn input(&self) -> &InputState {
+        &self.input
+    }
+
+    pub fn subscribe(&mut self, listener: impl FnMut(&GameEvent) + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    fn send(&mut self, evt: Event) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(&evt);
+        }
+        if let Err(e) = self.engine.event_tx.send(evt) {
+            println!("send event to engine:{}", e);
+        }
+    }
+
+    fn emit(&mut self, evt: &GameEvent) {
+        for listener in self.listeners.iter_mut() {
+            listener(evt);
+        }
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.send(Event::Exit);
+        //the engine only looks at events after a tick
+        let _ = self.engine.tick_tx.try_send(Tick::Run(0));
+    }
+}
+
+#[test]
+fn test_input_changes() {
+    let previous = InputState::pressed(&[KeyCode::A, KeyCode::Left]);
+    let input = InputState::pressed(&[KeyCode::A, KeyCode::Right]);
+    assert!(input.is_pressed(KeyCode::Right) && !input.is_pressed(KeyCode::Left));
+    let changes: Vec<String> = input.changes(&previous).iter().map(|e| e.to_string()).collect();
+    assert_eq!(changes, vec!["KeyUp(Left)", "KeyDown(Right)"]);
+    assert!(input.changes(&input).is_empty());
+}
+
+#[test]
+fn test_emulator_end_to_end() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let src = b"let presses = 0
+info.setScore(1)
+scene.setBackgroundColor(7)
+controller.anyButton.onEvent(ControllerButtonEvent.Pressed, function () {
+    presses += 1
+    info.changeScoreBy(10)
+})
+game.onUpdateInterval(100, function () {
+    info.changeScoreBy(1)
+})
+game.onUpdate(function () {
+    if (presses >= 2) game.over(true)
+})
+";
+    let events = Rc::new(RefCell::new(vec![]));
+    let mut emulator = Emulator::new();
+    let seen = events.clone();
+    emulator.subscribe(move |evt| seen.borrow_mut().push(evt.clone()));
+    emulator.load(src, Some("main.ts")).unwrap();
+
+    let none = InputState::default();
+    //the top level code runs in the first step, the game's time starts with the next
+    let frame = emulator.step(Duration::from_millis(20), &none);
+    assert_eq!((frame.score, frame.game_over), (1, None));
+    assert_eq!(frame.pixels[60 * 160 + 80], crate::emulator::resource::DEFAULT_COLORS[7]);
+    assert_eq!(emulator.step(Duration::from_millis(100), &none).score, 2);
+    assert_eq!(emulator.step(Duration::ZERO, &InputState::pressed(&[KeyCode::A])).score, 12);
+    assert_eq!(emulator.step(Duration::ZERO, &none).score, 12);
+    assert_eq!(emulator.step(Duration::ZERO, &InputState::pressed(&[KeyCode::B])).score, 22);
+    let frame = emulator.step(Duration::ZERO, &InputState::pressed(&[KeyCode::B]));
+    assert_eq!((frame.score, frame.game_over), (22, Some(true)));
+
+    assert_eq!(
+        events.borrow()[1..],
+        [
+            GameEvent::ScoreChanged(1),
+            GameEvent::ScoreChanged(2),
+            GameEvent::KeyDown(KeyCode::A),
+            GameEvent::ScoreChanged(12),
+            GameEvent::KeyUp(KeyCode::A),
+            GameEvent::KeyDown(KeyCode::B),
+            GameEvent::ScoreChanged(22),
+            GameEvent::GameOver { win: true },
+        ]
+    );
+    assert!(matches!(events.borrow()[0], GameEvent::Loaded(_)));
+}
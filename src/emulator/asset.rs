@@ -0,0 +1,417 @@
+//images, tiles, animations and tilemaps of the asset editor, stored in the .jres files of a project
+//https://github.com/microsoft/pxt/blob/master/pxtlib/spriteutils.ts https://github.com/microsoft/pxt/blob/master/pxtlib/tilemap.ts
+use std::{collections::HashMap, fmt};
+
+const MIME_IMAGE: &str = "image/x-mkcd-f4";
+const MIME_ANIMATION: &str = "application/mkcd-animation";
+const MIME_TILEMAP: &str = "application/mkcd-tilemap";
+
+//first byte of an image buffer, what image.ofBuffer takes
+const IMAGE_MAGIC: u8 = 0x87;
+
+//largest tilemap drawn, in pixels: 256x256 tiles of 16 pixels
+pub const MAX_TILEMAP_PIXELS: usize = 4096 * 4096;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TilemapError {
+    Truncated,
+    //tile width in pixels
+    BadTileSize(u8),
+    //width and height in tiles, tile width
+    TooLarge(usize, usize, usize),
+}
+
+impl fmt::Display for TilemapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TilemapError::Truncated => write!(f, "tilemap data is truncated"),
+            TilemapError::BadTileSize(size) => write!(f, "invalid tile width {}", size),
+            TilemapError::TooLarge(w, h, size) => write!(f, "tilemap of {}x{} tiles of {} pixels is too large", w, h, size),
+        }
+    }
+}
+
+impl std::error::Error for TilemapError {}
+
+//width and height in pixels of a map of width x height tiles
+pub fn tilemap_pixels(width: usize, height: usize, tile_size: usize) -> Result<(usize, usize), TilemapError> {
+    match (width.checked_mul(tile_size), height.checked_mul(tile_size)) {
+        (Some(w), Some(h)) if w.checked_mul(h).map_or(false, |n| n <= MAX_TILEMAP_PIXELS) => Ok((w, h)),
+        _ => Err(TilemapError::TooLarge(width, height, tile_size)),
+    }
+}
+
+//one resource of a .jres file, data is the decoded payload
+#[derive(Debug, Clone)]
+pub struct JresEntry {
+    //qualified as namespace.id, the name the .g.ts files declare it with
+    pub id: String,
+    pub display_name: Option<String>,
+    pub mime_type: String,
+    pub tilemap_tile: bool,
+    //ids of the tiles a tilemap's indices refer to
+    pub tileset: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+//"*" holds the defaults of the other entries
+pub fn parse_jres(text: &str) -> Result<Vec<JresEntry>, serde_json::Error> {
+    let obj: serde_json::Value = serde_json::from_str(text)?;
+    let empty = serde_json::Map::new();
+    let entries = obj.as_object().unwrap_or(&empty);
+    let defaults = entries.get("*");
+    let field = |v: &serde_json::Value, name: &str| -> Option<String> {
+        v.get(name)
+            .or_else(|| defaults.and_then(|d| d.get(name)))
+            .and_then(|s| s.as_str())
+            .map(String::from)
+    };
+
+    let mut ret = vec![];
+    for (key, v) in entries.iter().filter(|(k, _)| k.as_str() != "*") {
+        //old projects store just the data string
+        let (data, v) = match v {
+            serde_json::Value::String(s) => (s.clone(), &serde_json::Value::Null),
+            serde_json::Value::Object(_) => match v.get("data").and_then(|d| d.as_str()) {
+                Some(d) => (String::from(d), v),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let id = field(v, "id").unwrap_or_else(|| key.clone());
+        let id = match field(v, "namespace") {
+            Some(ns) if !ns.is_empty() && !id.contains('.') => format!("{}.{}", ns.trim_end_matches('.'), id),
+            _ => id,
+        };
+        let data = match field(v, "dataEncoding").as_deref() {
+            None | Some("base64") => match base64::decode(data.trim()) {
+                Ok(d) => d,
+                Err(e) => {
+                    println!("skip jres {}: {}", id, e);
+                    continue;
+                }
+            },
+            _ => data.into_bytes(),
+        };
+        ret.push(JresEntry {
+            id,
+            display_name: v.get("displayName").and_then(|s| s.as_str()).map(String::from),
+            mime_type: field(v, "mimeType").unwrap_or_else(|| String::from(MIME_IMAGE)),
+            tilemap_tile: v.get("tilemapTile").and_then(|t| t.as_bool()).unwrap_or(false),
+            tileset: v
+                .get("tileset")
+                .and_then(|t| t.as_array())
+                .map(|t| t.iter().filter_map(|s| s.as_str()).map(String::from).collect())
+                .unwrap_or_default(),
+            data,
+        });
+    }
+    Ok(ret)
+}
+
+//tilemaps (and animations of older editors) are hex text inside the base64
+fn unhex_payload(data: &[u8]) -> Vec<u8> {
+    if data.len() % 2 == 0 && !data.is_empty() && data.iter().all(|c| c.is_ascii_hexdigit()) {
+        if let Some(bytes) = std::str::from_utf8(data).ok().and_then(from_hex) {
+            return bytes;
+        }
+    }
+    data.to_vec()
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let s: Vec<u8> = s.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    s.chunks_exact(2)
+        .map(|c| u8::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok())
+        .collect()
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//the img`` text binding.js passes around, a hex digit per pixel, '.' for transparent
+fn image_literal(width: usize, height: usize, pixel: impl Fn(usize, usize) -> u8) -> String {
+    let mut lit = String::with_capacity((width + 1) * height);
+    for y in 0..height {
+        if y > 0 {
+            lit.push('\n');
+        }
+        for x in 0..width {
+            lit.push(match pixel(x, y) {
+                0 => '.',
+                c => char::from_digit(c as u32 & 0xf, 16).unwrap(),
+            });
+        }
+    }
+    lit
+}
+
+//image buffer: 0x87, bpp, width u16, height u16, 2 pad bytes, then columns of pixels each aligned to 4 bytes
+pub fn decode_image(buf: &[u8]) -> Option<String> {
+    if buf.len() < 8 || buf[0] != IMAGE_MAGIC {
+        return None;
+    }
+    let bpp = buf[1] as usize;
+    if bpp != 1 && bpp != 4 {
+        return None;
+    }
+    let width = u16::from_le_bytes([buf[2], buf[3]]) as usize;
+    let height = u16::from_le_bytes([buf[4], buf[5]]) as usize;
+    let byte_height = ((height * bpp + 31) >> 5) << 2;
+    let pixels = &buf[8..];
+    if pixels.len() < byte_height * width {
+        return None;
+    }
+    Some(image_literal(width, height, |x, y| {
+        let bit = y * bpp;
+        let byte = pixels[x * byte_height + (bit >> 3)];
+        (byte >> (bit & 7)) & ((1 << bpp) - 1) as u8
+    }))
+}
+
+//the editor's bitmap: row major, two pixels a byte, low nibble first
+fn decode_bitmap(data: &[u8], width: usize, height: usize) -> Option<String> {
+    if data.len() < (width * height + 1) / 2 {
+        return None;
+    }
+    Some(image_literal(width, height, |x, y| {
+        let i = y * width + x;
+        (data[i >> 1] >> ((i & 1) * 4)) & 0xf
+    }))
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub interval: u32,
+    pub frames: Vec<String>,
+}
+
+//interval, width, height, frame count as u16, then the bitmap of each frame
+fn decode_animation(data: &[u8]) -> Option<Animation> {
+    let data = unhex_payload(data);
+    if data.len() < 8 {
+        return None;
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]) as usize;
+    let (interval, width, height, count) = (u16_at(0), u16_at(2), u16_at(4), u16_at(6));
+    let frame_len = (width * height + 1) / 2;
+    let frames = (0..count)
+        .map(|i| decode_bitmap(data.get(8 + i * frame_len..)?, width, height))
+        .collect::<Option<Vec<String>>>()?;
+    Some(Animation { interval: interval as u32, frames })
+}
+
+#[derive(Debug, Clone)]
+pub struct Tilemap {
+    //tile size is 1 << scale pixels
+    pub scale: u8,
+    pub width: usize,
+    pub height: usize,
+    //index into tileset of each cell, row major
+    pub tiles: Vec<u8>,
+    pub walls: Vec<bool>,
+    pub tileset: Vec<String>,
+}
+
+impl Tilemap {
+    //what tiles.createTilemap takes: width u16, height u16, then the cells
+    pub fn data(&self) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend((self.width as u16).to_le_bytes());
+        data.extend((self.height as u16).to_le_bytes());
+        data.extend(&self.tiles);
+        data
+    }
+}
+
+//tile width in pixels, width u16, height u16, a byte per cell, then the walls as an editor bitmap
+fn decode_tilemap(data: &[u8]) -> Result<Tilemap, TilemapError> {
+    let data = unhex_payload(data);
+    if data.len() < 5 {
+        return Err(TilemapError::Truncated);
+    }
+    if !data[0].is_power_of_two() {
+        return Err(TilemapError::BadTileSize(data[0]));
+    }
+    let width = u16::from_le_bytes([data[1], data[2]]) as usize;
+    let height = u16::from_le_bytes([data[3], data[4]]) as usize;
+    tilemap_pixels(width, height, data[0] as usize)?;
+    let tiles = data.get(5..5 + width * height).ok_or(TilemapError::Truncated)?.to_vec();
+    let walls = data[5 + width * height..].to_vec();
+    Ok(Tilemap {
+        scale: data[0].trailing_zeros() as u8,
+        width,
+        height,
+        tiles,
+        walls: (0..width * height)
+            .map(|i| walls.get(i >> 1).map_or(false, |b| (b >> ((i & 1) * 4)) & 0xf != 0))
+            .collect(),
+        tileset: vec![],
+    })
+}
+
+#[derive(Debug, Clone)]
+enum Asset {
+    Image(String),
+    Animation(Animation),
+    Tilemap(Tilemap),
+}
+
+//every asset of the project, found by qualified id, bare id or display name
+#[derive(Debug, Clone, Default)]
+pub struct AssetTable {
+    assets: HashMap<String, Asset>,
+    names: HashMap<String, String>,
+    //raw payload by qualified id, what the jres hex`` literals of the .g.ts files are filled with
+    buffers: HashMap<String, Vec<u8>>,
+}
+
+impl AssetTable {
+    pub fn add_jres(&mut self, text: &str) -> Result<(), serde_json::Error> {
+        let entries = parse_jres(text)?;
+        for e in entries.iter() {
+            let asset = match e.mime_type.as_str() {
+                MIME_IMAGE => decode_image(&e.data).map(Asset::Image),
+                MIME_ANIMATION => decode_animation(&e.data).map(Asset::Animation),
+                MIME_TILEMAP => match decode_tilemap(&e.data) {
+                    Ok(mut t) => {
+                        t.tileset = e.tileset.clone();
+                        Some(Asset::Tilemap(t))
+                    }
+                    Err(err) => {
+                        println!("skip jres {}: {}", e.id, err);
+                        continue;
+                    }
+                },
+                _ => None,
+            };
+            let asset = match asset {
+                Some(a) => a,
+                None => {
+                    println!("skip jres {} of {}", e.id, e.mime_type);
+                    continue;
+                }
+            };
+            let mut names = vec![e.id.rsplit('.').next().unwrap_or(&e.id)];
+            names.extend(e.display_name.as_deref());
+            for name in names {
+                self.names.entry(String::from(name)).or_insert_with(|| e.id.clone());
+            }
+            self.buffers.insert(e.id.clone(), e.data.clone());
+            self.assets.insert(e.id.clone(), asset);
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Option<&Asset> {
+        let name = name.trim();
+        self.assets
+            .get(name)
+            .or_else(|| self.names.get(name).and_then(|id| self.assets.get(id)))
+    }
+
+    pub fn buffer(&self, id: &str) -> Option<&Vec<u8>> {
+        self.buffers.get(id)
+    }
+
+    pub fn image(&self, name: &str) -> Option<&String> {
+        match self.get(name) {
+            Some(Asset::Image(lit)) => Some(lit),
+            _ => None,
+        }
+    }
+
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        match self.get(name) {
+            Some(Asset::Animation(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn tilemap(&self, name: &str) -> Option<&Tilemap> {
+        match self.get(name) {
+            Some(Asset::Tilemap(t)) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+}
+
+#[cfg(test)]
+fn encode_image(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let byte_height = ((height * 4 + 31) >> 5) << 2;
+    let mut buf = vec![IMAGE_MAGIC, 4];
+    buf.extend((width as u16).to_le_bytes());
+    buf.extend((height as u16).to_le_bytes());
+    buf.extend([0, 0]);
+    for x in 0..width {
+        let mut col = vec![0u8; byte_height];
+        for y in 0..height {
+            col[y >> 1] |= pixels[y * width + x] << ((y & 1) * 4);
+        }
+        buf.extend(col);
+    }
+    buf
+}
+
+#[test]
+fn test_decode_image() {
+    let buf = encode_image(3, 2, &[0, 1, 2, 0xf, 0xa, 0]);
+    assert_eq!(buf.len(), 8 + 3 * 4);
+    assert_eq!(decode_image(&buf).unwrap(), ".12\nfa.");
+    assert_eq!(decode_image(&buf[..10]), None);
+    assert_eq!(decode_image(b"not an image"), None);
+}
+
+#[test]
+fn test_asset_table() {
+    let tile = encode_image(2, 2, &[7, 7, 7, 7]);
+    //animation of two 2x1 frames, 100ms each
+    let anim = [100u8, 0, 2, 0, 1, 0, 2, 0, 0x21, 0x43];
+    //2x1 map of 16px tiles as the pxt editor saves it, hex text: tile width, width, height,
+    //the cells, then the walls, the second cell is one
+    let map = "1002000100010020";
+    let jres = serde_json::json!({
+        "*": { "mimeType": MIME_IMAGE, "dataEncoding": "base64", "namespace": "myTiles" },
+        "tile1": { "data": base64::encode(&tile), "tilemapTile": true, "displayName": "grass" },
+        "anim1": { "data": base64::encode(anim), "mimeType": MIME_ANIMATION, "namespace": "myAnimations" },
+        "level1": {
+            "data": base64::encode(map),
+            "mimeType": MIME_TILEMAP,
+            "tileset": ["myTiles.transparency16", "myTiles.tile1"],
+            "displayName": "level 1"
+        },
+        "broken": "@@@",
+    });
+    let mut table = AssetTable::default();
+    table.add_jres(&jres.to_string()).unwrap();
+    assert_eq!(table.image("grass").unwrap(), "77\n77");
+    assert_eq!(table.image("myTiles.tile1"), table.image("tile1"));
+    assert_eq!(table.buffer("myTiles.tile1"), Some(&tile));
+    let a = table.animation("anim1").unwrap();
+    assert_eq!(a.interval, 100);
+    assert_eq!(a.frames, vec!["12", "34"]);
+    let t = table.tilemap(" level 1 ").unwrap();
+    assert_eq!((t.scale, t.width, t.height), (4, 2, 1));
+    assert_eq!(t.tiles, vec![1, 0]);
+    assert_eq!(t.walls, vec![false, true]);
+    assert_eq!(t.data(), vec![2, 0, 1, 0, 1, 0]);
+    assert_eq!(t.tileset[1], "myTiles.tile1");
+    //tile widths are 8, 16 or 32 pixels
+    assert_eq!(decode_tilemap(b"0802000100010020").map(|t| t.scale), Ok(3));
+    assert_eq!(decode_tilemap(b"0c02000100010020").unwrap_err(), TilemapError::BadTileSize(0x0c));
+    assert_eq!(decode_tilemap(b"100200010001").unwrap_err(), TilemapError::Truncated);
+    //65535x65535 tiles, refused before the cells are looked at
+    assert_eq!(decode_tilemap(b"10ffffffff").unwrap_err(), TilemapError::TooLarge(0xffff, 0xffff, 16));
+    assert_eq!(tilemap_pixels(256, 256, 16), Ok((4096, 4096)));
+    assert!(tilemap_pixels(usize::MAX, 1, 2).is_err());
+    assert!(table.image("level1").is_none());
+    assert!(table.image("broken").is_none());
+}
@@ -1,20 +1,28 @@
-use std::cell::RefCell;
-use std::{collections::hash_map::DefaultHasher, time};
-use std::hash::Hasher;
+use std::time;
 
 use crate::emulator::game::{BMP_HEIGHT, BMP_WIDTH};
 
-use super::resource::{Bitmap, COLORS, Canvas, Rect};
+use super::resource::{Bitmap, Canvas, Rect};
+use super::savestate::{Reader, StateError, Writer};
 
 pub trait EffectItem {
-    fn new(left:i32, top:i32, width:usize, height:usize)->Self;
-    fn renew(&self) -> Self;
-    fn update(&mut self, dt: &time::Duration);
+    //tells the effects apart in save states
+    const KIND: u8;
+    fn new(rng:&mut Rng, left:i32, top:i32, width:usize, height:usize)->Self;
+    fn renew(&self, rng:&mut Rng) -> Self;
+    fn update(&mut self, dt: &time::Duration, rng:&mut Rng);
     fn get_bmp(&self) -> Option<(i32, i32, &Bitmap)>;
+    fn save(&self, w: &mut Writer);
+    fn load(r: &mut Reader) -> Result<Self, StateError> where Self: Sized;
 }
 
 
-static COLOR_SNOW:u32=COLORS[1];
+//white
+static COLOR_SNOW:u8=1;
+
+const KIND_SNOW: u8 = 0;
+const KIND_CONFETTI: u8 = 1;
+const KIND_MELT: u8 = 2;
 
 struct Snow {
     current: Bitmap,
@@ -26,36 +34,61 @@ struct Snow {
     speed_pixels_per_ms: f32,
 }
 
-thread_local! {
-    static HASHER:RefCell<DefaultHasher> = RefCell::new(DefaultHasher::default());
-}
+//the generator behind scene effects and js Math.random, one per runtime.
+//seeded when a project starts, so the same seed gives the same game
+pub struct Rng(u64);
 
-pub fn rand() -> f32{
-    let seed = time::SystemTime::now().duration_since(time::SystemTime::UNIX_EPOCH).unwrap().as_nanos();
-    HASHER.with(|hh|{
-        let mut h = hh.borrow_mut();
-        h.write(&seed.to_le_bytes()[..]);
-        h.finish() as f32 / u64::MAX as f32
-    })
+impl Rng {
+    pub fn new(seed:u64) -> Self {
+        Rng(seed)
+    }
+
+    //where the generator is, Rng::new with it to go on from there
+    pub fn state(&self) -> u64 {
+        self.0
+    }
+
+    //splitmix64
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    //0 <= random() < 1
+    pub fn random(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn rand(&mut self) -> f32{
+        (self.next_u64() >> 40) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn randint(&mut self, min:i32, max:i32) -> i32 {
+        ((self.rand() * ((max-min) as f32)) as i32) + min
+    }
 }
 
-pub fn randint(min:i32, max:i32) -> i32 {
-    ((rand() * ((max-min) as f32)) as i32) + min
+pub fn time_seed() -> u64 {
+    time::SystemTime::now().duration_since(time::SystemTime::UNIX_EPOCH).unwrap().as_nanos() as u64
 }
 
 impl EffectItem for Snow{
-    fn renew(&self) -> Self{
+    const KIND: u8 = KIND_SNOW;
+    fn renew(&self, rng:&mut Rng) -> Self{
         let(a,b,c,d)=self.params;
-        Self::new(a,b,c,d)
+        Self::new(rng,a,b,c,d)
     }
-    fn new(left:i32, top:i32, width:usize, height:usize) -> Self{
-        let x = (rand() * width as f32) as i32 + left;
-        let y = (rand() * height as f32) as i32 + top;
+    fn new(rng:&mut Rng, left:i32, top:i32, width:usize, height:usize) -> Self{
+        let x = (rng.rand() * width as f32) as i32 + left;
+        let y = (rng.rand() * height as f32) as i32 + top;
         //0.5 ~ 1 sec
-        let life = time::Duration::from_secs_f32((500.0 + rand() * 500.0)/1000.0);
-        let mut speed_pixels_per_ms = width as f32 / life.as_millis() as f32;
-        speed_pixels_per_ms = (rand() + 1.0) * speed_pixels_per_ms;
-        let mut size = (rand() * 5 as f32) as i32;
+        let life = time::Duration::from_secs_f32((500.0 + rng.rand() * 500.0)/1000.0);
+        let mut speed_pixels_per_ms = width as f32 / (life.as_secs_f32() * 1000.0);
+        speed_pixels_per_ms = (rng.rand() + 1.0) * speed_pixels_per_ms;
+        let mut size = (rng.rand() * 5 as f32) as i32;
         if size == 4 {
             size = 1;
         }
@@ -65,27 +98,83 @@ impl EffectItem for Snow{
         Snow { speed_pixels_per_ms, age:time::Duration::from_millis(0), current, x:left as f32, y: top as f32, life, params:(left,top,width,height)}
     }
 
-    fn update(&mut self, dt: &time::Duration){                
+    fn update(&mut self, dt: &time::Duration, rng:&mut Rng){                
         self.age += *dt;
-        let dice = rand();
+        let ms = dt.as_secs_f32() * 1000.0;
+        let dice = rng.rand();
         if dice <= 0.6 {
             //0.6 possibility move in self.speed_pixels_per_ms
-            self.x += dt.as_millis() as f32 * self.speed_pixels_per_ms;
-            self.y += dt.as_millis() as f32 * self.speed_pixels_per_ms;
+            self.x += ms * self.speed_pixels_per_ms;
+            self.y += ms * self.speed_pixels_per_ms;
         }else if dice <= 0.9 {
             //0.3 possibility move double speed
-            self.x += dt.as_millis() as f32 * self.speed_pixels_per_ms * 2.0;
-            self.y += dt.as_millis() as f32 * self.speed_pixels_per_ms * 2.0;
+            self.x += ms * self.speed_pixels_per_ms * 2.0;
+            self.y += ms * self.speed_pixels_per_ms * 2.0;
         }else{
             //0.1 possibility move triple speed
-            self.x += dt.as_millis() as f32 * self.speed_pixels_per_ms * 3.0;
-            self.y += dt.as_millis() as f32 * self.speed_pixels_per_ms * 3.0;
+            self.x += ms * self.speed_pixels_per_ms * 3.0;
+            self.y += ms * self.speed_pixels_per_ms * 3.0;
         }
     }
 
     fn get_bmp(& self) -> Option<(i32, i32, &Bitmap)> {
         if self.age >= self.life { None } else { Some((self.x as i32, self.y as i32, &self.current)) }
     }
+
+    fn save(&self, w: &mut Writer) {
+        self.current.save(w);
+        let (left, top, width, height) = self.params;
+        w.i32(left);
+        w.i32(top);
+        w.usize(width);
+        w.usize(height);
+        w.f32(self.x);
+        w.f32(self.y);
+        w.duration(&self.life);
+        w.duration(&self.age);
+        w.f32(self.speed_pixels_per_ms);
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, StateError> {
+        Ok(Snow {
+            current: Bitmap::load(r)?,
+            params: (r.i32()?, r.i32()?, r.usize()?, r.usize()?),
+            x: r.f32()?,
+            y: r.f32()?,
+            life: r.duration()?,
+            age: r.duration()?,
+            speed_pixels_per_ms: r.f32()?,
+        })
+    }
+}
+
+//snow in every color but white and black
+struct Confetti(Snow);
+
+impl EffectItem for Confetti {
+    const KIND: u8 = KIND_CONFETTI;
+    fn renew(&self, rng:&mut Rng) -> Self {
+        let (a, b, c, d) = self.0.params;
+        Self::new(rng, a, b, c, d)
+    }
+    fn new(rng:&mut Rng, left:i32, top:i32, width:usize, height:usize) -> Self {
+        let mut snow = Snow::new(rng, left, top, width, height);
+        let (w, h) = (snow.current.width(), snow.current.height());
+        snow.current = Bitmap::new_with_bmp(vec![rng.randint(2, 15) as u8; w * h], w);
+        Confetti(snow)
+    }
+    fn update(&mut self, dt: &time::Duration, rng:&mut Rng) {
+        self.0.update(dt, rng);
+    }
+    fn get_bmp(&self) -> Option<(i32, i32, &Bitmap)> {
+        self.0.get_bmp()
+    }
+    fn save(&self, w: &mut Writer) {
+        self.0.save(w);
+    }
+    fn load(r: &mut Reader) -> Result<Self, StateError> {
+        Ok(Confetti(Snow::load(r)?))
+    }
 }
 
 struct EffectContainer<T:Sized + EffectItem> {
@@ -93,17 +182,18 @@ struct EffectContainer<T:Sized + EffectItem> {
 }
 
 pub trait Effect {
-    fn update(&mut self, dt:&time::Duration);
+    fn update(&mut self, dt:&time::Duration, rng:&mut Rng);
     fn draw(&self, canvas: &mut Canvas);
+    fn save(&self, w: &mut Writer);
 }
 
 impl<T:Sized + EffectItem> Effect for EffectContainer<T> {
-    fn update(&mut self, dt:&time::Duration) {        
+    fn update(&mut self, dt:&time::Duration, rng:&mut Rng) {        
         for i in 0..self.items.len(){
             let item = self.items.get_mut(i).unwrap();
-            item.update(dt);
+            item.update(dt, rng);
             if item.get_bmp().is_none() {
-                self.items[i] = item.renew();
+                self.items[i] = item.renew(rng);
             }
         }
     }
@@ -118,23 +208,141 @@ impl<T:Sized + EffectItem> Effect for EffectContainer<T> {
             }
         }
     }
-}
 
-pub struct SceneEffect();
+    fn save(&self, w: &mut Writer) {
+        w.u8(T::KIND);
+        w.usize(self.items.len());
+        self.items.iter().for_each(|item| item.save(w));
+    }
+}
 
-impl SceneEffect {
-    pub fn blizzard() -> Box<dyn Effect>{
-        let mut items : Vec<Snow> = vec![];
+impl<T:Sized + EffectItem> EffectContainer<T> {
+    //items scattered over the screen in a 5x4 grid, starting a cell off screen
+    fn cover_screen(rng:&mut Rng) -> Box<dyn Effect> where T: 'static {
+        let mut items : Vec<T> = vec![];
         let width = BMP_WIDTH as i32 / 5;
         let height = BMP_HEIGHT as i32 / 4;
         for x in (-(width as i32)..BMP_WIDTH as i32).step_by(width as usize) {
             for y in (-(height as i32)..BMP_HEIGHT as i32).step_by(height as usize) {
-                items.push(Snow::new(x, y, width as usize, height as usize));
+                items.push(T::new(rng, x, y, width as usize, height as usize));
             }
         }
-        Box::new(EffectContainer::<Snow>{items})
+        Box::new(EffectContainer::<T>{items})
+    }
+
+    fn load(r: &mut Reader) -> Result<Box<dyn Effect>, StateError> where T: 'static {
+        let n = r.count(16)?;
+        let items = (0..n).map(|_| T::load(r)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Box::new(EffectContainer::<T>{items}))
+    }
+}
+
+//the screen runs down, every column at its own speed. drawn over what is on the canvas
+struct Melt {
+    //pixels per second
+    speeds: Vec<f32>,
+    age: time::Duration,
+}
+
+impl Effect for Melt {
+    fn update(&mut self, dt:&time::Duration, _rng:&mut Rng) {
+        self.age += *dt;
+    }
+
+    fn draw(&self, canvas: &mut Canvas) {
+        let (w, h) = (BMP_WIDTH as usize, BMP_HEIGHT as usize);
+        for (x, speed) in self.speeds.iter().enumerate() {
+            let fall = ((speed * self.age.as_secs_f32()) as usize).min(h);
+            if fall == 0 {
+                continue;
+            }
+            for y in (0..h).rev() {
+                canvas.0[y * w + x] = if y >= fall { canvas.0[(y - fall) * w + x] } else { 0 };
+            }
+        }
+    }
+
+    fn save(&self, w: &mut Writer) {
+        w.u8(KIND_MELT);
+        w.usize(self.speeds.len());
+        self.speeds.iter().for_each(|v| w.f32(*v));
+        w.duration(&self.age);
+    }
+}
+
+pub struct SceneEffect();
+
+impl SceneEffect {
+    pub fn blizzard(rng:&mut Rng) -> Box<dyn Effect>{
+        EffectContainer::<Snow>::cover_screen(rng)
+    }
+    pub fn confetti(rng:&mut Rng) -> Box<dyn Effect>{
+        EffectContainer::<Confetti>::cover_screen(rng)
+    }
+    pub fn melt(rng:&mut Rng) -> Box<dyn Effect>{
+        Box::new(Melt {
+            speeds: (0..BMP_WIDTH).map(|_| 10.0 + rng.rand() * 30.0).collect(),
+            age: time::Duration::ZERO,
+        })
     }
     pub fn dummy() -> Box<dyn Effect> {
         Box::new(EffectContainer::<Snow>{items:vec![]})
     }
+    //the effects the scripts name, as in effects.blizzard
+    pub fn by_name(name: &str, rng:&mut Rng) -> Option<Box<dyn Effect>> {
+        match name {
+            "blizzard" => Some(Self::blizzard(rng)),
+            "confetti" => Some(Self::confetti(rng)),
+            "melt" => Some(Self::melt(rng)),
+            _ => None,
+        }
+    }
+    //dummy is an empty snow effect
+    pub fn load(r: &mut Reader) -> Result<Box<dyn Effect>, StateError> {
+        match r.u8()? {
+            KIND_SNOW => EffectContainer::<Snow>::load(r),
+            KIND_CONFETTI => EffectContainer::<Confetti>::load(r),
+            KIND_MELT => {
+                let n = r.count(4)?;
+                let speeds = (0..n).map(|_| r.f32()).collect::<Result<Vec<_>, _>>()?;
+                Ok(Box::new(Melt { speeds, age: r.duration()? }))
+            }
+            _ => Err(StateError::Invalid("effect")),
+        }
+    }
+}
+
+#[test]
+fn test_seeded_random() {
+    let mut rng = Rng::new(42);
+    let first: Vec<f64> = (0..8).map(|_| rng.random()).collect();
+    let mut rng = Rng::new(42);
+    let again: Vec<f64> = (0..8).map(|_| rng.random()).collect();
+    assert_eq!(first, again);
+    assert_eq!(Rng::new(rng.state()).random(), rng.random());
+    assert!(first.iter().all(|v| (0.0..1.0).contains(v)));
+    let mut rng = Rng::new(43);
+    assert_ne!(rng.random(), first[0]);
+    assert!((0..1000).all(|_| (0.0..1.0).contains(&rng.rand())));
+}
+
+#[test]
+fn test_melt() {
+    let mut canvas = Canvas::new();
+    canvas.0[3] = 7;
+    let mut rng = Rng::new(1);
+    let mut melt = SceneEffect::melt(&mut rng);
+    melt.update(&time::Duration::from_secs(1), &mut rng);
+    melt.draw(&mut canvas);
+    //every column falls 10 to 40 pixels a second
+    let y = (0..BMP_HEIGHT as usize).find(|y| canvas.get_pixel(3, *y) == 7).unwrap();
+    assert!((10..=40).contains(&y));
+    assert_eq!(canvas.get_pixel(3, 0), 0);
+
+    let mut w = Writer::default();
+    melt.save(&mut w);
+    let mut again = Writer::default();
+    SceneEffect::load(&mut Reader(&w.0)).unwrap().save(&mut again);
+    assert_eq!(w.0, again.0);
+    assert!(SceneEffect::by_name("confetti", &mut rng).is_some() && SceneEffect::by_name("slash", &mut rng).is_none());
 }
@@ -1,4 +1,20 @@
-use std::fmt;
+use std::{fmt, time};
+
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_4X6, FONT_5X8, FONT_6X10},
+        MonoFont, MonoTextStyle,
+    },
+    pixelcolor::{Gray8, IntoStorage},
+    prelude::{OriginDimensions, Point, Primitive, Size},
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable, Pixel,
+};
+
+use super::effect::{Effect, Rng, SceneEffect};
+use super::resource::{Bitmap, Canvas};
+use super::savestate::{Reader, StateError, Writer};
 
 pub const BMP_WIDTH: u32 = 160;
 pub const BMP_HEIGHT: u32 = 120;
@@ -33,12 +49,63 @@ pub enum KeyEvt {
     Repeat(KeyCode),
     Exit,
 }
+//how long the game over screen shows before A restarts the game, so a held button doesn't skip it
+const RESTART_DELAY: time::Duration = time::Duration::from_secs(1);
+//top of the banner, palette indices of its box and text
+const BANNER_Y: i32 = 30;
+const BANNER_HEIGHT: u32 = 60;
+const BANNER_COLOR: u8 = 15;
+const TEXT_COLOR: u8 = 1;
+
 pub struct Game {
     //pub controller: controller::Controller<'a>,
     //pub scene: scene::Scene<'a>,
     pub over: bool,
     pub win: bool,
     //pixels: [u8; 4 * BMP_HEIGHT as usize * BMP_WIDTH as usize],
+    //score when the game ended, and the best one since the game was loaded, kept over restarts
+    pub score: i32,
+    pub high_score: i32,
+    new_high_score: bool,
+    message: String,
+    effect: Option<Box<dyn Effect>>,
+    //time since the game ended
+    since: time::Duration,
+    //A was pressed on the game over screen
+    pub restart: bool,
+    banner: Bitmap,
+    banner_with_hint: Bitmap,
+}
+
+//a bitmap embedded_graphics draws palette indices into, as Info draws the score
+struct Painter {
+    w: usize,
+    pixels: Vec<u8>,
+}
+
+impl embedded_graphics::draw_target::DrawTarget for Painter {
+    type Color = Gray8;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Gray8>>,
+    {
+        for Pixel(p, c) in pixels {
+            if p.x >= 0 && p.y >= 0 && (p.x as usize) < self.w {
+                if let Some(dest) = self.pixels.get_mut(p.y as usize * self.w + p.x as usize) {
+                    *dest = c.into_storage();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OriginDimensions for Painter {
+    fn size(&self) -> Size {
+        Size::new(self.w as u32, (self.pixels.len() / self.w) as u32)
+    }
 }
 
 impl Game {
@@ -46,14 +113,188 @@ impl Game {
         Game {
             over: false,
             win: true,
+            score: 0,
+            high_score: 0,
+            new_high_score: false,
+            message: String::new(),
+            effect: None,
+            since: time::Duration::ZERO,
+            restart: false,
+            banner: Bitmap::new(0, 0),
+            banner_with_hint: Bitmap::new(0, 0),
+        }
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        w.bool(self.over);
+        w.bool(self.win);
+        w.i32(self.score);
+        w.i32(self.high_score);
+        w.bool(self.new_high_score);
+        w.str(&self.message);
+        w.duration(&self.since);
+        w.bool(self.effect.is_some());
+        if let Some(effect) = self.effect.as_ref() {
+            effect.save(w);
         }
     }
 
-    pub fn over(&mut self, _win: bool) {
-        //println!("game over, win ? {win}");
+    pub fn load(r: &mut Reader) -> Result<Game, StateError> {
+        let mut game = Game {
+            over: r.bool()?,
+            win: r.bool()?,
+            score: r.i32()?,
+            high_score: r.i32()?,
+            new_high_score: r.bool()?,
+            message: r.string()?,
+            since: r.duration()?,
+            ..Game::new()
+        };
+        if r.bool()? {
+            game.effect = Some(SceneEffect::load(r)?);
+        }
+        if game.over {
+            game.paint_banners();
+        }
+        Ok(game)
+    }
+
+    //the game ends with score: the banner, and effect over the frozen scene, until A restarts it.
+    //an empty message or no effect is the default one of a win or a loss
+    pub fn over(&mut self, win: bool, score: i32, message: String, effect: Option<Box<dyn Effect>>, rng: &mut Rng) {
+        if self.over {
+            return;
+        }
         self.over = true;
+        self.win = win;
+        self.score = score;
+        self.new_high_score = score > self.high_score;
+        self.high_score = self.high_score.max(score);
+        self.message = if !message.is_empty() {
+            message
+        } else if win {
+            String::from("YOU WIN!")
+        } else {
+            String::from("GAME OVER!")
+        };
+        self.effect = effect.or_else(|| Some(if win { SceneEffect::confetti(rng) } else { SceneEffect::melt(rng) }));
+        self.since = time::Duration::ZERO;
+        self.paint_banners();
+    }
+
+    pub fn update(&mut self, dt: &time::Duration, rng: &mut Rng) {
+        if !self.over {
+            return;
+        }
+        self.since += *dt;
+        if let Some(effect) = self.effect.as_mut() {
+            effect.update(dt, rng);
+        }
+    }
+
+    //keys only matter to the game over screen
+    pub fn key_down(&mut self, key: KeyCode) {
+        if self.over && key == KeyCode::A && self.since >= RESTART_DELAY {
+            self.restart = true;
+        }
+    }
+
+    //the game over screen over the scene
+    pub fn draw(&self, canvas: &mut Canvas) {
+        if !self.over {
+            return;
+        }
+        if let Some(effect) = self.effect.as_ref() {
+            effect.draw(canvas);
+        }
+        let banner = if self.since >= RESTART_DELAY { &self.banner_with_hint } else { &self.banner };
+        canvas.draw(0, BANNER_Y, banner);
+    }
+
+    fn paint_banners(&mut self) {
+        let best = if self.new_high_score {
+            String::from("New High Score!")
+        } else {
+            format!("High Score: {}", self.high_score)
+        };
+        let lines = [
+            (self.message.as_str(), &FONT_6X10, 6),
+            (&format!("Score: {}", self.score), &FONT_5X8, 22),
+            (&best, &FONT_5X8, 32),
+        ];
+        self.banner = Self::paint(&lines);
+        self.banner_with_hint = Self::paint(&[&lines[..], &[("Press A to restart", &FONT_4X6, 48)]].concat());
+    }
+
+    //centered lines of text (text, font, top) in a box across the screen
+    fn paint(lines: &[(&str, &MonoFont, i32)]) -> Bitmap {
+        let mut painter = Painter {
+            w: BMP_WIDTH as usize,
+            pixels: vec![0; (BMP_WIDTH * BANNER_HEIGHT) as usize],
+        };
+        Rectangle::new(Point::zero(), painter.size())
+            .into_styled(
+                PrimitiveStyleBuilder::new()
+                    .fill_color(Gray8::new(BANNER_COLOR))
+                    .stroke_color(Gray8::new(TEXT_COLOR))
+                    .stroke_width(1)
+                    .build(),
+            )
+            .draw(&mut painter)
+            .unwrap();
+        let position = TextStyleBuilder::new().alignment(Alignment::Center).baseline(Baseline::Top).build();
+        for (text, font, top) in lines {
+            Text::with_text_style(text, Point::new(BMP_WIDTH as i32 / 2, *top), MonoTextStyle::new(font, Gray8::new(TEXT_COLOR)), position)
+                .draw(&mut painter)
+                .unwrap();
+        }
+        Bitmap::new_with_bmp(painter.pixels, painter.w)
     }
 }
 
 //API: https://github.com/microsoft/pxt-common-packages/tree/master/libs/game
 //https://github.com/microsoft/pxt-common-packages/tree/master/libs/game/docs
+
+#[test]
+fn test_game_over() {
+    let mut rng = Rng::new(1);
+    let mut game = Game::new();
+    game.high_score = 7;
+    game.over(false, 5, String::new(), None, &mut rng);
+    assert_eq!((game.over, game.win, game.message.as_str(), game.high_score), (true, false, "GAME OVER!", 7));
+    game.over(true, 9, String::from("again"), None, &mut rng);
+    assert!(!game.win);
+
+    let mut canvas = Canvas::new();
+    game.draw(&mut canvas);
+    assert_eq!(canvas.get_pixel(0, BANNER_Y as usize + 1), TEXT_COLOR);
+    assert_eq!(canvas.get_pixel(2, BANNER_Y as usize + 2), BANNER_COLOR);
+
+    game.key_down(KeyCode::A);
+    assert!(!game.restart);
+    game.update(&RESTART_DELAY, &mut rng);
+    game.key_down(KeyCode::B);
+    assert!(!game.restart);
+    game.key_down(KeyCode::A);
+    assert!(game.restart);
+
+    let mut w = Writer::default();
+    game.save(&mut w);
+    let loaded = Game::load(&mut Reader(&w.0)).unwrap();
+    let mut again = Writer::default();
+    loaded.save(&mut again);
+    assert_eq!(w.0, again.0);
+    let mut restored = Canvas::new();
+    loaded.draw(&mut restored);
+    assert_eq!(restored.0, {
+        let mut c = Canvas::new();
+        game.draw(&mut c);
+        c.0
+    });
+    assert!(!loaded.restart);
+
+    let mut next = Game::new();
+    next.high_score = game.high_score;
+    next.over(true, 12, String::new(), None, &mut rng);
+    assert!(next.new_high_score && next.high_score == 12);
+}
@@ -2,7 +2,11 @@ use embedded_graphics::Drawable;
 use embedded_graphics::primitives::Primitive;
 use embedded_graphics::pixelcolor::IntoStorage;
 
-use super::{game::{BMP_HEIGHT, BMP_WIDTH}, resource::{Bitmap, COLORS, Canvas, Rect}};
+use super::{game::{BMP_HEIGHT, BMP_WIDTH}, resource::{Bitmap, Canvas, Rect}, savestate::{Reader, StateError, Writer}};
+
+//palette indices, drawn as gray levels so embedded_graphics keeps them as is
+const SCORE_COLOR: u8 = 3;
+const TRANSPARENT: u8 = 0;
 
 enum What{
     Score,
@@ -15,18 +19,18 @@ const LIFE_Y : i32= 20;
 const CD_Y : i32= 40;
 
 pub struct Info<'a> {
-    pixels:[u32; BMP_WIDTH as usize * BMP_HEIGHT as usize],
+    pixels:[u8; BMP_WIDTH as usize * BMP_HEIGHT as usize],
     show_score:bool,
     score:f32,
     score_bmp:Bitmap,
-    score_text_style:embedded_graphics::mono_font::MonoTextStyle<'a,embedded_graphics::pixelcolor::Rgb888>,
-    score_rect_style:embedded_graphics::primitives::PrimitiveStyle<embedded_graphics::pixelcolor::Rgb888>,
+    score_text_style:embedded_graphics::mono_font::MonoTextStyle<'a,embedded_graphics::pixelcolor::Gray8>,
+    score_rect_style:embedded_graphics::primitives::PrimitiveStyle<embedded_graphics::pixelcolor::Gray8>,
     drawing:What,
 }
 
 impl<'a> embedded_graphics::draw_target::DrawTarget for Info<'a>  {
     //embedded_graphics not allow to implement customer pixelColor
-    type Color=embedded_graphics::pixelcolor::Rgb888;
+    type Color=embedded_graphics::pixelcolor::Gray8;
 
     type Error=&'static str;
 
@@ -50,15 +54,15 @@ impl<'a> Info<'a> {
     pub fn new() -> Info<'a, > {
         let score_text_style = embedded_graphics::mono_font::MonoTextStyleBuilder::new()
                     .font(&embedded_graphics::mono_font::ascii::FONT_4X6)
-                    .text_color(Self::from_u32(COLORS[3]))
-                    .background_color(Self::from_u32(COLORS[0]))
+                    .text_color(Self::from_index(SCORE_COLOR))
+                    .background_color(Self::from_index(TRANSPARENT))
                     .build();
         let score_rect_style = embedded_graphics::primitives::PrimitiveStyleBuilder::new()
             .stroke_color(score_text_style.text_color.unwrap())
             .stroke_width(1)
             //.fill_color(score_text_style.background_color.unwrap())
             .build();        
-        Info{show_score:false,score:0.0,pixels:[0u32;BMP_WIDTH as usize * BMP_HEIGHT as usize],score_bmp:Bitmap::new(0,0), score_text_style, score_rect_style, drawing:What::Score}
+        Info{show_score:false,score:0.0,pixels:[0u8;BMP_WIDTH as usize * BMP_HEIGHT as usize],score_bmp:Bitmap::new(0,0), score_text_style, score_rect_style, drawing:What::Score}
     }
 
     pub fn draw(&self, canvas: &mut Canvas){
@@ -67,6 +71,30 @@ impl<'a> Info<'a> {
         }
     }
 
+    pub fn score(&self) -> i32 {
+        self.score as i32
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        w.bool(self.show_score);
+        w.f32(self.score);
+        w.bytes(&self.pixels);
+        self.score_bmp.save(w);
+    }
+
+    pub fn load(r: &mut Reader) -> Result<Info<'a>, StateError> {
+        let mut info = Info::new();
+        info.show_score = r.bool()?;
+        info.score = r.f32()?;
+        let pixels = r.bytes()?;
+        if pixels.len() != info.pixels.len() {
+            return Err(StateError::Invalid("info pixels"));
+        }
+        info.pixels.copy_from_slice(pixels);
+        info.score_bmp = Bitmap::load(r)?;
+        Ok(info)
+    }
+
     pub fn change_score(&mut self, del:f32) {
         self.show_score = true;
         self.set_score(self.score + del);
@@ -74,7 +102,7 @@ impl<'a> Info<'a> {
     pub fn set_score(&mut self, n:f32) {
         self.show_score = true;
         self.drawing = What::Score;
-        self.score = ((self.score + n) as i32) as f32;
+        self.score = (n as i32) as f32;
         let next_point = embedded_graphics::text::Text::with_baseline(
             format!("{}", self.score).as_str(), 
             embedded_graphics::prelude::Point::new(2, SCORE_Y + 2), 
@@ -88,19 +116,19 @@ impl<'a> Info<'a> {
         //TODO:line by line should have better performance
         self.score_bmp.set_data(rect.size.width as usize, clip);
     }
-    pub fn from_u32(c:u32) -> embedded_graphics::pixelcolor::Rgb888{
-        embedded_graphics::pixelcolor::Rgb888::from(embedded_graphics::pixelcolor::raw::RawU24::new(c))
+    pub fn from_index(c:u8) -> embedded_graphics::pixelcolor::Gray8{
+        embedded_graphics::pixelcolor::Gray8::new(c)
     }
 }
 
 struct Clip<'a>{
-    pixels:&'a[u32; BMP_WIDTH as usize * BMP_HEIGHT as usize],
+    pixels:&'a[u8; BMP_WIDTH as usize * BMP_HEIGHT as usize],
     rect:Rect,
     idx:i32,
 }
 
 impl<'a> Iterator for Clip<'a>{
-    type Item=u32;
+    type Item=u8;
 
     fn next(&mut self) -> Option<Self::Item> {        
         let y_offset = self.idx / self.rect.w as i32;
@@ -111,4 +139,17 @@ impl<'a> Iterator for Clip<'a>{
         self.idx += 1;        
         Some(self.pixels[((y_offset + self.rect.y) * BMP_WIDTH as i32 + x_offset + self.rect.x) as usize])
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_set_score() {
+    let mut info = Info::new();
+    info.set_score(5.0);
+    info.change_score(3.0);
+    //changeScoreBy used to add the new score to the old one, giving 13
+    assert_eq!(info.score(), 8);
+    info.set_score(2.7);
+    assert_eq!(info.score(), 2);
+    info.change_score(-4.0);
+    assert_eq!(info.score(), -2);
+}
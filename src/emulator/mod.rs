@@ -5,6 +5,8 @@ pub mod sprite;
 pub mod scene;
 pub mod info;
 pub mod effect;
+pub mod asset;
+pub mod savestate;
 
 #[cfg(feature="firestorm-cpu")]
 pub(crate) use firestorm::{
@@ -1,49 +1,94 @@
 use std::{cmp::{max, min}, collections::{HashMap}, time};
 
 use super::game::{self, BMP_HEIGHT, BMP_WIDTH};
+use super::savestate::{Reader, StateError, Writer};
 
-pub static COLORS: [u32; 0x10] = [
+//makecode arcade default palette, index 0 is transparent
+pub static DEFAULT_COLORS: [u32; 0x10] = [
     0,//0
-    0xffffffu32,//.to_le_bytes(),//1
-    0xff2121u32,//.to_le_bytes(),//2
-    0xff93c4u32,//.to_le_bytes(),//3
-    0xff8135u32,//.to_le_bytes(),//4
-    0xfff609u32,//.to_le_bytes(),//5
-    0x249ca3u32,//.to_le_bytes(),//6
-    0x78dc52u32,//.to_le_bytes(),//7
-    0x003fadu32,//.to_le_bytes(),//8
-    0x87f2ffu32,//.to_le_bytes(),//9
-    0x8e2ec4u32,//.to_le_bytes(),//a
-    0xa4839fu32,//.to_le_bytes(),//b
-    0x5c406cu32,//.to_le_bytes(),//c
-    0xe5cdc4u32,//.to_le_bytes(),//d
-    0x91463du32,//.to_le_bytes(),//e
-    0x000000FFu32,//.to_le_bytes(),//f
+    0xffffffu32,//1
+    0xff2121u32,//2
+    0xff93c4u32,//3
+    0xff8135u32,//4
+    0xfff609u32,//5
+    0x249ca3u32,//6
+    0x78dc52u32,//7
+    0x003fadu32,//8
+    0x87f2ffu32,//9
+    0x8e2ec4u32,//a
+    0xa4839fu32,//b
+    0x5c406cu32,//c
+    0xe5cdc4u32,//d
+    0x91463du32,//e
+    0x000000u32,//f
 ];
 
-pub fn map_color(idx : &u8) -> u32{
-    match idx {
-        b'a'..=b'f' => COLORS[(idx - b'a' + 10) as usize], //&[255,0,0,255],
-        b'0'..=b'9' => COLORS[(idx - b'0') as usize],      //&[255,255,0,255],//
-        _ => COLORS[0],                                     //&[255,0,255,255],//
+//the 16 xrgb colors bitmaps index into, a project may bring its own
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette(pub [u32; 0x10]);
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette(DEFAULT_COLORS)
+    }
+}
+
+impl Palette {
+    //"#rrggbb" strings, as pxt.json "palette" lists them
+    pub fn from_hex_colors<'a>(colors: impl Iterator<Item = &'a str>) -> Option<Palette> {
+        let mut palette = [0u32; 0x10];
+        let mut n = 0;
+        for c in colors {
+            if n == palette.len() {
+                return None;
+            }
+            palette[n] = u32::from_str_radix(c.trim().trim_start_matches('#'), 16).ok()? & 0xffffff;
+            n += 1;
+        }
+        if n != palette.len() {
+            return None;
+        }
+        Some(Palette(palette))
+    }
+
+    pub fn color(&self, idx: u8) -> u32 {
+        self.0[(idx & 0xf) as usize]
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        self.0.iter().for_each(|c| w.u32(*c));
+    }
+
+    pub fn load(r: &mut Reader) -> Result<Palette, StateError> {
+        let mut palette = [0u32; 0x10];
+        for c in palette.iter_mut() {
+            *c = r.u32()?;
+        }
+        Ok(Palette(palette))
     }
-    // println!("{},{},{}", dest[40],dest[41],dest[42]);
-    // println!("{},{},{}", dest[43],dest[44],dest[45])
 }
 
+//palette index of an img`` character, '.' and unknown ones are transparent
+pub fn map_color(idx : &u8) -> u8{
+    match idx {
+        b'a'..=b'f' => idx - b'a' + 10,
+        b'A'..=b'F' => idx - b'A' + 10,
+        b'0'..=b'9' => idx - b'0',
+        _ => 0,
+    }
+}
 
-pub fn map_color2(idx : u8) -> u32{
+//as map_color, but unknown characters are black, for backgrounds
+pub fn map_color2(idx : u8) -> u8{
     match idx {
-        b'a'..=b'f' => COLORS[(idx - b'a' + 10) as usize], //&[255,0,0,255],
-        b'0'..=b'9' => COLORS[(idx - b'0') as usize],      //&[255,255,0,255],//
-        _ => COLORS[15],                                     //&[255,0,255,255],//
+        b'a'..=b'f' | b'A'..=b'F' | b'0'..=b'9' => map_color(&idx),
+        _ => 15,
     }
-    // println!("{},{},{}", dest[40],dest[41],dest[42]);
-    // println!("{},{},{}", dest[43],dest[44],dest[45])
 }
 
+//pixels are palette indices, 0 is transparent
 pub struct Bitmap{
-    pixels: Vec<u32>,
+    pixels: Vec<u8>,
     w: usize,
 }
 
@@ -57,7 +102,7 @@ impl Bitmap {
         Bitmap{pixels:vec![map_color2(color);w*h], w}
     }
 
-    pub fn new_with_bmp(pixels:Vec<u32>,w:usize) -> Self{
+    pub fn new_with_bmp(pixels:Vec<u8>,w:usize) -> Self{
         Bitmap{pixels, w}
     }
     pub fn new(w:usize,h:usize) ->Bitmap{
@@ -65,7 +110,7 @@ impl Bitmap {
     }
 
     pub fn new_from_string_without_size(data:&str) -> Bitmap{
-        let mut pixels:Vec<u32> = vec![];
+        let mut pixels:Vec<u8> = vec![];
         let mut width = 0;
         let mut ix = 0;
         for line in data.split("\n") {
@@ -84,7 +129,7 @@ impl Bitmap {
         Bitmap{pixels, w: width}
     }
 
-    pub fn set_data<T>(&mut self, w:usize,src:T) where T: Iterator<Item=u32>{
+    pub fn set_data<T>(&mut self, w:usize,src:T) where T: Iterator<Item=u8>{
         self.w = w;
         self.pixels=src.collect();
         assert!(self.pixels.len() % self.w == 0);
@@ -105,6 +150,20 @@ impl Bitmap {
         self.w
     }
 
+    pub fn save(&self, w: &mut Writer) {
+        w.usize(self.w);
+        w.bytes(&self.pixels);
+    }
+
+    pub fn load(r: &mut Reader) -> Result<Bitmap, StateError> {
+        let w = r.usize()?;
+        let pixels = r.bytes()?.to_vec();
+        if (w == 0 && !pixels.is_empty()) || (w != 0 && pixels.len() % w != 0) {
+            return Err(StateError::Invalid("bitmap"));
+        }
+        Ok(Bitmap{pixels, w})
+    }
+
     pub fn height(&self) -> usize {
         if self.w == 0 {
             return 0;
@@ -112,7 +171,17 @@ impl Bitmap {
         self.pixels.len() / self.w
     }
 
-    fn get_pixel(&self, x:usize, y:usize) -> u32{
+    //copy src into self at x,y, clipped to self
+    pub fn blit(&mut self, x:usize, y:usize, src:&Bitmap){
+        let h = self.height();
+        for sy in 0..min(src.height(), h.saturating_sub(y)) {
+            for sx in 0..min(src.w, self.w.saturating_sub(x)) {
+                self.pixels[(y+sy)*self.w + x+sx] = src.get_pixel(sx, sy);
+            }
+        }
+    }
+
+    fn get_pixel(&self, x:usize, y:usize) -> u8{
         //println!("{}, {}", x, y);
         self.pixels[y*self.w+x]
     }
@@ -157,13 +226,28 @@ pub struct Rect{
 pub static CANVAS_RECT: Rect = Rect{x:0,y:0,w:BMP_WIDTH as usize,h:BMP_HEIGHT as usize};
 
 //#[derive(Clone, Copy)]
-pub struct Canvas(pub [u32;game::BMP_WIDTH as usize * game::BMP_HEIGHT as usize]);
+//palette indices of the screen, to_xrgb gives the frame buffer
+pub struct Canvas(pub [u8;game::BMP_WIDTH as usize * game::BMP_HEIGHT as usize]);
 
 impl Canvas {
     pub fn new() -> Self {
         Canvas([0;BMP_WIDTH as usize * BMP_HEIGHT as usize])
     }
 
+    pub fn save(&self, w: &mut Writer) {
+        w.bytes(&self.0);
+    }
+
+    pub fn load(r: &mut Reader) -> Result<Canvas, StateError> {
+        let pixels = r.bytes()?;
+        let mut canvas = Canvas::new();
+        if pixels.len() != canvas.0.len() {
+            return Err(StateError::Invalid("canvas"));
+        }
+        canvas.0.copy_from_slice(pixels);
+        Ok(canvas)
+    }
+
     // pub fn test_overlap(&self, rhs: &BitmapOnCanvas, clip: &Rect) -> bool {
     //     for x in clip.x..clip.right() {
     //         for y in clip.y..clip.bottom() {
@@ -176,9 +260,22 @@ impl Canvas {
     //     false
     // }
 
-    pub fn get_pixel(&self, x:usize, y:usize) -> u32{
+    pub fn to_xrgb(&self, palette: &Palette) -> [u32; BMP_WIDTH as usize * BMP_HEIGHT as usize] {
+        let mut fb = [0u32; BMP_WIDTH as usize * BMP_HEIGHT as usize];
+        self.write_xrgb(palette, &mut fb);
+        fb
+    }
+
+    //same as to_xrgb, into a frame buffer the caller keeps
+    pub fn write_xrgb(&self, palette: &Palette, fb: &mut [u32]) {
+        for (dest, idx) in fb.iter_mut().zip(self.0.iter()) {
+            *dest = palette.color(*idx);
+        }
+    }
+
+    pub fn get_pixel(&self, x:usize, y:usize) -> u8{
         if x >= BMP_WIDTH as usize || y >=BMP_HEIGHT as usize{
-            0u32
+            0u8
         }else{
             self.0[y * BMP_WIDTH as usize + x]
         }
@@ -210,7 +307,7 @@ impl Canvas {
                 if bmp.pixels[pixel as usize] == 0 {
                     continue;
                 }
-                let dest_idx:&mut u32 = &mut self.0[(dest_y + i) as usize * BMP_WIDTH as usize + (dest_x as usize + (pixel-src_start) as usize)];
+                let dest_idx:&mut u8 = &mut self.0[(dest_y + i) as usize * BMP_WIDTH as usize + (dest_x as usize + (pixel-src_start) as usize)];
                 *dest_idx = bmp.pixels[pixel as usize];
             }
         }
@@ -280,6 +377,27 @@ impl Rect{
     }
 }
 
+#[test]
+fn test_palette() {
+    let palette = Palette::default();
+    assert_eq!(palette.color(15), 0);
+    assert_eq!(palette.color(map_color(&b'2')), 0xff2121);
+    assert_eq!(map_color(&b'.'), 0);
+    assert_eq!(map_color2(b'.'), 15);
+
+    let custom: Vec<String> = (0..16).map(|i| format!("#{:06X}", i * 0x111111)).collect();
+    let palette = Palette::from_hex_colors(custom.iter().map(|s| s.as_str())).unwrap();
+    assert_eq!(palette.color(1), 0x111111);
+    assert_eq!(palette.color(0xf), 0xffffff);
+    assert_eq!(Palette::from_hex_colors(custom[..15].iter().map(|s| s.as_str())), None);
+    assert_eq!(Palette::from_hex_colors(["#zzzzzz"; 16].into_iter()), None);
+
+    let mut canvas = Canvas::new();
+    canvas.draw(0, 0, &Bitmap::new_from_string("1.", 2, 1));
+    let fb = canvas.to_xrgb(&palette);
+    assert_eq!(&fb[..2], &[0x111111, 0]);
+}
+
 #[cfg(test)]
 mod tests_rect{
     use crate::emulator::resource::Rect;
@@ -329,6 +447,22 @@ impl Frames {
     pub fn add_bmp(&mut self, bmp: Bitmap) {
         self.bmp.push(bmp);
     }
+
+    pub fn save(&self, w: &mut Writer) {
+        w.duration(&self.interval);
+        w.usize(self.bmp.len());
+        self.bmp.iter().for_each(|b| b.save(w));
+    }
+
+    pub fn load(r: &mut Reader) -> Result<Frames, StateError> {
+        let interval = r.duration()?;
+        if interval.as_millis() == 0 {
+            return Err(StateError::Invalid("animation interval"));
+        }
+        let n = r.count(16)?;
+        let bmp = (0..n).map(|_| Bitmap::load(r)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Frames{bmp, interval})
+    }
 }
 
 pub struct FrameMgr {
@@ -345,6 +479,26 @@ impl FrameMgr {
         //println!("append frame to {}. animations len:{}", name, self.frames.len());
         self.frames.get_mut(name).unwrap().add_bmp(bmp);
     }
+
+    pub fn save(&self, w: &mut Writer) {
+        let mut names: Vec<&String> = self.frames.keys().collect();
+        names.sort();
+        w.usize(names.len());
+        for name in names {
+            w.str(name);
+            self.frames[name].save(w);
+        }
+    }
+
+    pub fn load(r: &mut Reader) -> Result<FrameMgr, StateError> {
+        let n = r.count(8 + 8 + 8)?;
+        let mut frames = HashMap::new();
+        for _ in 0..n {
+            let name = r.string()?;
+            frames.insert(name, Frames::load(r)?);
+        }
+        Ok(FrameMgr{frames})
+    }
 }
 
 #[derive(Clone)]
@@ -375,6 +529,15 @@ impl Animation {
         self.elapsed += *dt;
     }
 
+    pub fn save(&self, w: &mut Writer) {
+        w.str(&self.current_name);
+        w.duration(&self.elapsed);
+    }
+
+    pub fn load(r: &mut Reader) -> Result<Animation, StateError> {
+        Ok(Animation{current_name: r.string()?, elapsed: r.duration()?})
+    }
+
     pub fn current<'a>(&self, fmr:&'a FrameMgr) -> Option<&'a Bitmap> {        
         if self.current_name == "" {
             return None;
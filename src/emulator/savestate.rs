@@ -0,0 +1,142 @@
+//little endian writer and reader of the save state blob, every emulator type writes its own fields
+use std::{fmt, time};
+
+#[derive(Debug)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    //a value the game could not have written, e.g. a bitmap of a wrong size
+    Invalid(&'static str),
+    //no game is loaded to restore the state into
+    NoGame,
+    //the engine thread is gone
+    Stopped,
+    Js(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported save state version {}", v),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "invalid {} in save state", what),
+            StateError::NoGame => write!(f, "no game loaded"),
+            StateError::Stopped => write!(f, "game engine stopped"),
+            StateError::Js(e) => write!(f, "restore script state: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+#[derive(Default)]
+pub struct Writer(pub Vec<u8>);
+
+impl Writer {
+    pub fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+    pub fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn i32(&mut self, v: i32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn usize(&mut self, v: usize) {
+        self.u64(v as u64);
+    }
+    pub fn f32(&mut self, v: f32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn duration(&mut self, v: &time::Duration) {
+        self.u64(v.as_nanos() as u64);
+    }
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.usize(v.len());
+        self.0.extend_from_slice(v);
+    }
+    pub fn str(&mut self, v: &str) {
+        self.bytes(v.as_bytes());
+    }
+}
+
+pub struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < n {
+            return Err(StateError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn i32(&mut self) -> Result<i32, StateError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn usize(&mut self) -> Result<usize, StateError> {
+        usize::try_from(self.u64()?).map_err(|_| StateError::Invalid("length"))
+    }
+    //a count of items, each at least min_size bytes, checked against what is left
+    pub fn count(&mut self, min_size: usize) -> Result<usize, StateError> {
+        let n = self.usize()?;
+        if n.saturating_mul(min_size.max(1)) > self.0.len() {
+            return Err(StateError::Truncated);
+        }
+        Ok(n)
+    }
+    pub fn f32(&mut self) -> Result<f32, StateError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn duration(&mut self) -> Result<time::Duration, StateError> {
+        Ok(time::Duration::from_nanos(self.u64()?))
+    }
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let n = self.usize()?;
+        self.take(n)
+    }
+    pub fn string(&mut self) -> Result<String, StateError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| StateError::Invalid("string"))
+    }
+}
+
+#[test]
+fn test_writer_reader() {
+    let mut w = Writer::default();
+    w.u8(7);
+    w.bool(true);
+    w.i32(-3);
+    w.f32(1.5);
+    w.duration(&time::Duration::from_millis(20));
+    w.str("sprite");
+    w.usize(1 << 40);
+    let mut r = Reader(&w.0);
+    assert_eq!(r.u8().unwrap(), 7);
+    assert!(r.bool().unwrap());
+    assert_eq!(r.i32().unwrap(), -3);
+    assert_eq!(r.f32().unwrap(), 1.5);
+    assert_eq!(r.duration().unwrap(), time::Duration::from_millis(20));
+    assert_eq!(r.string().unwrap(), "sprite");
+    assert!(matches!(r.count(1), Err(StateError::Truncated)));
+    assert!(matches!(r.u8(), Err(StateError::Truncated)));
+}
@@ -1,8 +1,9 @@
 use std::{cell::RefCell, cmp::Ordering, collections::{HashMap, HashSet}, ops::Deref, rc::Rc};
 use crate::emulator::{resource::CANVAS_RECT, sprite::Flag};
 
-use super::{effect::{Effect, SceneEffect}, resource::{Bitmap, Canvas, FrameMgr}};
+use super::{effect::{Effect, Rng, SceneEffect}, resource::{Bitmap, Canvas, FrameMgr, Palette}, savestate::{Reader, StateError, Writer}};
 use super::sprite::Sprite;
+use super::asset::{tilemap_pixels, TilemapError};
 #[derive(Copy, Clone)]
 struct PixelLine<T> {
     points: [T; 160],
@@ -32,9 +33,15 @@ pub struct Scene {
     overlap_detections: HashSet<(usize, usize)>,
     //canvas: Canvas,
     bgi: Canvas,
+    //tilemap drawn over the background, from the top left of the screen
+    tiles: Bitmap,
     current_z: u32,
+    //last kind SpriteKind.create() gave out
+    last_kind: usize,
     pub frame_mgr:Rc<RefCell<FrameMgr>>,
     effect:Box<dyn Effect>,
+    //colors of the indices drawn on the canvas
+    pub palette:Palette,
 }
 
 impl Scene {
@@ -45,11 +52,61 @@ impl Scene {
             overlap_detections: HashSet::new(),
             //canvas: Canvas::new(),
             bgi: Canvas::new(),
+            tiles: Bitmap::new(0, 0),
             current_z: 1u32,
+            last_kind: super::sprite::sprite_kind::LAST_BUILTIN,
             frame_mgr:fmr,
             effect:SceneEffect::dummy(),
+            palette:Palette::default(),
         }
     }
+    pub fn save(&self, w: &mut Writer) {
+        w.usize(self.last_idx);
+        w.u32(self.current_z);
+        w.usize(self.last_kind);
+        let mut idxs: Vec<&usize> = self.sprites.keys().collect();
+        idxs.sort();
+        w.usize(idxs.len());
+        for i in idxs {
+            w.usize(*i);
+            self.sprites[i].save(w);
+        }
+        let mut kinds: Vec<&(usize, usize)> = self.overlap_detections.iter().collect();
+        kinds.sort();
+        w.usize(kinds.len());
+        for (k1, k2) in kinds {
+            w.usize(*k1);
+            w.usize(*k2);
+        }
+        self.bgi.save(w);
+        self.tiles.save(w);
+        self.frame_mgr.borrow().save(w);
+        self.effect.save(w);
+        self.palette.save(w);
+    }
+    pub fn load(r: &mut Reader) -> Result<Scene, StateError> {
+        let mut scene = Scene::new(Rc::new(RefCell::new(FrameMgr{frames:HashMap::new()})));
+        scene.last_idx = r.usize()?;
+        scene.current_z = r.u32()?;
+        scene.last_kind = r.usize()?;
+        for _ in 0..r.count(8)? {
+            let i = r.usize()?;
+            scene.sprites.insert(i, Sprite::load(r)?);
+        }
+        for _ in 0..r.count(16)? {
+            scene.overlap_detections.insert((r.usize()?, r.usize()?));
+        }
+        scene.bgi = Canvas::load(r)?;
+        scene.tiles = Bitmap::load(r)?;
+        *scene.frame_mgr.borrow_mut() = FrameMgr::load(r)?;
+        scene.effect = SceneEffect::load(r)?;
+        scene.palette = Palette::load(r)?;
+        Ok(scene)
+    }
+    pub fn create_sprite_kind(&mut self) -> usize {
+        self.last_kind += 1;
+        self.last_kind
+    }
     pub fn active_effect(&mut self, effect:Box<dyn Effect>) {
         self.effect = effect;
     }
@@ -79,6 +136,19 @@ impl Scene {
         self.bgi.draw(0, 0, &img);
     }
 
+    //cells index tileset, row major, width x height of them
+    pub fn set_tilemap(&mut self, width:usize, height:usize, cells:&[u8], tileset:&[Bitmap], tile_size:usize) -> Result<(), TilemapError>{
+        let (w, h) = tilemap_pixels(width, height, tile_size)?;
+        let mut tiles = Bitmap::new(w, h);
+        for (i, cell) in cells.iter().enumerate().take(width * height) {
+            if let Some(tile) = tileset.get(*cell as usize) {
+                tiles.blit((i % width) * tile_size, (i / width) * tile_size, tile);
+            }
+        }
+        self.tiles = tiles;
+        Ok(())
+    }
+
     pub fn draw(&self, canvas:&mut Canvas){
         super::profile_method!(draw);
 
@@ -88,6 +158,7 @@ impl Scene {
 
         super::profile_section!(draw_background);
         //canvas.draw(0,0, &self.bgi);
+        canvas.draw(0, 0, &self.tiles);
         drop(draw_background);
 
         super::profile_section!(draw_scene_effect);
@@ -162,7 +233,7 @@ impl Scene {
         self.overlap_detections.insert((kind1, kind2));
     }
 
-    pub fn update(&mut self, dt: &std::time::Duration) {
+    pub fn update(&mut self, dt: &std::time::Duration, rng: &mut Rng) {
         super::profile_fn!(scene_update);
 
         {
@@ -176,7 +247,7 @@ impl Scene {
         }
         {
             super::profile_section!(effect_update);
-            self.effect.update(dt);
+            self.effect.update(dt, rng);
         }
 
         {
@@ -303,3 +374,44 @@ mod test_overlap {
     }
 }
 
+
+#[test]
+fn test_save_load() {
+    let mut scene = Scene::new(Rc::new(RefCell::new(FrameMgr{frames:HashMap::new()})));
+    scene.set_bgi(Bitmap::new_with_color(160, 120, b'7'));
+    let mut sp = Sprite::new_with_bmp(Bitmap::new_from_string("1221", 2, 2), 1);
+    sp.vx = 12.5;
+    let i = scene.add_sprite(sp);
+    scene.add_overlap_detection(1, 2);
+    scene.frame_mgr.borrow_mut().create("walk", 100);
+    scene.frame_mgr.borrow_mut().append("walk", Bitmap::new_from_string("33", 1, 2));
+    scene.get_mut(i).active_animation(String::from("walk"));
+    let mut rng = Rng::new(1);
+    scene.active_effect(SceneEffect::blizzard(&mut rng));
+    scene.update(&std::time::Duration::from_millis(20), &mut rng);
+
+    let mut w = Writer::default();
+    scene.save(&mut w);
+    let loaded = Scene::load(&mut Reader(&w.0)).unwrap();
+    let mut again = Writer::default();
+    loaded.save(&mut again);
+    assert_eq!(w.0, again.0);
+    let (mut c1, mut c2) = (Canvas::new(), Canvas::new());
+    scene.draw(&mut c1);
+    loaded.draw(&mut c2);
+    assert_eq!(c1.0, c2.0);
+    assert_eq!(loaded.get(i).vx, 12.5);
+    assert!(Scene::load(&mut Reader(&w.0[..w.0.len() - 1])).is_err());
+}
+
+#[test]
+fn test_set_tilemap() {
+    let mut scene = Scene::new(Rc::new(RefCell::new(FrameMgr{frames:HashMap::new()})));
+    let tileset = [Bitmap::new(2, 2), Bitmap::new_with_color(2, 2, b'3')];
+    scene.set_tilemap(2, 1, &[0, 1], &tileset, 2).unwrap();
+    assert_eq!((scene.tiles.width(), scene.tiles.height()), (4, 2));
+    //refused before allocating, the previous map stays
+    assert_eq!(scene.set_tilemap(0xffff, 0xffff, &[], &tileset, 128), Err(TilemapError::TooLarge(0xffff, 0xffff, 128)));
+    assert!(scene.set_tilemap(usize::MAX, 2, &[], &tileset, 16).is_err());
+    assert_eq!(scene.tiles.width(), 4);
+}
@@ -1,6 +1,6 @@
 use std::{time};
 
-use super::{game::{BMP_HEIGHT, BMP_WIDTH}, resource::{Animation, Bitmap, FrameMgr, Rect}};
+use super::{game::{BMP_HEIGHT, BMP_WIDTH}, resource::{Animation, Bitmap, FrameMgr, Rect}, savestate::{Reader, StateError, Writer}};
 
 #[repr(u32)]
 pub enum Flag{
@@ -48,6 +48,34 @@ impl Sprite {
         //s.height = bmp.pixels.len()
         s
     }
+    pub fn save(&self, w: &mut Writer) {
+        for v in [self.x, self.y, self.vx, self.vy, self.ax, self.ay, self.fx, self.fy, self.sx, self.sy] {
+            w.f32(v);
+        }
+        w.i32(self.z);
+        w.i32(self.z2);
+        w.usize(self.kind);
+        w.usize(self.width);
+        w.usize(self.height);
+        w.u32(self.flag);
+        self.image.save(w);
+        self.anmi.save(w);
+    }
+    pub fn load(r: &mut Reader) -> Result<Sprite, StateError> {
+        let mut s = Sprite::new();
+        for v in [&mut s.x, &mut s.y, &mut s.vx, &mut s.vy, &mut s.ax, &mut s.ay, &mut s.fx, &mut s.fy, &mut s.sx, &mut s.sy] {
+            *v = r.f32()?;
+        }
+        s.z = r.i32()?;
+        s.z2 = r.i32()?;
+        s.kind = r.usize()?;
+        s.width = r.usize()?;
+        s.height = r.usize()?;
+        s.flag = r.u32()?;
+        s.image = Bitmap::load(r)?;
+        s.anmi = Animation::load(r)?;
+        Ok(s)
+    }
     pub fn set_left(&mut self, x:i32) {
         self.x = (x + (self.width /2 ) as i32) as f32;
     }
@@ -145,19 +173,9 @@ impl Sprite {
     }
 }
 
+//kinds made by SpriteKind.create() are counted by the scene, from LAST_BUILTIN + 1
 pub mod sprite_kind {
-    use std::cell::RefCell;
-
-thread_local!{
-    static LAST_KIND :RefCell<usize> = RefCell::new(10usize);
-}
-pub fn create() -> usize {
-    LAST_KIND.with(|vp| -> usize {
-        let v = vp.take() + 1;
-        *vp.borrow_mut() = v;
-        v
-    })
-}
+pub const LAST_BUILTIN: usize = 10;
 
 pub fn player() -> usize {
     1usize
@@ -1,6 +1,6 @@
 //mod emulator;
 use mpsc::Receiver;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 
 use std::sync::mpsc::{self, SyncSender};
@@ -9,6 +9,7 @@ use std::{thread, fmt};
 
 use crate::emulator;
 use crate::emulator::game::{BMP_WIDTH, BMP_HEIGHT, KeyCode};
+use crate::emulator::savestate::StateError;
 use crate::v8_binding::Runtime;
 
 #[cfg(feature = "dhat-heap")]
@@ -22,14 +23,73 @@ pub enum Event {
     KeyUp(KeyCode),
     KeyDown(KeyCode),
     KeyRepeat(KeyCode),
+    //a new game, in a fresh context
     Load(Project),
+    //the loaded game from its start
+    Restart,
+    //drop the game and its context, a blank screen until the next load
     Unload,
+    //Some(seed): seeded randomness and a fixed frame time from the next (re)start, None: back to the clock
+    Deterministic(Option<u64>),
+    //debug controls: frames are still drawn and keys handled while paused, but the game's time stands still
+    Pause(bool),
+    //while paused, the next tick runs as if the game was not
+    FrameStep,
+    //the game's time runs at this many times the time of the ticks, 0.25 to 4
+    TimeScale(f32),
     Exit,
 }
 
 #[derive(Debug)]
 pub struct Project{
+    //original text of every project file, as in the pxt file map
+    pub files:HashMap<String, String>,
+    //transpiled js, keyed by source file name
     pub sources:HashMap<String, String>,
+    //file names in the order they must run, as listed in pxt.json
+    pub order:Vec<String>,
+    //source map json of each transpiled file
+    pub source_maps:HashMap<String, String>,
+    //images, animations and tilemaps of the .jres files
+    pub assets:emulator::asset::AssetTable,
+    //pxt.json "palette", or the default one
+    pub palette:emulator::resource::Palette,
+    //name, editor and versions, for the frontend and embedders
+    pub meta:ProjectMeta,
+}
+
+//what makecode keeps about a project beside its files, from pxt.json and the saved header
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProjectMeta{
+    pub name:String,
+    pub description:String,
+    //"blocksprj", "tsprj" or "pyprj"
+    pub editor:String,
+    //makecode target id, "arcade"
+    pub target:String,
+    //version of the target the project was saved with, e.g. "1.8.21"
+    pub target_version:String,
+    //package name to version, "*" for the ones built in the target
+    pub dependencies:BTreeMap<String, String>,
+}
+
+impl ProjectMeta {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "description": self.description,
+            "editor": self.editor,
+            "target": self.target,
+            "targetVersion": self.target_version,
+            "dependencies": self.dependencies,
+        })
+    }
+}
+
+impl Project {
+    pub fn scripts(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.order.iter().filter_map(|name| self.sources.get(name).map(|s| (name, s)))
+    }
 }
 
 unsafe impl Send for Project{}
@@ -42,23 +102,196 @@ impl fmt::Display for Event {
     }
 }
 
+//one rendered frame, and the game state it shows
+#[derive(Clone)]
+pub struct Frame {
+    pub pixels: [u32; (BMP_WIDTH * BMP_HEIGHT) as usize],
+    pub score: i32,
+    //Some(win) once the game is over
+    pub game_over: Option<bool>,
+    //key events handled before the frame was drawn, to tell when a key press shows on screen
+    pub inputs: u64,
+}
+
+impl Frame {
+    pub fn blank() -> Self {
+        Frame {
+            pixels: [0; (BMP_WIDTH * BMP_HEIGHT) as usize],
+            score: 0,
+            game_over: None,
+            inputs: 0,
+        }
+    }
+}
+
+//the game loop of one runtime: a tick, the events since the last one, then a frame drawn in place.
+//the engine thread drives one, retro_run drives one itself in synchronous mode
+pub struct Stepper {
+    runtime: Runtime,
+    prj: Option<Project>,
+    fps: u32,
+    //the frame time in deterministic mode, whatever the frontend measured
+    fixed_dt: Option<u64>,
+    canvas: emulator::resource::Canvas,
+    frame: Frame,
+    inputs: u64,
+    paused: bool,
+    //frame steps not run yet
+    steps: u32,
+    time_scale: f32,
+}
+
+impl Stepper {
+    pub fn new(fps: u32) -> Self {
+        Stepper {
+            runtime: Runtime::new(),
+            prj: None,
+            fps,
+            fixed_dt: None,
+            canvas: emulator::resource::Canvas::new(),
+            frame: Frame::blank(),
+            inputs: 0,
+            paused: false,
+            steps: 0,
+            time_scale: 1.0,
+        }
+    }
+
+    //false for Exit, whoever drives the stepper stops
+    pub fn handle(&mut self, evt: Event) -> bool {
+        match evt {
+            //TODO: process inputs
+            Event::Exit => return false,
+            Event::Restart => {
+                if let Some(p) = self.prj.as_ref() {
+                    self.runtime.reset();
+                    self.runtime.run_project(p);
+                }
+            }
+            Event::Unload => {
+                self.prj = None;
+                self.runtime.unload();
+            }
+            Event::Load(v) => {
+                self.runtime.unload();
+                self.runtime.run_project(&v);
+                self.prj = Some(v);
+            }
+            Event::Deterministic(seed) => {
+                self.runtime.set_seed(seed);
+                self.fixed_dt = seed.map(|_| 1_000_000 / self.fps as u64);
+            }
+            Event::Pause(paused) => {
+                self.paused = paused;
+                self.steps = 0;
+            }
+            Event::FrameStep => {
+                if self.paused {
+                    self.steps += 1;
+                }
+            }
+            Event::TimeScale(scale) => {
+                self.time_scale = if scale.is_finite() { scale.clamp(0.25, 4.0) } else { 1.0 };
+            }
+            Event::KeyDown(_) | Event::KeyRepeat(_) | Event::KeyUp(_) => {
+                self.inputs += 1;
+                self.runtime.process_events(evt);
+                if self.runtime.restart_requested() {
+                    return self.handle(Event::Restart);
+                }
+            }
+        }
+        true
+    }
+
+    pub fn update(&mut self, micro_sec: u64) {
+        if self.paused {
+            if self.steps == 0 {
+                return;
+            }
+            self.steps -= 1;
+        }
+        let micro_sec = self.fixed_dt.unwrap_or(micro_sec);
+        self.runtime.update((micro_sec as f64 * self.time_scale as f64).round() as u64);
+    }
+
+    //overlaps, then the screen into the frame, whose pixels stay at the same address between steps
+    pub fn render(&mut self) -> &Frame {
+        self.runtime.process_overlap_check();
+        self.canvas.0.fill(0);
+        self.runtime.draw(&mut self.canvas);
+        self.canvas.write_xrgb(&self.runtime.palette(), &mut self.frame.pixels);
+        self.frame.score = self.runtime.score();
+        self.frame.game_over = self.runtime.game_over();
+        self.frame.inputs = self.inputs;
+        &self.frame
+    }
+
+    //one whole frame in the caller's thread, the events are handled after the tick as on the engine thread
+    pub fn step(&mut self, micro_sec: u64, events: impl IntoIterator<Item = Event>) -> &Frame {
+        self.update(micro_sec);
+        for evt in events {
+            self.handle(evt);
+        }
+        self.render()
+    }
+
+    //the frame of the last render
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    pub fn save_state(&mut self) -> Vec<u8> {
+        self.runtime.save_state()
+    }
+
+    pub fn restore_state(&mut self, blob: &[u8]) -> Result<(), StateError> {
+        if self.prj.is_none() {
+            return Err(StateError::NoGame);
+        }
+        self.runtime.restore_state(blob)
+    }
+}
+
+//what wakes the engine thread up
+pub enum Tick {
+    //run the game for micro seconds, then draw a frame
+    Run(u64),
+    //a save state of the game, no frame is drawn
+    Save(SyncSender<Vec<u8>>),
+    //go on from a save state of the loaded project, no frame is drawn
+    Restore(Vec<u8>, SyncSender<Result<(), StateError>>),
+}
+
+//a Stepper on its own thread, every frame is copied to fb_rx
 pub struct Engine {
-    pub fb_rx: Receiver<[u32; (BMP_WIDTH * BMP_HEIGHT) as usize]>,
+    pub fb_rx: Receiver<Frame>,
     pub event_tx: SyncSender<Event>,
-    pub tick_tx: SyncSender<u64>,
+    pub tick_tx: SyncSender<Tick>,
 }
 
 unsafe impl Sync for Engine {}
 
 impl Engine {
-    pub fn new(_fps: u32, _audio_hz: u32) -> Self {
-        let (fb_tx, fb_rx) =
-            std::sync::mpsc::sync_channel::<[u32; (BMP_WIDTH * BMP_HEIGHT) as usize]>(2);
-        let (tick_tx, tick_rx) = std::sync::mpsc::sync_channel::<u64>(1);
-        let (event_tx, event_rx) = std::sync::mpsc::sync_channel::<Event>(5);
+    pub fn save_state(&self) -> Option<Vec<u8>> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.tick_tx.send(Tick::Save(tx)).ok()?;
+        rx.recv().ok()
+    }
+
+    pub fn restore_state(&self, blob: &[u8]) -> Result<(), StateError> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.tick_tx.send(Tick::Restore(blob.to_vec(), tx)).map_err(|_| StateError::Stopped)?;
+        rx.recv().map_err(|_| StateError::Stopped)?
+    }
+
+    pub fn new(fps: u32, _audio_hz: u32) -> Self {
+        let (fb_tx, fb_rx) = std::sync::mpsc::sync_channel::<Frame>(2);
+        let (tick_tx, tick_rx) = std::sync::mpsc::sync_channel::<Tick>(1);
+        //every key may change between two ticks, plus a load
+        let (event_tx, event_rx) = std::sync::mpsc::sync_channel::<Event>(16);
         thread::spawn(move || {
-            let mut runtime = Runtime::new();
-            let mut prj:Option<Project> = None;
+            let mut stepper = Stepper::new(fps);
             
             'main: loop {                
                 match tick_rx.recv() {
@@ -66,8 +299,14 @@ impl Engine {
                         println!("game engine receive event:{}", e);
                         break 'main;
                     }
-                    Ok(micro_sec) => {
-                        runtime.update(micro_sec)
+                    Ok(Tick::Run(micro_sec)) => stepper.update(micro_sec),
+                    Ok(Tick::Save(reply)) => {
+                        let _ = reply.send(stepper.save_state());
+                        continue 'main;
+                    },
+                    Ok(Tick::Restore(blob, reply)) => {
+                        let _ = reply.send(stepper.restore_state(&blob));
+                        continue 'main;
                     },
                 }
 
@@ -78,32 +317,13 @@ impl Engine {
                             println!("game engine receive event:{}", e);
                             break 'main;
                         },
-                        Ok(evt) => match evt {
-                            //TODO: process inputs
-                            Event::Exit => {break 'main;},
-                            Event::Unload=>{
-                                runtime.reset();
-                                if let Some(p) = prj.as_ref() {
-                                    runtime.run_script(p.sources.get("main.ts").unwrap());
-                                }
-                            },
-                            Event::Load(v)=>{               
-                                prj = Some(v);                 
-                                runtime.run_script(prj.as_ref().unwrap().sources.get("main.ts").unwrap());
-                            },
-                            Event::KeyDown(_)|Event::KeyRepeat(_)|Event::KeyUp(_)=>{
-                                runtime.process_events(evt);
-                            },
+                        Ok(evt) => if !stepper.handle(evt) {
+                            break 'main;
                         },
                     }
                 }
 
-                runtime.process_overlap_check();
-
-                let mut canvas = emulator::resource::Canvas::new();
-                runtime.draw(&mut canvas);
-
-                match fb_tx.send(canvas.0) {
+                match fb_tx.send(stepper.render().clone()) {
                     Err(err) => {
                         println!("receiver disconnected:{}", err);
                         break 'main;
@@ -119,3 +339,107 @@ impl Engine {
         }
     }
 }
+
+#[test]
+fn test_stepper() {
+    let mut stepper = Stepper::new(50);
+    let prj = crate::loader::load(
+        b"controller.anyButton.onEvent(ControllerButtonEvent.Pressed, () => info.changeScoreBy(1))",
+        None,
+    )
+    .unwrap();
+    stepper.handle(Event::Load(prj));
+    let pixels = stepper.step(20000, None).pixels.as_ptr();
+    let frame = stepper.step(20000, [Event::KeyDown(KeyCode::A)]);
+    //the key shows in the frame of the step it came with, drawn into the same buffer
+    assert_eq!((frame.score, frame.inputs), (1, 1));
+    assert_eq!(frame.pixels.as_ptr(), pixels);
+    assert_eq!(stepper.step(20000, [Event::Restart]).score, 0);
+    stepper.handle(Event::Unload);
+    assert_eq!(stepper.step(20000, [Event::Restart, Event::KeyDown(KeyCode::B)]).score, 0);
+    assert!(matches!(stepper.restore_state(b"MKSS"), Err(StateError::NoGame)));
+    assert!(!stepper.handle(Event::Exit));
+}
+
+#[test]
+fn test_debug_controls() {
+    let mut stepper = Stepper::new(50);
+    let prj = crate::loader::load(b"game.onUpdateInterval(100, () => info.changeScoreBy(1))", None).unwrap();
+    stepper.handle(Event::Load(prj));
+    stepper.handle(Event::Pause(true));
+    for _ in 0..10 {
+        stepper.step(20000, None);
+    }
+    assert_eq!(stepper.frame().score, 0);
+    //5 frames of 20ms make the 100ms of the interval
+    for _ in 0..5 {
+        stepper.handle(Event::FrameStep);
+        stepper.step(20000, None);
+        stepper.step(20000, None);
+    }
+    assert_eq!(stepper.frame().score, 1);
+
+    stepper.handle(Event::Pause(false));
+    stepper.handle(Event::TimeScale(100.0));
+    for _ in 0..2 {
+        stepper.step(20000, None);
+    }
+    //at 4x two frames are 160ms
+    assert_eq!(stepper.frame().score, 2);
+    //the 60ms past the interval carry over, 8 frames of 5ms make the next 100ms
+    stepper.handle(Event::TimeScale(0.25));
+    for _ in 0..7 {
+        stepper.step(20000, None);
+    }
+    assert_eq!(stepper.frame().score, 2);
+    stepper.step(20000, None);
+    assert_eq!(stepper.frame().score, 3);
+}
+
+#[test]
+fn test_replay_playback() {
+    use crate::replay::{Player, Recorder, Replay};
+
+    let src = b"let x = 0
+controller.anyButton.onEvent(ControllerButtonEvent.Pressed, () => info.changeScoreBy(randint(1, 100)))
+game.onUpdate(() => { x += randint(0, 3); info.changeScoreBy(x % 2) })
+effects.blizzard.startScreenEffect()
+";
+    let inputs = |frame: u32| match frame {
+        3 => vec![Event::KeyDown(KeyCode::A)],
+        5 => vec![Event::KeyUp(KeyCode::A)],
+        8 => vec![Event::Pause(true), Event::KeyDown(KeyCode::B)],
+        10 => vec![Event::FrameStep],
+        13 => vec![Event::Pause(false), Event::TimeScale(2.0)],
+        16 => vec![Event::KeyUp(KeyCode::B)],
+        _ => vec![],
+    };
+    let mut stepper = Stepper::new(50);
+    stepper.handle(Event::Deterministic(Some(9)));
+    stepper.handle(Event::Load(crate::loader::load(src, None).unwrap()));
+    let mut recorder = Recorder::new(9, 20000, "replay");
+    let mut recorded = vec![];
+    for n in 0..20 {
+        let events = inputs(n);
+        events.iter().for_each(|evt| recorder.record(evt));
+        let frame = stepper.step(20000, events);
+        recorded.push((frame.score, frame.pixels.to_vec()));
+        recorder.next_frame();
+    }
+    //runtimes on one thread are dropped before the next one is made
+    drop(stepper);
+
+    let replay = Replay::from_bytes(&recorder.finish().to_bytes().unwrap()).unwrap();
+    let mut stepper = Stepper::new(50);
+    stepper.handle(Event::Deterministic(Some(replay.seed)));
+    stepper.handle(Event::Load(crate::loader::load(src, None).unwrap()));
+    let mut player = Player::new(replay);
+    let mut played = vec![];
+    while !player.done() {
+        let frame = stepper.step(20000, player.next_frame());
+        played.push((frame.score, frame.pixels.to_vec()));
+    }
+    assert_eq!(played.len(), recorded.len());
+    assert!(played == recorded);
+    assert!(recorded[19].0 > 0);
+}
@@ -1,19 +1,25 @@
 #![allow(dead_code)]
 
-mod emulator;
-mod engine;
+pub mod embed;
+pub mod emulator;
+pub mod engine;
 mod libretro;
+pub mod loader;
+pub mod replay;
+pub mod transpile;
 mod v8_binding;
 
+pub use embed::{Emulator, GameEvent, InputState};
+
 use std::{
-    fs::File,
-    io::Read,
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     os::{raw::{c_char, c_uint, c_void}},    
-    ptr
+    ptr,
+    time::{Duration, Instant},
 };
 
-const FPS: u32 = 50;
+//frames the core runs per second, also the step of the headless runner
+pub const FPS: u32 = 50;
 const AUDIO_HZ: u32 = 16000;
 
 use emulator::game::KeyCode;
@@ -34,6 +40,9 @@ static mut AUDIO_CB: bindings::retro_audio_sample_t = None;
 //retroarch must not keep the log string
 static mut TMP_SPACE_FOR_LOG: Option<String> = None;
 
+//engine::ProjectMeta of the loaded game as json, for makecode_arcade_project_meta()
+static mut PROJECT_META: Option<std::ffi::CString> = None;
+
 macro_rules! static_cptr {
     ($s:expr) => {
         std::concat!($s, "\0").as_ptr() as *const i8
@@ -70,6 +79,24 @@ fn I(msg: &'static str) {
     }
 }
 
+//show a message on the frontend's screen for ~3 seconds
+fn show_message(msg: String) {
+    let msg = match std::ffi::CString::new(msg) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+    let mut retro_msg = bindings::retro_message {
+        msg: msg.as_ptr(),
+        frames: FPS * 3,
+    };
+    unsafe {
+        ENVIRON_CB.unwrap()(
+            bindings::RETRO_ENVIRONMENT_SET_MESSAGE,
+            ptr::addr_of_mut!(retro_msg) as *mut c_void,
+        );
+    }
+}
+
 #[no_mangle]
 extern "C" fn retro_api_version() -> u32 {
     bindings::RETRO_API_VERSION
@@ -113,9 +140,13 @@ extern "C" fn retro_set_next_tick_time(mut micro_sec: i64) {
                 WARMUP_COUNDDOWN -= 1;
                 micro_sec = 1000;
             }
-            match ENGINE.as_ref().unwrap().tick_tx.send(micro_sec as u64) {
-                Err(e) => I(tmp_c_str(format!("send tick to engine:{}", e))),
-                Ok(_) => {}
+            match CORE.as_ref() {
+                Some(Core::Threaded(engine)) => match engine.tick_tx.send(engine::Tick::Run(micro_sec as u64)) {
+                    Err(e) => I(tmp_c_str(format!("send tick to engine:{}", e))),
+                    Ok(_) => {}
+                },
+                Some(Core::Sync(..)) => NEXT_TICK_US = micro_sec as u64,
+                None => {}
             };
         }
     }
@@ -125,16 +156,9 @@ extern "C" fn retro_set_next_tick_time(mut micro_sec: i64) {
 extern "C" fn retro_unload_game() {
     D("retro_unload_game()");
     unsafe {
-        match ENGINE
-            .as_ref()
-            .unwrap()
-            .event_tx
-            .send(engine::Event::Unload)
-        {
-            Err(e) => I(tmp_c_str(format!("send unload to engine:{}", e))),
-            _ => {}
-        };
+        SAVE_STATE_SIZE = 0;
     }
+    send_event(engine::Event::Unload);
 }
 
 #[no_mangle]
@@ -217,9 +241,29 @@ extern "C" fn retro_load_game(info: *const bindings::retro_game_info) -> bool {
             ptr::addr_of_mut!(desc) as *mut c_void,
         );
 
+        //transpiled scripts are cached in the save directory
+        let mut save_dir: *const c_char = ptr::null();
+        let has_save_dir = ENVIRON_CB.unwrap()(
+            bindings::RETRO_ENVIRONMENT_GET_SAVE_DIRECTORY,
+            ptr::addr_of_mut!(save_dir) as *mut c_void,
+        );
+        let cache_dir = if has_save_dir && !save_dir.is_null() {
+            std::ffi::CStr::from_ptr(save_dir)
+                .to_str()
+                .ok()
+                .map(|dir| transpile::cache::dir_in(std::path::Path::new(dir)))
+        } else {
+            None
+        };
+
         match info.as_ref() {
             Some(p) => {
-                if !p.data.is_null() {
+                let path = if p.path.is_null() {
+                    None
+                } else {
+                    std::ffi::CStr::from_ptr(p.path).to_str().ok()
+                };
+                let loaded = if !p.data.is_null() && p.size > 0 {
                     let mut sl = std::slice::from_raw_parts(p.data as *const u8, p.size as usize);
                     if sl[sl.len() - 1] == 0 {
                         sl = &sl[..sl.len() - 1];
@@ -227,23 +271,37 @@ extern "C" fn retro_load_game(info: *const bindings::retro_game_info) -> bool {
                     D(tmp_c_str(format!("to load game data size: {}", sl.len())));
                     //let src = String::from(std::str::from_utf8(sl).unwrap());
                     //D(tmp_c_str(format!("to load game data: {}", src)));
-                    let prj = match loadPNG(sl) {
-                        Ok(p) => p,
-                        Err(msg) => {
-                            I(tmp_c_str(msg));
-                            return false;
-                        }
-                    };
-                    match ENGINE
-                        .as_ref()
-                        .unwrap()
-                        .event_tx
-                        .send(engine::Event::Load(prj))
-                    {
-                        Err(e) => I(tmp_c_str(format!("send load to engine:{}", e))),
-                        _ => {}
-                    };
+                    loader::load_cached(sl, path, cache_dir.as_deref())
+                } else if let Some(path) = path {
+                    //need_fullpath: we read the file, or the pxt project folder, ourselves
+                    D(tmp_c_str(format!("to load game from: {}", path)));
+                    loader::load_path_cached(std::path::Path::new(path), cache_dir.as_deref())
+                } else {
+                    return false;
+                };
+                let prj = match loaded {
+                    Ok(p) => p,
+                    Err(e) => {
+                        I(tmp_c_str(format!("load game failed:{}", e)));
+                        show_message(format!("Failed to load game: {}", e));
+                        return false;
+                    }
+                };
+                I(tmp_c_str(format!("loaded project {:?}", prj.meta)));
+                if !prj.meta.name.is_empty() {
+                    show_message(prj.meta.name.clone());
                 }
+                PROJECT_META = std::ffi::CString::new(prj.meta.to_json().to_string()).ok();
+                //measured again for this game
+                SAVE_STATE_SIZE = 0;
+                let seed = if core_option("makecode_arcade_deterministic").as_deref() == Some("enabled") {
+                    Some(0)
+                } else {
+                    None
+                };
+                send_event(engine::Event::Deterministic(seed));
+                send_event(engine::Event::Load(prj));
+                apply_debug_options();
             }
             None => {}
         };
@@ -252,167 +310,41 @@ extern "C" fn retro_load_game(info: *const bindings::retro_game_info) -> bool {
     true
 }
 
-fn loadPNG(png: &[u8]) -> Result<engine::Project, String> {
-    use png::ColorType::*;
-    let mut decoder = png::Decoder::new(&png[..]);
-    decoder.set_transformations(png::Transformations::normalize_to_color8());
-    let mut reader = match decoder.read_info() {
-        Ok(r) => r,
-        Err(err) => return Err(format!("decode png failed:{}", err)),
-    };
-    let mut img_data = vec![0; reader.output_buffer_size()];
-    println!("png {} x {}", reader.info().width, reader.info().height);
-    let info = match reader.next_frame(&mut img_data) {
-        Ok(i) => i,
-        Err(err) => return Err(format!("decode frame failed:{}", err)),
-    };
-    assert_eq!(info.color_type, Rgba);
-    let d = &img_data[..];
-    //https://github.com/microsoft/pxt/blob/master/pxtlib/util.ts decodeBlobAsync
-    let bpp = (d[0] & 1) | ((d[1] & 1) << 1) | ((d[2] & 1) << 2);
-    if bpp > 5 || bpp == 0 {
-        return Err(String::from("Invalid encoded PNG format"));
-    }
-    let decode = |mut ptr: usize, bpp: u8, tgr_len: usize| -> (usize, Vec<u8>) {
-        let mut shift = 0u8;
-        let mut i = 0usize;
-        let mut acc = 0u8;
-        let mask = (1 << bpp) - 1;
-        let mut tgr = vec![];
-        while i < tgr_len {
-            acc |= (d[ptr] & mask) << shift;
-            ptr += 1;
-            if ptr & 3 == 3 {
-                ptr += 1;
-            }
-            shift += bpp;
-            if shift >= 8 {
-                tgr.push(acc & 0xff);
-                i += 1;
-                acc = 0;
-                shift -= 8;
-            }
-        }
-        (ptr, tgr)
-    };
+//the frame step option has no meaning of its own, every change of it steps one frame
+static mut FRAME_STEP_OPTION: Option<String> = None;
 
-    let IMG_HEADER_SIZE = 36;
-    let (ptr, hd) = decode(4, bpp, IMG_HEADER_SIZE);
-    let (_, dhd, _) = unsafe { (&hd[..]).align_to::<u32>() };
-    if dhd[0] != 0x59347a7d {
-        return Err(String::from("Invalid magic in encoded PNG"));
-    }
-    let ret_len = dhd[1] as usize;
-    let added_lines = dhd[2];
-    let res = if added_lines > 0 {
-        let orig_size = (reader.info().height - added_lines) * reader.info().width;
-        let img_cap = (orig_size - 1) * 3 * bpp as u32 >> 3;
-        let (_, mut res) = decode(ptr, bpp, img_cap as usize - IMG_HEADER_SIZE);
-        let added = decode(orig_size as usize * 4, 8, ret_len - res.len());
-        res.extend(added.1);
-        res
-    } else {
-        let (_, res) = decode(ptr, bpp, ret_len);
-        res
-    };
-    let content = if res[0] == b'{' {
-        String::from_utf8(res).unwrap()
-    } else {
-        let mut decoded: Vec<u8> = vec![];
-        let mut sl = &res[..];
-        if lzma_rs::lzma_decompress(&mut sl, &mut decoded).is_err() {
-            return Err(String::from("lzma decompress failed"));
+//pause, frame step and game speed, from the core options
+fn apply_debug_options() {
+    send_event(engine::Event::Pause(core_option("makecode_arcade_paused").as_deref() == Some("enabled")));
+    let scale = core_option("makecode_arcade_time_scale")
+        .and_then(|v| v.trim_end_matches('x').parse::<f32>().ok())
+        .unwrap_or(1.0);
+    send_event(engine::Event::TimeScale(scale));
+    let step = core_option("makecode_arcade_frame_step");
+    unsafe {
+        if FRAME_STEP_OPTION.is_some() && step != FRAME_STEP_OPTION {
+            send_event(engine::Event::FrameStep);
         }
-        String::from_utf8(decoded).unwrap()
-    };
-    let obj: serde_json::Value = serde_json::from_str(content.as_str()).unwrap();
-    let source_json = obj
-        .as_object()
-        .unwrap()
-        .get("source")
-        .unwrap()
-        .as_str()
-        .unwrap();
-    let source_obj: serde_json::Value = serde_json::from_str(source_json).unwrap();
-
-    let mut prj = engine::Project {
-        sources: HashMap::new(),
-    };
-    prj.sources.insert(
-        String::from("main.ts"),
-        ts2js("main.ts",source_obj.get("main.ts").unwrap().as_str().unwrap()),
-    );
-
-    //println!("{}", source_obj.get("main.ts").unwrap().as_str().unwrap());
-
-    //std::fs::File::create(r"C:\Users\YDJiang\Downloads\ffmpeg-4.4-full_build\bin\bmp.raw").unwrap().write_all(&img_data[..]);
-    //fmpeg.exe -f rawvideo  -pixel_format rgba -y -s 512x424  -i bmp.rgba -frames:v 1 image.png
-    Ok(prj)
-}
-
-#[test]
-fn test_ts2js(){
-    let mut src = "".to_string();
-    let read_rst = File::open("test.ts").unwrap().read_to_string(&mut src);
-    assert!(read_rst.is_ok());
-    println!("final js:{}", ts2js("main.ts", src.as_str()));
-}
-
-struct WrapVecWr{
-    v:Vec<u8>,
-}
-
-impl std::io::Write for WrapVecWr {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        return self.v.write(buf)
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        std::io::Result::Ok(())
+        FRAME_STEP_OPTION = step;
     }
 }
 
-fn ts2js(name: &str, src: &str) -> String {
-    use swc::{config::Options, common::{errors::Handler, SourceMap,FileName, sync::Lrc}, ecmascript::ast::EsVersion};
-    use swc_ecma_parser::Syntax;
-    use swc_ecma_codegen::{text_writer::JsWriter, Emitter};
-    use swc_error_reporters::{PrettyEmitter, PrettyEmitterConfig, GraphicalReportHandler};
-
-    let cm: Lrc<SourceMap> = Default::default();
-    let fm = cm.new_source_file(FileName::Custom(name.into()), src.into());    
-    let mut opt:Options = Default::default();
-    opt.config.jsc.syntax = Some(Syntax::Typescript(Default::default()));
-    opt.config.jsc.target = Some(EsVersion::Es2022);
-    //let handler = Handler::with_emitter(can_emit_warnings, treat_err_as_bug, emitter)
-    //compiler.process_js(handler, program, opts)
-    let error_wr:Box<String>= Box::new(String::from(""));
-    let mut dest_wr:Vec<u8> = vec![];
-    {
-        let emitter = PrettyEmitter::new(
-            cm.clone(),
-            error_wr,
-            GraphicalReportHandler::default(),
-            PrettyEmitterConfig {
-                skip_filename: false
-            },
-        );
-        let handler = Handler::with_emitter(true, false, Box::new(emitter));
-        let _emitter = Emitter {
-            cfg: Default::default(),
-            cm:cm.clone(),
-            wr: Box::new(JsWriter::new(
-                cm.clone(),
-                "\n",
-                &mut dest_wr,
-                None,
-            )),
-            comments: None, //Some(comments),
-        };
-        let compiler = swc::Compiler::new(cm);
-        match compiler.process_js_file(fm, &handler, &opt) {
-            Ok(out)=>out.code,
-            Err(e)=>e.to_string(),
+//value of a core option declared in retro_set_environment
+fn core_option(key: &'static str) -> Option<String> {
+    let key = std::ffi::CString::new(key).unwrap();
+    let mut var = bindings::retro_variable {
+        key: key.as_ptr(),
+        value: ptr::null(),
+    };
+    unsafe {
+        if !ENVIRON_CB.unwrap()(
+            bindings::RETRO_ENVIRONMENT_GET_VARIABLE,
+            ptr::addr_of_mut!(var) as *mut c_void,
+        ) || var.value.is_null()
+        {
+            return None;
         }
+        std::ffi::CStr::from_ptr(var.value).to_str().ok().map(String::from)
     }
 }
 
@@ -421,6 +353,36 @@ extern "C" fn retro_set_environment(cb: bindings::retro_environment_t) {
     D("retro_set_environment()");
     unsafe {
         ENVIRON_CB = cb;
+        let mut vars = [
+            bindings::retro_variable {
+                key: static_cptr!("makecode_arcade_deterministic"),
+                value: static_cptr!("Deterministic mode (fixed seed and frame time); disabled|enabled"),
+            },
+            bindings::retro_variable {
+                key: static_cptr!("makecode_arcade_paused"),
+                value: static_cptr!("Pause the game (debug); disabled|enabled"),
+            },
+            bindings::retro_variable {
+                key: static_cptr!("makecode_arcade_frame_step"),
+                value: static_cptr!("Step one frame while paused, on every change (debug); 0|1"),
+            },
+            bindings::retro_variable {
+                key: static_cptr!("makecode_arcade_time_scale"),
+                value: static_cptr!("Game speed (debug); 1x|0.25x|0.5x|2x|4x"),
+            },
+            bindings::retro_variable {
+                key: static_cptr!("makecode_arcade_sync_step"),
+                value: static_cptr!("Run the game inside retro_run (one frame less latency, restart to apply); disabled|enabled"),
+            },
+            bindings::retro_variable {
+                key: ptr::null(),
+                value: ptr::null(),
+            },
+        ];
+        cb.unwrap()(
+            bindings::RETRO_ENVIRONMENT_SET_VARIABLES,
+            vars.as_mut_ptr() as *mut c_void,
+        );
         //TODO: makecode arcade saved .PNG file as ROM
         // let mut no_rom = true;
         // cb.unwrap()(
@@ -462,7 +424,30 @@ extern "C" fn retro_set_input_state(cb: bindings::retro_input_state_t) {
     }
 }
 
-static mut ENGINE: Option<engine::Engine> = None;
+//where the game runs: on the engine thread, or in retro_run itself with makecode_arcade_sync_step
+enum Core {
+    Threaded(engine::Engine),
+    //events wait for the next retro_run, as they wait for the next tick on the engine thread
+    Sync(engine::Stepper, Vec<engine::Event>),
+}
+
+static mut CORE: Option<Core> = None;
+//the time the next retro_run advances the game by in synchronous mode, from the frame time callback
+static mut NEXT_TICK_US: u64 = 1_000_000 / FPS as u64;
+
+fn send_event(evt: engine::Event) {
+    unsafe {
+        match CORE.as_mut() {
+            Some(Core::Threaded(engine)) => {
+                if let Err(e) = engine.event_tx.send(evt) {
+                    I(tmp_c_str(format!("send event to engine:{}", e)));
+                }
+            }
+            Some(Core::Sync(_, events)) => events.push(evt),
+            None => {}
+        }
+    }
+}
 
 #[no_mangle]
 extern "C" fn retro_init() {
@@ -477,7 +462,12 @@ extern "C" fn retro_init() {
         LOG_CB = log.log;
     }
     unsafe {
-        ENGINE = Some(engine::Engine::new(FPS, AUDIO_HZ));
+        CORE = Some(if core_option("makecode_arcade_sync_step").as_deref() == Some("enabled") {
+            I("game runs in retro_run");
+            Core::Sync(engine::Stepper::new(FPS), vec![])
+        } else {
+            Core::Threaded(engine::Engine::new(FPS, AUDIO_HZ))
+        });
     }
 }
 
@@ -488,9 +478,10 @@ extern "C" fn retro_get_system_info(info: *mut bindings::retro_system_info) {
         ptr::write_bytes(info, 0, 1);
         (*info).library_name = static_cptr!("makecode-arcade");
         (*info).library_version = static_cptr!("0.1.3");
-        (*info).need_fullpath = false;
+        //a pxt.json is loaded together with the other files of its folder
+        (*info).need_fullpath = true;
         (*info).block_extract = false;
-        (*info).valid_extensions = static_cptr!("png");
+        (*info).valid_extensions = static_cptr!("png|uf2|mkcd|json|ts|js|py");
     }
 }
 
@@ -519,26 +510,103 @@ extern "C" fn retro_get_system_av_info(info: *mut bindings::retro_system_av_info
 #[no_mangle]
 extern "C" fn retro_reset() {
     D("retro_reset");
-    unsafe {
-        match ENGINE
-            .as_ref()
-            .unwrap()
-            .event_tx
-            .send(engine::Event::Unload)
-        {
-            Err(e) => I(tmp_c_str(format!("send unload to engine:{}", e))),
-            _ => {}
+    send_event(engine::Event::Restart);
+}
+
+static mut TEN_CC: i32 = 20;
+
+//time from a key press sent to the game to the first frame showing it, logged every 5 seconds,
+//beside the time retro_run takes. compare makecode_arcade_sync_step disabled and enabled
+struct Latency {
+    sent: u64,
+    //(key events sent with this one, when)
+    pending: VecDeque<(u64, Instant)>,
+    keys: u32,
+    key_total: Duration,
+    key_max: Duration,
+    frames: u32,
+    run_total: Duration,
+}
+
+impl Latency {
+    const fn new() -> Self {
+        Latency {
+            sent: 0,
+            pending: VecDeque::new(),
+            keys: 0,
+            key_total: Duration::ZERO,
+            key_max: Duration::ZERO,
+            frames: 0,
+            run_total: Duration::ZERO,
+        }
+    }
+
+    fn key_sent(&mut self) {
+        self.sent += 1;
+        self.pending.push_back((self.sent, Instant::now()));
+    }
+
+    //a frame drawn after inputs key events went to the frontend, retro_run began at run_start
+    fn frame_shown(&mut self, inputs: u64, run_start: Instant) {
+        while let Some((_, at)) = self.pending.front().filter(|(n, _)| *n <= inputs) {
+            let waited = at.elapsed();
+            self.keys += 1;
+            self.key_total += waited;
+            self.key_max = self.key_max.max(waited);
+            self.pending.pop_front();
+        }
+        self.frames += 1;
+        self.run_total += run_start.elapsed();
+        if self.frames < FPS * 5 {
+            return;
+        }
+        let mode = match unsafe { CORE.as_ref() } {
+            Some(Core::Sync(..)) => "sync",
+            _ => "threaded",
         };
+        if self.keys > 0 {
+            I(tmp_c_str(format!(
+                "{} frame latency: key to screen avg {}us max {}us over {} keys, retro_run avg {}us",
+                mode,
+                (self.key_total / self.keys).as_micros(),
+                self.key_max.as_micros(),
+                self.keys,
+                (self.run_total / self.frames).as_micros()
+            )));
+        } else {
+            I(tmp_c_str(format!(
+                "{} frame latency: retro_run avg {}us",
+                mode,
+                (self.run_total / self.frames).as_micros()
+            )));
+        }
+        self.keys = 0;
+        self.key_total = Duration::ZERO;
+        self.key_max = Duration::ZERO;
+        self.frames = 0;
+        self.run_total = Duration::ZERO;
     }
 }
 
-static mut TEN_CC: i32 = 20;
+static mut LATENCY: Latency = Latency::new();
+
+fn present(frame: &engine::Frame) {
+    unsafe {
+        VIDEO_CB.unwrap()(
+            frame.pixels.as_ptr() as *const c_void,
+            emulator::game::BMP_WIDTH,
+            emulator::game::BMP_HEIGHT,
+            (emulator::game::BMP_WIDTH * 4) as bindings::size_t,
+        );
+    }
+}
 
 #[no_mangle]
 extern "C" fn retro_run() {
     // one step(aka. one frame) of game.
-    // 1. get the previous rendered frame buffer to this thread
-    // 2. move one step(20ms) forward in game engine working thread
+    // threaded: get the frame the engine thread rendered for the last tick, the keys below go with the next one
+    // sync: run the tick and the keys below here, then hand over the frame the stepper keeps
+    let run_start = Instant::now();
     unsafe {
         //VIDEO_CB.unwrap()(FRAME_BUFFER.as_ptr() as *const c_void, emulator::game::BMP_WIDTH, emulator::game::BMP_HEIGHT, emulator::game::BMP_WIDTH * 4);
         if TEN_CC > 0 {
@@ -551,6 +619,14 @@ extern "C" fn retro_run() {
     }
 
     unsafe {
+        let mut options_changed = false;
+        if ENVIRON_CB.unwrap()(
+            bindings::RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+            ptr::addr_of_mut!(options_changed) as *mut c_void,
+        ) && options_changed
+        {
+            apply_debug_options();
+        }
         INPUT_POLL_CB.unwrap()();
     }
 
@@ -575,41 +651,48 @@ extern "C" fn retro_run() {
             //TODO: key repeat
             if old_pressed && !new_pressed {
                 I(tmp_c_str(format!("UP {}", id.1)));
-                ENGINE
-                    .as_ref()
-                    .unwrap()
-                    .event_tx
-                    .send(engine::Event::KeyUp(id.1))
-                    .unwrap_or_else(|e| {
-                        I(tmp_c_str(format!("send key event to engine:{e}")));
-                    })
+                LATENCY.key_sent();
+                send_event(engine::Event::KeyUp(id.1));
             }
             if !old_pressed && new_pressed {
                 I(tmp_c_str(format!("DOWN {}", id.1)));
-                ENGINE
-                    .as_ref()
-                    .unwrap()
-                    .event_tx
-                    .send(engine::Event::KeyDown(id.1))
-                    .unwrap_or_else(|e| {
-                        I(tmp_c_str(format!("send key event to engine:{e}")));
-                    })
+                LATENCY.key_sent();
+                send_event(engine::Event::KeyDown(id.1));
             }
         }
     }
 
-    match unsafe { ENGINE.as_ref().unwrap() }.fb_rx.recv() {
-        Err(e) => I(tmp_c_str(format!("receive fb error:{}", e))),
-        Ok(fb) => {
-            let fbp = fb.as_ptr();
-            unsafe {
-                VIDEO_CB.unwrap()(
-                    fbp as *const c_void,
-                    emulator::game::BMP_WIDTH,
-                    emulator::game::BMP_HEIGHT,
-                    (emulator::game::BMP_WIDTH * 4) as bindings::size_t,
-                );
+    let inputs = match unsafe { CORE.as_mut() } {
+        Some(Core::Threaded(engine)) => match engine.fb_rx.recv() {
+            Err(e) => {
+                I(tmp_c_str(format!("receive fb error:{}", e)));
+                return;
+            }
+            Ok(fb) => {
+                present(&fb);
+                fb.inputs
             }
+        },
+        Some(Core::Sync(stepper, events)) => {
+            let fb = stepper.step(unsafe { NEXT_TICK_US }, events.drain(..));
+            present(fb);
+            fb.inputs
+        }
+        None => return,
+    };
+    unsafe {
+        LATENCY.frame_shown(inputs, run_start);
+    }
+}
+
+//for frontends embedding the core: name, editor, target version and dependencies of the
+//loaded game as a json object, null before a game is loaded. valid until the next load
+#[no_mangle]
+pub extern "C" fn makecode_arcade_project_meta() -> *const c_char {
+    unsafe {
+        match PROJECT_META.as_ref() {
+            Some(meta) => meta.as_ptr(),
+            None => ptr::null(),
         }
     }
 }
@@ -649,33 +732,93 @@ extern "C" fn retro_get_memory_size(_id: u32) -> usize {
     0
 }
 
+fn save_state() -> Option<Vec<u8>> {
+    match unsafe { CORE.as_mut() }? {
+        Core::Threaded(engine) => engine.save_state(),
+        Core::Sync(stepper, _) => Some(stepper.save_state()),
+    }
+}
+
+//save states grow with the game, the size given to the frontend is rounded up and never shrinks.
+//it is measured once after a load, then grows with the states retro_serialize makes, so asking
+//for it (every frame with rewind on) doesn't build a state each time
+static mut SAVE_STATE_SIZE: usize = 0;
+const SAVE_STATE_STEP: usize = 0x10000;
+
+fn grow_save_state_size(len: usize) -> usize {
+    unsafe {
+        //one step of headroom, so that a state saved right after still fits
+        let rounded = (len / SAVE_STATE_STEP + 2) * SAVE_STATE_STEP;
+        SAVE_STATE_SIZE = SAVE_STATE_SIZE.max(rounded);
+        SAVE_STATE_SIZE
+    }
+}
+
 #[no_mangle]
 extern "C" fn retro_serialize_size() -> usize {
     D("retro_serialize_size()");
-    0
+    if unsafe { SAVE_STATE_SIZE } > 0 {
+        return unsafe { SAVE_STATE_SIZE };
+    }
+    match save_state() {
+        Some(state) => grow_save_state_size(state.len()),
+        None => 0,
+    }
 }
 
 #[no_mangle]
-extern "C" fn retro_serialize(_data: *const c_void, _size: usize) -> bool {
+extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
     D("retro_serialize()");
-    false
+    if data.is_null() {
+        return false;
+    }
+    let state = match save_state() {
+        Some(state) => state,
+        None => return false,
+    };
+    //the next retro_serialize_size has room for it
+    grow_save_state_size(state.len());
+    if state.len() > size {
+        I(tmp_c_str(format!("save state of {} bytes does not fit in {}", state.len(), size)));
+        return false;
+    }
+    unsafe {
+        let out = std::slice::from_raw_parts_mut(data as *mut u8, size);
+        out[..state.len()].copy_from_slice(&state);
+        //the reader stops where the state ends, the padding is never read
+        out[state.len()..].fill(0);
+    }
+    true
 }
 
 #[no_mangle]
-extern "C" fn retro_unserialize(_data: *mut c_void, _size: usize) -> bool {
+extern "C" fn retro_unserialize(data: *mut c_void, size: usize) -> bool {
     D("retro_unserialize()");
-    false
+    if data.is_null() {
+        return false;
+    }
+    let state = unsafe { std::slice::from_raw_parts(data as *const u8, size) };
+    let restored = match unsafe { CORE.as_mut() } {
+        Some(Core::Threaded(engine)) => Some(engine.restore_state(state)),
+        Some(Core::Sync(stepper, _)) => Some(stepper.restore_state(state)),
+        None => None,
+    };
+    match restored {
+        Some(Ok(())) => true,
+        Some(Err(e)) => {
+            I(tmp_c_str(format!("restore save state:{}", e)));
+            false
+        }
+        None => false,
+    }
 }
 
 #[no_mangle]
 extern "C" fn retro_deinit() {
     D("retro_deinit()");
+    send_event(engine::Event::Exit);
     unsafe {
-        match ENGINE.as_ref().unwrap().event_tx.send(engine::Event::Exit) {
-            Err(e) => D(tmp_c_str(format!("send tick to engine:{}", e))),
-            Ok(_) => {}
-        };
-        ENGINE = None;
+        CORE = None;
         //LOG_CB = None; //log can work even deinit()-ed.
     }
 }
@@ -0,0 +1,54 @@
+//a local pxt project folder: pxt.json and the files it lists
+use std::{fs, path::Path};
+
+use crate::engine;
+
+use super::LoadError;
+
+pub fn load_dir(dir: &Path, cache_dir: Option<&Path>) -> Result<engine::Project, LoadError> {
+    let pxt_json = fs::read_to_string(dir.join("pxt.json"))?;
+    let pxt: serde_json::Value = serde_json::from_str(pxt_json.as_str())?;
+    let mut files = serde_json::Map::new();
+    if let Some(names) = pxt.get("files").and_then(|v| v.as_array()) {
+        for name in names.iter().filter_map(|v| v.as_str()) {
+            match fs::read_to_string(dir.join(name)) {
+                Ok(content) => {
+                    files.insert(String::from(name), serde_json::Value::String(content));
+                }
+                Err(e) => println!("skip {} of {}: {}", name, dir.display(), e),
+            }
+        }
+    }
+    files.insert(String::from("pxt.json"), serde_json::Value::String(pxt_json));
+    super::project_from_sources(&serde_json::Value::Object(files), cache_dir)
+}
+
+//a pxt.json (or its folder) loads the whole project, any other file is sniffed
+pub fn load_path(path: &Path) -> Result<engine::Project, LoadError> {
+    load_path_cached(path, None)
+}
+
+//load_path, with the transpiled scripts cached in cache_dir
+pub fn load_path_cached(path: &Path, cache_dir: Option<&Path>) -> Result<engine::Project, LoadError> {
+    if path.is_dir() {
+        return load_dir(path, cache_dir);
+    }
+    if path.file_name().map_or(false, |n| n == "pxt.json") {
+        return load_dir(path.parent().unwrap_or_else(|| Path::new(".")), cache_dir);
+    }
+    let data = fs::read(path)?;
+    super::load_cached(&data, path.to_str(), cache_dir)
+}
+
+#[test]
+fn test_load_dir() {
+    let dir = std::env::temp_dir().join(format!("makecode-arcade-dir-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("pxt.json"), r#"{"name":"dir","files":["README.md","main.ts","missing.ts"]}"#).unwrap();
+    fs::write(dir.join("README.md"), "# dir").unwrap();
+    fs::write(dir.join("main.ts"), "info.setScore(3)").unwrap();
+    let prj = load_path(&dir.join("pxt.json")).unwrap();
+    assert_eq!(prj.order, vec!["main.ts"]);
+    assert!(matches!(load_path(&dir.join("nothing.png")), Err(LoadError::Io(_))));
+    fs::remove_dir_all(&dir).unwrap();
+}
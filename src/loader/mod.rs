@@ -0,0 +1,627 @@
+//load makecode project files into engine::Project
+use std::{collections::HashMap, fmt, path::Path};
+
+use crate::{
+    emulator::{
+        asset::{self, AssetTable},
+        resource::Palette,
+    },
+    engine, transpile,
+};
+
+#[derive(Debug)]
+pub enum LoadError {
+    NotAPng(png::DecodingError),
+    UnsupportedColorType(png::ColorType),
+    UnsupportedBpp(u8),
+    BadMagic(u32),
+    //encoded data ends before the length its header claims
+    Truncated,
+    Lzma(lzma_rs::error::Error),
+    Utf8(std::string::FromUtf8Error),
+    BadJson(serde_json::Error),
+    //a required key of the project json, or a source file, is absent
+    MissingSource(String),
+    UnsupportedCompression(String),
+    Io(std::io::Error),
+    Encode(png::EncodingError),
+    //the cover image given to encodePNG can't hold even the header, (width, height)
+    CoverTooSmall(u32, u32),
+    Transpile(Vec<transpile::Diagnostic>),
+    //binary data that is neither png, uf2 nor lzma
+    UnknownFormat,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotAPng(e) => write!(f, "not a png file: {}", e),
+            LoadError::UnsupportedColorType(c) => write!(f, "unsupported png color type {:?}", c),
+            LoadError::UnsupportedBpp(bpp) => write!(f, "invalid encoded PNG format, {} bits per pixel", bpp),
+            LoadError::BadMagic(m) => write!(f, "invalid magic in encoded PNG: {:#x}", m),
+            LoadError::Truncated => write!(f, "encoded project data is truncated"),
+            LoadError::Lzma(e) => write!(f, "lzma decompress failed: {}", e),
+            LoadError::Utf8(e) => write!(f, "project is not valid utf8: {}", e),
+            LoadError::BadJson(e) => write!(f, "project json is invalid: {}", e),
+            LoadError::MissingSource(what) => write!(f, "project has no {}", what),
+            LoadError::UnsupportedCompression(c) => write!(f, "unsupported compression {}", c),
+            LoadError::Io(e) => write!(f, "read project failed: {}", e),
+            LoadError::Encode(e) => write!(f, "encode png failed: {}", e),
+            LoadError::CoverTooSmall(w, h) => write!(f, "cover image of {}x{} is too small to hold a project", w, h),
+            LoadError::Transpile(diagnostics) => {
+                write!(f, "typescript errors:")?;
+                for d in diagnostics {
+                    write!(f, "\n{}", d)?;
+                }
+                Ok(())
+            }
+            LoadError::UnknownFormat => write!(f, "unknown project format"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<png::DecodingError> for LoadError {
+    fn from(e: png::DecodingError) -> Self {
+        LoadError::NotAPng(e)
+    }
+}
+
+impl From<png::EncodingError> for LoadError {
+    fn from(e: png::EncodingError) -> Self {
+        LoadError::Encode(e)
+    }
+}
+
+impl From<lzma_rs::error::Error> for LoadError {
+    fn from(e: lzma_rs::error::Error) -> Self {
+        LoadError::Lzma(e)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for LoadError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        LoadError::Utf8(e)
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> Self {
+        LoadError::BadJson(e)
+    }
+}
+
+mod dir;
+mod png_blob;
+mod uf2;
+
+pub use dir::{load_dir, load_path, load_path_cached};
+pub use png_blob::{encodePNG, loadPNG};
+pub use uf2::loadUF2;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    //project encoded in a png by makecode "save to computer"
+    Png,
+    //makecode hardware download with the source in trailing blocks
+    Uf2,
+    //lzma compressed project json, the .mkcd file
+    Lzma,
+    //project json, either {"meta", "source"} or the pxt file map itself
+    Json,
+    JavaScript,
+    TypeScript,
+    //makecode python, main.py
+    Python,
+    //binary data none of the above
+    Unknown,
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+//lzma header: properties byte, 4 bytes dictionary size, 8 bytes uncompressed size
+const LZMA_HEADER_SIZE: usize = 13;
+//properties byte is (pb * 5 + lp) * 9 + lc, with lc <= 8, lp <= 4, pb <= 4
+const LZMA_MAX_PROPERTIES: u8 = (4 * 5 + 4) * 9 + 8;
+
+fn is_lzma(data: &[u8]) -> bool {
+    data.len() >= LZMA_HEADER_SIZE && data[0] <= LZMA_MAX_PROPERTIES
+}
+
+//guess the format from the content, name_hint (usually the file path) only separates js, py and ts
+pub fn sniff(data: &[u8], name_hint: Option<&str>) -> Format {
+    if data.starts_with(PNG_SIGNATURE) {
+        return Format::Png;
+    }
+    if uf2::is_uf2(data) {
+        return Format::Uf2;
+    }
+    let text = data.strip_prefix(UTF8_BOM).unwrap_or(data);
+    let text = match std::str::from_utf8(text) {
+        Ok(t) => t,
+        //lzma stream starts with its properties byte, 0x5d for the settings pxt uses, it's never text
+        Err(_) if is_lzma(data) => return Format::Lzma,
+        Err(_) => return Format::Unknown,
+    };
+    if text.trim_start().starts_with('{') {
+        return Format::Json;
+    }
+    match name_hint {
+        Some(name) if name.to_lowercase().ends_with(".js") => Format::JavaScript,
+        Some(name) if name.to_lowercase().ends_with(".py") => Format::Python,
+        _ => Format::TypeScript,
+    }
+}
+
+pub fn load(data: &[u8], name_hint: Option<&str>) -> Result<engine::Project, LoadError> {
+    load_cached(data, name_hint, None)
+}
+
+//load, with the transpiled scripts cached in cache_dir
+pub fn load_cached(data: &[u8], name_hint: Option<&str>, cache_dir: Option<&Path>) -> Result<engine::Project, LoadError> {
+    let format = sniff(data, name_hint);
+    let text = || String::from_utf8(data.strip_prefix(UTF8_BOM).unwrap_or(data).to_vec());
+    match format {
+        Format::Png => loadPNG(data, cache_dir),
+        Format::Uf2 => loadUF2(data, cache_dir),
+        Format::Lzma | Format::Json => project_from_blob(data, cache_dir),
+        Format::Unknown => Err(LoadError::UnknownFormat),
+        Format::TypeScript => project_from_sources(&serde_json::json!({ "main.ts": text()? }), cache_dir),
+        Format::Python => project_from_sources(&serde_json::json!({ "main.py": text()? }), cache_dir),
+        Format::JavaScript => {
+            let mut prj = empty_project();
+            let js = text()?;
+            prj.files.insert(String::from("main.js"), js.clone());
+            prj.sources.insert(String::from("main.js"), js);
+            prj.order.push(String::from("main.js"));
+            Ok(prj)
+        }
+    }
+}
+
+//the payload of a .png or .mkcd: project json, raw or lzma compressed
+pub fn project_from_blob(blob: &[u8], cache_dir: Option<&Path>) -> Result<engine::Project, LoadError> {
+    let blob = blob.strip_prefix(UTF8_BOM).unwrap_or(blob);
+    if blob.is_empty() {
+        return Err(LoadError::Truncated);
+    }
+    let content = if blob.iter().find(|c| !c.is_ascii_whitespace()) == Some(&b'{') {
+        String::from_utf8(blob.to_vec())?
+    } else {
+        let mut decoded: Vec<u8> = vec![];
+        let mut sl = &blob[..];
+        lzma_rs::lzma_decompress(&mut sl, &mut decoded)?;
+        String::from_utf8(decoded)?
+    };
+    let obj: serde_json::Value = serde_json::from_str(content.as_str())?;
+    project_from_json(&obj, cache_dir)
+}
+
+pub fn project_from_json(obj: &serde_json::Value, cache_dir: Option<&Path>) -> Result<engine::Project, LoadError> {
+    //a bare file map, e.g. the "source" of an exported project
+    if ["pxt.json", "main.ts", "main.blocks", "main.py"].iter().any(|name| obj.get(name).is_some()) {
+        return project_from_sources(obj, cache_dir);
+    }
+    let mut prj = match obj.get("source") {
+        Some(serde_json::Value::String(source_json)) => {
+            let source_obj: serde_json::Value = serde_json::from_str(source_json)?;
+            project_from_sources(&source_obj, cache_dir)?
+        }
+        Some(source_obj @ serde_json::Value::Object(_)) => project_from_sources(source_obj, cache_dir)?,
+        _ => return Err(LoadError::MissingSource(String::from("\"source\""))),
+    };
+    //"meta" of png and .mkcd files, "header" of exported projects
+    for header in [obj.get("meta"), obj.get("header")].into_iter().flatten() {
+        apply_header(&mut prj.meta, header);
+    }
+    Ok(prj)
+}
+
+//what makecode "save to computer" puts in a png: {"meta", "source"}, lzma compressed
+pub fn project_to_blob(files: &serde_json::Map<String, serde_json::Value>) -> Result<Vec<u8>, LoadError> {
+    let name = files
+        .get("pxt.json")
+        .and_then(|v| v.as_str())
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+        .and_then(|pxt| pxt.get("name").and_then(|n| n.as_str()).map(String::from))
+        .unwrap_or_else(|| String::from("Untitled"));
+    let editor = project_editor(|name| files.contains_key(name));
+    let obj = serde_json::json!({
+        "meta": { "name": name, "editor": editor },
+        "source": serde_json::Value::Object(files.clone()).to_string(),
+    });
+    let mut blob = vec![];
+    lzma_rs::lzma_compress(&mut obj.to_string().as_bytes(), &mut blob)?;
+    Ok(blob)
+}
+
+//a png makecode can import, with the project hidden in cover (or a plain image)
+pub fn encode_project(prj: &engine::Project, cover: Option<&[u8]>) -> Result<Vec<u8>, LoadError> {
+    let files = prj
+        .files
+        .iter()
+        .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+        .collect();
+    encodePNG(&project_to_blob(&files)?, cover)
+}
+
+fn empty_project() -> engine::Project {
+    engine::Project {
+        files: HashMap::new(),
+        sources: HashMap::new(),
+        order: vec![],
+        source_maps: HashMap::new(),
+        assets: AssetTable::default(),
+        palette: Palette::default(),
+        meta: engine::ProjectMeta::default(),
+    }
+}
+
+//build the project from the pxt file map, e.g. {"main.ts":"...", "pxt.json":"..."}.
+//the transpiled scripts are cached in cache_dir if given
+pub fn project_from_sources(source_obj: &serde_json::Value, cache_dir: Option<&Path>) -> Result<engine::Project, LoadError> {
+    let mut prj = empty_project();
+    if let Some(files) = source_obj.as_object() {
+        for (name, content) in files.iter() {
+            if let Some(content) = content.as_str() {
+                prj.files.insert(name.clone(), String::from(content));
+            }
+        }
+    }
+    if let Some(palette) = project_palette(source_obj) {
+        prj.palette = palette;
+    }
+    prj.meta = project_meta(source_obj);
+    for (name, content) in prj.files.iter().filter(|(name, _)| name.ends_with(".jres")) {
+        if let Err(e) = prj.assets.add_jres(content) {
+            println!("skip assets of {}: {}", name, e);
+        }
+    }
+    let mut diagnostics = vec![];
+    //python and blocks projects may come without a usable main.ts, makecode would regenerate it
+    //from main.py or main.blocks
+    let main_ts = source_obj.get("main.ts").and_then(|v| v.as_str());
+    let main_py = source_obj.get("main.py").and_then(|v| v.as_str()).filter(|py| !py.trim().is_empty());
+    let main_blocks = source_obj.get("main.blocks").and_then(|v| v.as_str());
+    let (mut main_from_py, mut main_from_blocks) = (None, None);
+    if main_ts.map_or(true, |ts| ts.trim().is_empty()) {
+        if let Some(py) = main_py {
+            match transpile::py2js("main.py", py) {
+                Ok(js) => main_from_py = Some(js),
+                Err(d) => diagnostics.extend(d),
+            }
+        } else if let Some(xml) = main_blocks {
+            match transpile::blocks2ts(xml) {
+                Ok(ts) => main_from_blocks = Some(ts),
+                Err(d) => diagnostics.extend(d),
+            }
+        }
+    }
+    let mut names = project_ts_files(source_obj);
+    if (main_py.is_some() || main_from_blocks.is_some()) && !names.iter().any(|n| n == "main.ts") {
+        names.push(String::from("main.ts"));
+    }
+    for name in names {
+        //python is javascript already, it runs in main.ts's place
+        if name == "main.ts" && main_py.is_some() && main_ts.map_or(true, |ts| ts.trim().is_empty()) {
+            if let Some(js) = main_from_py.take() {
+                prj.sources.insert(String::from("main.py"), js);
+                prj.order.push(String::from("main.py"));
+            }
+            continue;
+        }
+        let src = match (name.as_str(), main_from_blocks.as_deref()) {
+            ("main.ts", Some(ts)) => ts,
+            _ => match source_obj.get(&name).and_then(|v| v.as_str()) {
+                Some(s) => s,
+                None => {
+                    println!("{} listed in pxt.json but not found in project", name);
+                    continue;
+                }
+            },
+        };
+        let filled;
+        let src = if name.ends_with(".g.ts") && !prj.assets.is_empty() {
+            filled = fill_jres_literals(src, &prj.assets);
+            filled.as_str()
+        } else {
+            src
+        };
+        match transpile::cached_ts2js(name.as_str(), src, cache_dir) {
+            Ok(out) => {
+                prj.sources.insert(name.clone(), out.code);
+                if let Some(map) = out.source_map {
+                    prj.source_maps.insert(name.clone(), map);
+                }
+            }
+            Err(d) => diagnostics.extend(d),
+        }
+        prj.order.push(name);
+    }
+    if !diagnostics.is_empty() {
+        return Err(LoadError::Transpile(diagnostics));
+    }
+    if prj.order.is_empty() {
+        return Err(LoadError::MissingSource(String::from("main.ts")));
+    }
+    Ok(prj)
+}
+
+//the generated .g.ts declare assets as `export const tile1 = image.ofBuffer(hex``);` inside their namespace,
+//the pxt compiler fills the empty hex`` with the jres data of namespace.name
+fn fill_jres_literals(src: &str, assets: &AssetTable) -> String {
+    let mut namespace = "";
+    let mut out = String::with_capacity(src.len());
+    for line in src.split_inclusive('\n') {
+        let code = line.trim_start();
+        if let Some(ns) = code.strip_prefix("namespace ") {
+            namespace = ns.trim_end().trim_end_matches('{').trim();
+        }
+        let name = code
+            .strip_prefix("export ")
+            .unwrap_or(code)
+            .strip_prefix("const ")
+            .and_then(|rest| rest.split(|c: char| c == '=' || c == ':' || c.is_whitespace()).next());
+        let buffer = name.and_then(|name| assets.buffer(&format!("{}.{}", namespace, name)));
+        match buffer {
+            Some(buf) if line.contains("hex``") => {
+                out.push_str(&line.replacen("hex``", &format!("hex`{}`", asset::to_hex(buf)), 1))
+            }
+            _ => out.push_str(line),
+        }
+    }
+    out
+}
+
+//pxt.json "palette": ["#000000", "#ffffff", ...], 16 colors set in the project settings
+fn project_palette(source_obj: &serde_json::Value) -> Option<Palette> {
+    let pxt_json: serde_json::Value = serde_json::from_str(source_obj.get("pxt.json")?.as_str()?).ok()?;
+    let colors = pxt_json.get("palette")?.as_array()?;
+    let palette = Palette::from_hex_colors(colors.iter().filter_map(|c| c.as_str()));
+    if palette.is_none() {
+        println!("ignore invalid palette {:?}", colors);
+    }
+    palette
+}
+
+//python projects keep a main.blocks too
+fn project_editor(has_file: impl Fn(&str) -> bool) -> &'static str {
+    if has_file("main.py") {
+        "pyprj"
+    } else if has_file("main.blocks") {
+        "blocksprj"
+    } else {
+        "tsprj"
+    }
+}
+
+fn project_meta(source_obj: &serde_json::Value) -> engine::ProjectMeta {
+    let mut meta = engine::ProjectMeta {
+        editor: String::from(project_editor(|name| source_obj.get(name).is_some())),
+        ..Default::default()
+    };
+    let pxt_json: serde_json::Value = match source_obj.get("pxt.json").and_then(|v| v.as_str()).map(serde_json::from_str) {
+        Some(Ok(v)) => v,
+        _ => return meta,
+    };
+    let text = |v: Option<&serde_json::Value>| v.and_then(|v| v.as_str()).map(String::from);
+    meta.name = text(pxt_json.get("name")).unwrap_or_default();
+    meta.description = text(pxt_json.get("description")).unwrap_or_default();
+    meta.target_version = text(pxt_json.pointer("/targetVersions/target")).unwrap_or_default();
+    meta.target = text(pxt_json.pointer("/targetVersions/targetId")).unwrap_or_default();
+    if let Some(deps) = pxt_json.get("dependencies").and_then(|v| v.as_object()) {
+        meta.dependencies = deps.iter().filter_map(|(k, v)| Some((k.clone(), String::from(v.as_str()?)))).collect();
+    }
+    meta
+}
+
+//the project header makecode saves beside the files, whatever it has wins over pxt.json
+pub(crate) fn apply_header(meta: &mut engine::ProjectMeta, header: &serde_json::Value) {
+    let text = |key: &str| header.pointer(key).and_then(|v| v.as_str()).filter(|s| !s.is_empty()).map(String::from);
+    if let Some(name) = text("/name") {
+        meta.name = name;
+    }
+    if let Some(editor) = text("/editor") {
+        meta.editor = editor;
+    }
+    if let Some(target) = text("/target") {
+        meta.target = target;
+    }
+    //"meta" of a png has versions.target, a header has targetVersion
+    if let Some(version) = text("/targetVersion").or_else(|| text("/versions/target")) {
+        meta.target_version = version;
+    }
+}
+
+//.ts files of pxt.json "files" in the order they run: the generated .g.ts first and main.ts last, as pxt
+//does, so the top level code of main.ts finds the assets. the others in between, in "files" order
+fn project_ts_files(source_obj: &serde_json::Value) -> Vec<String> {
+    let pxt_json: Option<serde_json::Value> = source_obj
+        .get("pxt.json")
+        .and_then(|v| v.as_str())
+        .and_then(|s| serde_json::from_str(s).ok());
+    let mut files: Vec<String> = match pxt_json.as_ref().and_then(|v| v.get("files")).and_then(|v| v.as_array()) {
+        Some(files) => files
+            .iter()
+            .filter_map(|f| f.as_str())
+            .filter(|f| f.ends_with(".ts") && !f.ends_with(".d.ts"))
+            .map(String::from)
+            .collect(),
+        None => vec![],
+    };
+    if files.is_empty() {
+        return vec![String::from("main.ts")];
+    }
+    //stable, the files of each group keep their order
+    files.sort_by_key(|f| {
+        if f.ends_with(".g.ts") {
+            0
+        } else if f == "main.ts" {
+            2
+        } else {
+            1
+        }
+    });
+    files
+}
+
+#[test]
+fn test_project_ts_files() {
+    let source_obj = serde_json::json!({
+        "pxt.json": r#"{"files":["main.blocks","main.ts","README.md","images.g.jres","images.g.ts","enums.d.ts","player.ts","enemy.ts","tilemap.g.ts"]}"#,
+    });
+    assert_eq!(
+        project_ts_files(&source_obj),
+        vec!["images.g.ts", "tilemap.g.ts", "player.ts", "enemy.ts", "main.ts"]
+    );
+    assert_eq!(project_ts_files(&serde_json::json!({})), vec!["main.ts"]);
+}
+
+#[test]
+fn test_project_palette() {
+    let mut colors: Vec<String> = (0..16).map(|i| format!("#{:02x}0000", i)).collect();
+    let source_obj = serde_json::json!({
+        "pxt.json": serde_json::json!({ "files": ["main.ts"], "palette": colors }).to_string(),
+        "main.ts": "",
+    });
+    let prj = project_from_sources(&source_obj, None).unwrap();
+    assert_eq!(prj.palette.color(2), 0x020000);
+
+    colors.pop();
+    let source_obj = serde_json::json!({ "pxt.json": serde_json::json!({ "palette": colors }).to_string() });
+    assert_eq!(project_palette(&source_obj), None);
+    assert_eq!(project_palette(&serde_json::json!({})), None);
+}
+
+#[test]
+fn test_sniff() {
+    assert_eq!(sniff(b"\x89PNG\r\n\x1a\n....", None), Format::Png);
+    assert_eq!(sniff(b"\x5d\x00\x00\x80\x00\xff\xff\xff\xff\xff\xff\xff\xff\x00", None), Format::Lzma);
+    assert_eq!(sniff(b"\xef\xbb\xbf  {\"main.ts\":\"\"}", None), Format::Json);
+    assert_eq!(sniff(b"let a = 1", Some("/games/a.js")), Format::JavaScript);
+    assert_eq!(sniff(b"let a: number = 1", Some("/games/main.ts")), Format::TypeScript);
+    assert_eq!(sniff(b"let a: number = 1", None), Format::TypeScript);
+    assert_eq!(sniff(b"\xff\xfe\x00\x01garbage with a high first byte", None), Format::Unknown);
+    assert_eq!(sniff(b"\x5d\x80", None), Format::Unknown);
+}
+
+#[test]
+fn test_load_errors() {
+    assert!(matches!(
+        project_from_sources(&serde_json::json!({"README.md": ""}), None),
+        Err(LoadError::MissingSource(_))
+    ));
+    assert!(matches!(load(b"{\"meta\":{}}", None), Err(LoadError::MissingSource(_))));
+    assert!(matches!(load(b"\xff\xd8\xff\xe0 a jpeg, say", None), Err(LoadError::UnknownFormat)));
+}
+
+#[test]
+fn test_load_mkcd() {
+    let source = serde_json::json!({ "main.ts": "info.setScore(1)" }).to_string();
+    let project = serde_json::json!({ "meta": { "name": "t" }, "source": source }).to_string();
+    let mut mkcd = vec![];
+    lzma_rs::lzma_compress(&mut project.as_bytes(), &mut mkcd).unwrap();
+    let prj = load(&mkcd, Some("t.mkcd")).unwrap();
+    assert_eq!(prj.order, vec!["main.ts"]);
+    assert_eq!(prj.meta.name, "t");
+}
+
+#[test]
+fn test_project_meta() {
+    let source = serde_json::json!({
+        "pxt.json": r#"{"name":"Untitled","description":"d","dependencies":{"device":"*"},"targetVersions":{"target":"1.8.0","targetId":"arcade"}}"#,
+        "main.blocks": "<xml/>",
+        "main.ts": "info.setScore(1)",
+    });
+    let meta = project_meta(&source);
+    assert_eq!(meta.name, "Untitled");
+    assert_eq!(meta.description, "d");
+    assert_eq!(meta.editor, "blocksprj");
+    assert_eq!((meta.target.as_str(), meta.target_version.as_str()), ("arcade", "1.8.0"));
+    assert_eq!(meta.dependencies.get("device").map(|v| v.as_str()), Some("*"));
+
+    let obj = serde_json::json!({
+        "meta": { "name": "Space Race", "editor": "tsprj", "versions": { "target": "1.8.21" } },
+        "source": source.to_string(),
+    });
+    let prj = project_from_json(&obj, None).unwrap();
+    assert_eq!(prj.meta.name, "Space Race");
+    assert_eq!(prj.meta.editor, "tsprj");
+    assert_eq!(prj.meta.target_version, "1.8.21");
+    assert_eq!(prj.meta.to_json()["dependencies"]["device"], "*");
+}
+
+#[test]
+fn test_png_round_trip() {
+    let mut files = serde_json::Map::new();
+    files.insert(String::from("main.ts"), serde_json::Value::from("info.setScore(2)"));
+    files.insert(String::from("pxt.json"), serde_json::Value::from(r#"{"name":"round trip","files":["main.ts"]}"#));
+    let png = encodePNG(&project_to_blob(&files).unwrap(), None).unwrap();
+    assert_eq!(sniff(&png, None), Format::Png);
+    let prj = load(&png, None).unwrap();
+    assert_eq!(prj.files.get("main.ts").unwrap(), "info.setScore(2)");
+    let again = encode_project(&prj, Some(&png)).unwrap();
+    assert_eq!(load(&again, None).unwrap().files, prj.files);
+}
+
+#[test]
+fn test_fill_jres_literals() {
+    let jres = serde_json::json!({
+        "*": { "mimeType": "image/x-mkcd-f4", "dataEncoding": "base64", "namespace": "myTiles" },
+        "tile1": "hwQBAAEAAAAHAAAA",
+    });
+    let mut assets = AssetTable::default();
+    assets.add_jres(&jres.to_string()).unwrap();
+    let src = "namespace myTiles {\n    //% fixedInstance jres blockIdentity=images._tile\n    export const tile1 = image.ofBuffer(hex``);\n    export const tile2 = image.ofBuffer(hex``);\n}\n";
+    let filled = fill_jres_literals(src, &assets);
+    assert!(filled.contains("tile1 = image.ofBuffer(hex`8704010001000000070000"));
+    assert!(filled.contains("tile2 = image.ofBuffer(hex``)"));
+}
+
+#[test]
+fn test_load_blocks_only() {
+    let xml = r#"<xml><block type="pxt-on-start"><statement name="HANDLER"><block type="hudSetScore"><value name="value"><shadow type="math_number"><field name="NUM">4</field></shadow></value></block></statement></block></xml>"#;
+    let source_obj = serde_json::json!({
+        "pxt.json": r#"{"files":["main.blocks","main.ts"]}"#,
+        "main.blocks": xml,
+        "main.ts": " ",
+    });
+    let prj = project_from_sources(&source_obj, None).unwrap();
+    assert_eq!(prj.order, vec!["main.ts"]);
+    assert!(prj.sources["main.ts"].contains("info.setScore(4)"));
+
+    let source_obj = serde_json::json!({ "main.blocks": "<xml><block type=\"pxt-on-start\"><statement name=\"HANDLER\"><block type=\"nope\"/></statement></block></xml>" });
+    assert!(matches!(project_from_sources(&source_obj, None), Err(LoadError::Transpile(_))));
+}
+
+#[test]
+fn test_load_python() {
+    let source_obj = serde_json::json!({
+        "pxt.json": r#"{"files":["main.blocks","main.ts","main.py","player.ts"]}"#,
+        "main.ts": "",
+        "main.py": "info.set_score(4)\n",
+        "player.ts": "info.changeScoreBy(1)",
+    });
+    let prj = project_from_sources(&source_obj, None).unwrap();
+    assert_eq!(prj.order, vec!["player.ts", "main.py"]);
+    assert_eq!(prj.sources["main.py"], "info.setScore(4);\n");
+
+    let prj = load(b"def f():\n    return 1\n", Some("game.py")).unwrap();
+    assert_eq!(prj.order, vec!["main.py"]);
+    assert!(matches!(load(b"x = (", Some("main.py")), Err(LoadError::Transpile(_))));
+}
+
+#[test]
+fn test_load_javascript() {
+    let prj = load(b"game.over(true)", Some("main.js")).unwrap();
+    let scripts: Vec<_> = prj.scripts().collect();
+    assert_eq!(scripts.len(), 1);
+    assert_eq!(scripts[0].1, "game.over(true)");
+}
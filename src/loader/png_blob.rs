@@ -0,0 +1,229 @@
+//https://github.com/microsoft/pxt/blob/master/pxtlib/util.ts decodeBlobAsync, encodeBlobAsync
+use std::path::Path;
+
+use crate::engine;
+
+use super::LoadError;
+
+const IMG_HEADER_SIZE: usize = 36;
+const IMG_MAGIC: u32 = 0x59347a7d;
+const MAX_BPP: u32 = 4;
+
+//png makecode shows in its import dialog when no cover image is given
+const COVER_WIDTH: u32 = 160;
+const COVER_HEIGHT: u32 = 120;
+
+pub fn loadPNG(png: &[u8], cache_dir: Option<&Path>) -> Result<engine::Project, LoadError> {
+    let res = decode_blob(png)?;
+    super::project_from_blob(&res, cache_dir)
+}
+
+//channel index of the next pixel byte to carry data, alpha is never used
+fn next_ptr(ptr: usize) -> usize {
+    if (ptr + 1) & 3 == 3 {
+        ptr + 2
+    } else {
+        ptr + 1
+    }
+}
+
+//the bytes hidden in the low bits of the rgb channels
+pub fn decode_blob(png: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let (width, height, img_data) = read_rgba(png)?;
+    let d = &img_data[..];
+    if d.len() < 4 {
+        return Err(LoadError::Truncated);
+    }
+    let bpp = (d[0] & 1) | ((d[1] & 1) << 1) | ((d[2] & 1) << 2);
+    if bpp > 5 || bpp == 0 {
+        return Err(LoadError::UnsupportedBpp(bpp));
+    }
+    let decode = |mut ptr: usize, bpp: u32, tgr_len: usize| -> Result<(usize, Vec<u8>), LoadError> {
+        let mut shift = 0u32;
+        let mut acc = 0u32;
+        let mask = (1u32 << bpp) - 1;
+        let mut tgr = Vec::with_capacity(tgr_len);
+        while tgr.len() < tgr_len {
+            acc |= (*d.get(ptr).ok_or(LoadError::Truncated)? as u32 & mask) << shift;
+            ptr = next_ptr(ptr);
+            shift += bpp;
+            if shift >= 8 {
+                tgr.push((acc & 0xff) as u8);
+                //bits of the next byte when bpp doesn't divide 8
+                acc >>= 8;
+                shift -= 8;
+            }
+        }
+        Ok((ptr, tgr))
+    };
+
+    let bpp = bpp as u32;
+    let (ptr, hd) = decode(4, bpp, IMG_HEADER_SIZE)?;
+    let dhd: Vec<u32> = hd
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    if dhd[0] != IMG_MAGIC {
+        return Err(LoadError::BadMagic(dhd[0]));
+    }
+    let ret_len = dhd[1] as usize;
+    let added_lines = dhd[2];
+    let res = if added_lines > 0 {
+        let orig_size = height.checked_sub(added_lines).ok_or(LoadError::Truncated)? * width;
+        let img_cap = ((orig_size.saturating_sub(1) * 3 * bpp) >> 3) as usize;
+        let first_len = img_cap.checked_sub(IMG_HEADER_SIZE).ok_or(LoadError::Truncated)?;
+        let (_, mut res) = decode(ptr, bpp, first_len.min(ret_len))?;
+        let added = decode(orig_size as usize * 4, 8, ret_len - res.len())?;
+        res.extend(added.1);
+        res
+    } else {
+        let (_, res) = decode(ptr, bpp, ret_len)?;
+        res
+    };
+
+    //std::fs::File::create(r"C:\Users\YDJiang\Downloads\ffmpeg-4.4-full_build\bin\bmp.raw").unwrap().write_all(&img_data[..]);
+    //fmpeg.exe -f rawvideo  -pixel_format rgba -y -s 512x424  -i bmp.rgba -frames:v 1 image.png
+    Ok(res)
+}
+
+fn read_rgba(png: &[u8]) -> Result<(u32, u32, Vec<u8>), LoadError> {
+    let mut decoder = png::Decoder::new(png);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut img_data = vec![0; reader.output_buffer_size()];
+    println!("png {} x {}", reader.info().width, reader.info().height);
+    let info = reader.next_frame(&mut img_data)?;
+    img_data.truncate(info.buffer_size());
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => img_data,
+        png::ColorType::Rgb => img_data
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xff])
+            .collect(),
+        c => return Err(LoadError::UnsupportedColorType(c)),
+    };
+    Ok((info.width, info.height, rgba))
+}
+
+//store blob in the low bits of each rgb channel, returns the next free channel index
+fn encode(img: &mut [u8], mut ptr: usize, bpp: u32, data: &[u8]) -> usize {
+    let mask = (1u32 << bpp) - 1;
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut bytes = data.iter();
+    loop {
+        if bits < bpp {
+            match bytes.next() {
+                Some(b) => {
+                    acc |= (*b as u32) << bits;
+                    bits += 8;
+                }
+                None if bits == 0 => break,
+                //pad the last chunk with zero bits
+                None => bits = bpp,
+            }
+        }
+        img[ptr] = (img[ptr] & !(mask as u8)) | (acc & mask) as u8;
+        acc >>= bpp;
+        bits -= bpp;
+        ptr = next_ptr(ptr);
+    }
+    ptr
+}
+
+//the inverse of decode_blob: hide blob in cover (a png, or a plain 160x120 image), returns the new png
+pub fn encodePNG(blob: &[u8], cover: Option<&[u8]>) -> Result<Vec<u8>, LoadError> {
+    let (width, mut height, mut img) = match cover {
+        Some(png) => read_rgba(png)?,
+        None => (
+            COVER_WIDTH,
+            COVER_HEIGHT,
+            [0x30u8, 0x30, 0x30, 0xff].repeat((COVER_WIDTH * COVER_HEIGHT) as usize),
+        ),
+    };
+    if width == 0 || height == 0 {
+        return Err(LoadError::Truncated);
+    }
+    let needed = IMG_HEADER_SIZE + blob.len();
+    let usable = ((width * height) as usize - 1) * 3;
+    let mut bpp = 1;
+    while bpp < MAX_BPP && usable * (bpp as usize) < needed * 8 {
+        bpp += 1;
+    }
+    let img_cap = (usable * bpp as usize) >> 3;
+    //the header has to be in the cover, only the data may go on in added lines
+    if img_cap < IMG_HEADER_SIZE {
+        return Err(LoadError::CoverTooSmall(width, height));
+    }
+    let orig_size = (width * height) as usize;
+    let mut added_lines = 0u32;
+    if needed > img_cap {
+        //the rest is stored a full byte per channel in lines added below the cover
+        let bytes_per_line = width as usize * 3;
+        added_lines = ((needed - img_cap + bytes_per_line - 1) / bytes_per_line) as u32;
+        height += added_lines;
+        img.resize((width * height * 4) as usize, 0);
+    }
+
+    let mut header = vec![];
+    for v in [IMG_MAGIC, blob.len() as u32, added_lines, 0, 0, 0, 0, 0, 0] {
+        header.extend(v.to_le_bytes());
+    }
+
+    //first pixel holds bpp
+    encode(&mut img, 0, 1, &[bpp as u8]);
+    let ptr = encode(&mut img, 4, bpp, &header);
+    if added_lines == 0 {
+        encode(&mut img, ptr, bpp, blob);
+    } else {
+        let first_chunk = img_cap - IMG_HEADER_SIZE;
+        encode(&mut img, ptr, bpp, &blob[..first_chunk]);
+        encode(&mut img, orig_size * 4, 8, &blob[first_chunk..]);
+    }
+    //alpha must be opaque, or browsers premultiply and lose the low bits
+    for alpha in img.iter_mut().skip(3).step_by(4) {
+        *alpha = 0xff;
+    }
+
+    let mut out = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&img)?;
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_load_errors() {
+    assert!(matches!(loadPNG(b"not a png", None), Err(LoadError::NotAPng(_))));
+}
+
+#[test]
+fn test_tiny_cover() {
+    let cover = |w: u32, h: u32| {
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, w, h);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(&vec![0x80; (w * h * 4) as usize]).unwrap();
+        png
+    };
+    assert!(matches!(encodePNG(b"x", Some(&cover(1, 1))), Err(LoadError::CoverTooSmall(1, 1))));
+    assert!(matches!(encodePNG(&[7; 100], Some(&cover(4, 4))), Err(LoadError::CoverTooSmall(4, 4))));
+    //25 pixels hold the header at 4 bpp, the data goes on in added lines
+    let blob: Vec<u8> = (0..100u8).collect();
+    let png = encodePNG(&blob, Some(&cover(5, 5))).unwrap();
+    assert_eq!(decode_blob(&png).unwrap(), blob);
+}
+
+#[test]
+fn test_blob_round_trip() {
+    //small blobs use 1 bpp, larger ones 2..4 bpp, the largest need added lines
+    for len in [0usize, 10, 2000, 9000, 16000, 25000, 30000] {
+        let blob: Vec<u8> = (0..len).map(|i| (i * 7 + i / 13) as u8).collect();
+        let png = encodePNG(&blob, None).unwrap();
+        assert_eq!(decode_blob(&png).unwrap(), blob, "blob of {} bytes", len);
+    }
+}
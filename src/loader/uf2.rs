@@ -0,0 +1,178 @@
+//makecode hardware downloads keep the compressed project after the program image
+//block format: https://github.com/microsoft/uf2
+//embedded source: https://github.com/microsoft/pxt/blob/master/pxtlib/hexfile.ts extractSourceFromBin, unpackSourceFromHexAsync
+use std::path::Path;
+
+use crate::engine;
+
+use super::LoadError;
+
+const UF2_BLOCK_SIZE: usize = 512;
+const UF2_MAGIC_START0: u32 = 0x0A324655;
+const UF2_MAGIC_START1: u32 = 0x9E5D5157;
+const UF2_MAGIC_END: u32 = 0x0AB16F30;
+const UF2_PAYLOAD_OFFSET: usize = 32;
+const UF2_MAX_PAYLOAD: usize = 476;
+
+const SOURCE_MAGIC: [u8; 8] = [0x41, 0x14, 0x0E, 0x2F, 0xB8, 0x2F, 0xA2, 0xBB];
+const SOURCE_HEADER_SIZE: usize = 16;
+
+fn le32(d: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([d[at], d[at + 1], d[at + 2], d[at + 3]])
+}
+
+pub fn is_uf2(data: &[u8]) -> bool {
+    data.len() >= UF2_BLOCK_SIZE && le32(data, 0) == UF2_MAGIC_START0 && le32(data, 4) == UF2_MAGIC_START1
+}
+
+pub fn loadUF2(data: &[u8], cache_dir: Option<&Path>) -> Result<engine::Project, LoadError> {
+    let flash = flatten(data);
+    let (meta, text) = find_source(&flash).ok_or_else(|| LoadError::MissingSource(String::from("embedded source")))?;
+    let meta: serde_json::Value = serde_json::from_slice(meta)?;
+    let text = match meta.get("compression").and_then(|v| v.as_str()) {
+        Some("LZMA") => {
+            let mut decoded: Vec<u8> = vec![];
+            let mut sl = text;
+            lzma_rs::lzma_decompress(&mut sl, &mut decoded)?;
+            String::from_utf8(decoded)?
+        }
+        None => String::from_utf8(text.to_vec())?,
+        Some(other) => return Err(LoadError::UnsupportedCompression(String::from(other))),
+    };
+    //the text is the project header json followed by the file map json
+    let header_size = meta
+        .get("headerSize")
+        .or_else(|| meta.get("metaSize"))
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0) as usize;
+    //sizes are counted by pxt in js string characters
+    let split = text.char_indices().nth(header_size).map_or(text.len(), |(i, _)| i);
+    let source_obj: serde_json::Value = serde_json::from_str(&text[split..])?;
+    let mut prj = super::project_from_sources(&source_obj, cache_dir)?;
+    if let Ok(header) = serde_json::from_str::<serde_json::Value>(&text[..split]) {
+        super::apply_header(&mut prj.meta, &header);
+    }
+    Ok(prj)
+}
+
+//payloads of all valid blocks, in target address order
+fn flatten(data: &[u8]) -> Vec<u8> {
+    let mut blocks: Vec<(u32, &[u8])> = data
+        .chunks_exact(UF2_BLOCK_SIZE)
+        .filter(|b| le32(b, 0) == UF2_MAGIC_START0 && le32(b, 4) == UF2_MAGIC_START1 && le32(b, UF2_BLOCK_SIZE - 4) == UF2_MAGIC_END)
+        .map(|b| {
+            let size = (le32(b, 16) as usize).min(UF2_MAX_PAYLOAD);
+            (le32(b, 12), &b[UF2_PAYLOAD_OFFSET..UF2_PAYLOAD_OFFSET + size])
+        })
+        .collect();
+    blocks.sort_by_key(|b| b.0);
+    blocks.into_iter().flat_map(|b| b.1.iter().cloned()).collect()
+}
+
+//returns (meta json, source text)
+fn find_source(flash: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut p = 0usize;
+    while p + SOURCE_HEADER_SIZE <= flash.len() {
+        if flash[p..p + SOURCE_MAGIC.len()] != SOURCE_MAGIC {
+            p += 1;
+            continue;
+        }
+        let meta_len = (flash[p + 8] as usize) | (flash[p + 9] as usize) << 8;
+        let text_len = le32(flash, p + 10) as usize;
+        let start = p + SOURCE_HEADER_SIZE;
+        let end = start + meta_len + text_len;
+        if end <= flash.len() {
+            return Some((&flash[start..start + meta_len], &flash[start + meta_len..end]));
+        }
+        p += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+fn make_uf2(flash: &[u8], base: u32) -> Vec<u8> {
+    let chunks: Vec<&[u8]> = flash.chunks(256).collect();
+    let mut uf2 = vec![];
+    //write the odd blocks first, then the even ones in reverse, the reader must order them by address
+    let order = (0..chunks.len()).filter(|i| i % 2 == 1).chain((0..chunks.len()).filter(|i| i % 2 == 0).rev());
+    for i in order {
+        let chunk = chunks[i];
+        let mut block = vec![0u8; UF2_BLOCK_SIZE];
+        for (at, v) in [
+            (0, UF2_MAGIC_START0),
+            (4, UF2_MAGIC_START1),
+            (12, base + i as u32 * 256),
+            (16, chunk.len() as u32),
+            (20, i as u32),
+            (24, chunks.len() as u32),
+            (UF2_BLOCK_SIZE - 4, UF2_MAGIC_END),
+        ] {
+            block[at..at + 4].copy_from_slice(&v.to_le_bytes());
+        }
+        block[UF2_PAYLOAD_OFFSET..UF2_PAYLOAD_OFFSET + chunk.len()].copy_from_slice(chunk);
+        uf2.extend(block);
+    }
+    uf2
+}
+
+#[test]
+fn test_load_uf2() {
+    let header = r#"{"name":"uf2 test","editor":"tsprj"}"#;
+    let files = serde_json::json!({ "main.js": "", "pxt.json": r#"{"files":["main.js"]}"# }).to_string();
+    let mut text = vec![];
+    lzma_rs::lzma_compress(&mut format!("{}{}", header, files).as_bytes(), &mut text).unwrap();
+    let meta = format!(r#"{{"compression":"LZMA","headerSize":{},"textSize":{}}}"#, header.len(), text.len());
+
+    let mut flash = vec![0xffu8; 1000];
+    flash.extend(SOURCE_MAGIC);
+    flash.extend((meta.len() as u16).to_le_bytes());
+    flash.extend((text.len() as u32).to_le_bytes());
+    flash.extend([0u8; 2]);
+    flash.extend(meta.as_bytes());
+    flash.extend(&text);
+    let uf2 = make_uf2(&flash, 0x2000);
+
+    assert!(is_uf2(&uf2));
+    let flattened = flatten(&uf2);
+    assert_eq!(flattened, flash);
+    let (m, t) = find_source(&flattened).unwrap();
+    assert_eq!(m, meta.as_bytes());
+    assert_eq!(t, &text[..]);
+    //no .ts in the file list, so there is nothing to run
+    assert!(matches!(loadUF2(&uf2, None), Err(LoadError::MissingSource(_))));
+    assert!(matches!(loadUF2(&make_uf2(&[0u8; 600], 0), None), Err(LoadError::MissingSource(_))));
+}
+
+#[test]
+fn test_load_uf2_sources() {
+    let header = r#"{"name":"uf2 sources","editor":"tsprj"}"#;
+    let main_ts = format!("let score: number = 0
+{}info.setScore(score)
+", "score += 1
+".repeat(60));
+    let files = serde_json::json!({
+        "main.ts": main_ts,
+        "pxt.json": r#"{"name":"uf2 sources","files":["main.ts"]}"#,
+    })
+    .to_string();
+    //uncompressed, so the text spans several blocks
+    let text = format!("{}{}", header, files);
+    let meta = format!(r#"{{"headerSize":{},"textSize":{}}}"#, header.len(), text.len());
+
+    let mut flash = vec![0xffu8; 300];
+    flash.extend(SOURCE_MAGIC);
+    flash.extend((meta.len() as u16).to_le_bytes());
+    flash.extend((text.len() as u32).to_le_bytes());
+    flash.extend([0u8; 2]);
+    flash.extend(meta.as_bytes());
+    flash.extend(text.as_bytes());
+    let uf2 = make_uf2(&flash, 0x2000);
+    assert!(uf2.len() / UF2_BLOCK_SIZE >= 4);
+
+    let prj = loadUF2(&uf2, None).unwrap();
+    assert_eq!(prj.meta.name, "uf2 sources");
+    assert_eq!(prj.order, vec![String::from("main.ts")]);
+    assert_eq!(prj.files["main.ts"], main_ts);
+    assert!(prj.files["pxt.json"].contains(r#""files":["main.ts"]"#));
+    assert!(prj.sources["main.ts"].contains("info.setScore(score)"));
+}
@@ -0,0 +1,406 @@
+//key and debug control events of a deterministic run, frame by frame, to play a game again exactly
+//as recorded. file: "MKRP", version, seed u64 le, frame time u32 le (micro seconds), then varints:
+//frames, project name length and bytes, event count, and per event the frames since the
+//previous one and a byte: the key index below or one of the CONTROL_ ones, 0x80 for down or paused.
+//a time scale is followed by its f32 le
+use std::{fmt, fs, io, path::Path};
+
+use crate::{emulator::game::KeyCode, engine::Event};
+
+const MAGIC: &[u8; 4] = b"MKRP";
+const VERSION: u8 = 2;
+//the keys by their index in the file, key_index the other way
+const KEYS: [KeyCode; 8] = [
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Left,
+    KeyCode::Right,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::A,
+    KeyCode::B,
+];
+const CONTROL_PAUSE: u8 = 0x08;
+const CONTROL_FRAME_STEP: u8 = 0x09;
+const CONTROL_TIME_SCALE: u8 = 0x0a;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadEvent(u8),
+    //a key with no index in the file
+    NoIndex(KeyCode),
+    //a frame number or length doesn't fit its type
+    Overflow,
+    Utf8(std::string::FromUtf8Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::BadMagic => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(v) => write!(f, "unsupported replay version {}", v),
+            ReplayError::Truncated => write!(f, "replay data is truncated"),
+            ReplayError::BadEvent(b) => write!(f, "invalid event {:#x} in replay", b),
+            ReplayError::NoIndex(k) => write!(f, "key {} can't be saved in a replay", k),
+            ReplayError::Overflow => write!(f, "number out of range in replay"),
+            ReplayError::Utf8(e) => write!(f, "replay project name is not valid utf8: {}", e),
+            ReplayError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+//what is recorded of the events sent to the engine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    //key, down
+    Key(KeyCode, bool),
+    Pause(bool),
+    FrameStep,
+    TimeScale(f32),
+}
+
+impl Input {
+    fn from_event(evt: &Event) -> Option<Input> {
+        match evt {
+            Event::KeyDown(key) if *key != KeyCode::None => Some(Input::Key(*key, true)),
+            Event::KeyUp(key) if *key != KeyCode::None => Some(Input::Key(*key, false)),
+            Event::Pause(paused) => Some(Input::Pause(*paused)),
+            Event::FrameStep => Some(Input::FrameStep),
+            Event::TimeScale(scale) => Some(Input::TimeScale(*scale)),
+            _ => None,
+        }
+    }
+
+    pub fn event(&self) -> Event {
+        match self {
+            Input::Key(key, true) => Event::KeyDown(*key),
+            Input::Key(key, false) => Event::KeyUp(*key),
+            Input::Pause(paused) => Event::Pause(*paused),
+            Input::FrameStep => Event::FrameStep,
+            Input::TimeScale(scale) => Event::TimeScale(*scale),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Replay {
+    //seed of the deterministic run
+    pub seed: u64,
+    //time every frame advanced the game by
+    pub frame_us: u32,
+    //frames run from the load of the project
+    pub frames: u32,
+    //name of the recorded project, to tell replays apart
+    pub project: String,
+    //(frame, input) in the order they were sent to the engine
+    pub events: Vec<(u32, Input)>,
+}
+
+//exhaustive, a new key must get its index here and in KEYS
+fn key_index(key: KeyCode) -> Option<u8> {
+    match key {
+        KeyCode::Up => Some(0),
+        KeyCode::Down => Some(1),
+        KeyCode::Left => Some(2),
+        KeyCode::Right => Some(3),
+        KeyCode::X => Some(4),
+        KeyCode::Y => Some(5),
+        KeyCode::A => Some(6),
+        KeyCode::B => Some(7),
+        KeyCode::None => None,
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], ReplayError> {
+        if self.0.len() < n {
+            return Err(ReplayError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn varint(&mut self) -> Result<u64, ReplayError> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.bytes(1)?[0];
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(ReplayError::Truncated)
+    }
+
+    fn varint_u32(&mut self) -> Result<u32, ReplayError> {
+        u32::try_from(self.varint()?).map_err(|_| ReplayError::Overflow)
+    }
+
+    fn varint_usize(&mut self) -> Result<usize, ReplayError> {
+        usize::try_from(self.varint()?).map_err(|_| ReplayError::Overflow)
+    }
+}
+
+impl Replay {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ReplayError> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.frame_us.to_le_bytes());
+        put_varint(&mut out, self.frames as u64);
+        put_varint(&mut out, self.project.len() as u64);
+        out.extend_from_slice(self.project.as_bytes());
+        put_varint(&mut out, self.events.len() as u64);
+        let mut last = 0;
+        for (frame, input) in self.events.iter() {
+            put_varint(&mut out, (frame - last) as u64);
+            last = *frame;
+            match input {
+                Input::Key(key, down) => {
+                    let idx = key_index(*key).ok_or(ReplayError::NoIndex(*key))?;
+                    out.push(idx | if *down { 0x80 } else { 0 });
+                }
+                Input::Pause(paused) => out.push(CONTROL_PAUSE | if *paused { 0x80 } else { 0 }),
+                Input::FrameStep => out.push(CONTROL_FRAME_STEP),
+                Input::TimeScale(scale) => {
+                    out.push(CONTROL_TIME_SCALE);
+                    out.extend_from_slice(&scale.to_le_bytes());
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Replay, ReplayError> {
+        let mut r = Reader(data);
+        if r.bytes(4).map_err(|_| ReplayError::BadMagic)? != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        let version = r.bytes(1)?[0];
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let seed = u64::from_le_bytes(r.bytes(8)?.try_into().unwrap());
+        let frame_us = u32::from_le_bytes(r.bytes(4)?.try_into().unwrap());
+        let frames = r.varint_u32()?;
+        let len = r.varint_usize()?;
+        let project = String::from_utf8(r.bytes(len)?.to_vec()).map_err(ReplayError::Utf8)?;
+        let count = r.varint_usize()?;
+        let mut events = Vec::with_capacity(count.min(data.len()));
+        let mut frame = 0u32;
+        for _ in 0..count {
+            frame = frame.checked_add(r.varint_u32()?).ok_or(ReplayError::Overflow)?;
+            let b = r.bytes(1)?[0];
+            let input = match b & 0x7f {
+                CONTROL_PAUSE => Input::Pause(b & 0x80 != 0),
+                CONTROL_FRAME_STEP => Input::FrameStep,
+                CONTROL_TIME_SCALE => Input::TimeScale(f32::from_le_bytes(r.bytes(4)?.try_into().unwrap())),
+                idx => Input::Key(*KEYS.get(idx as usize).ok_or(ReplayError::BadEvent(b))?, b & 0x80 != 0),
+            };
+            events.push((frame, input));
+        }
+        Ok(Replay { seed, frame_us, frames, project, events })
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, ReplayError> {
+        Replay::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+}
+
+//collects the key and debug control events sent to the engine, call next_frame after every tick
+pub struct Recorder {
+    replay: Replay,
+}
+
+impl Recorder {
+    pub fn new(seed: u64, frame_us: u32, project: &str) -> Self {
+        Recorder {
+            replay: Replay {
+                seed,
+                frame_us,
+                project: String::from(project),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn record(&mut self, evt: &Event) {
+        if let Some(input) = Input::from_event(evt) {
+            self.replay.events.push((self.replay.frames, input));
+        }
+    }
+
+    pub fn next_frame(&mut self) {
+        self.replay.frames += 1;
+    }
+
+    //the game (re)started, the recording begins again from frame 0
+    pub fn restart(&mut self, project: Option<&str>) {
+        self.replay.frames = 0;
+        self.replay.events.clear();
+        if let Some(name) = project {
+            self.replay.project = String::from(name);
+        }
+    }
+
+    pub fn finish(self) -> Replay {
+        self.replay
+    }
+}
+
+//hands the recorded events back frame by frame
+pub struct Player {
+    replay: Replay,
+    frame: u32,
+    next: usize,
+}
+
+impl Player {
+    pub fn new(replay: Replay) -> Self {
+        Player { replay, frame: 0, next: 0 }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    //the game (re)started, play from the first frame again
+    pub fn rewind(&mut self) {
+        self.frame = 0;
+        self.next = 0;
+    }
+
+    //every recorded frame was played
+    pub fn done(&self) -> bool {
+        self.frame >= self.replay.frames
+    }
+
+    //events to send before the tick of the next frame
+    pub fn next_frame(&mut self) -> Vec<Event> {
+        let mut events = vec![];
+        while let Some((frame, input)) = self.replay.events.get(self.next) {
+            if *frame > self.frame {
+                break;
+            }
+            events.push(input.event());
+            self.next += 1;
+        }
+        self.frame += 1;
+        events
+    }
+}
+
+#[test]
+fn test_replay_round_trip() {
+    let mut recorder = Recorder::new(42, 20000, "my game");
+    recorder.record(&Event::KeyDown(KeyCode::Right));
+    recorder.next_frame();
+    recorder.next_frame();
+    recorder.record(&Event::KeyDown(KeyCode::A));
+    recorder.record(&Event::Unload);
+    recorder.next_frame();
+    for _ in 0..300 {
+        recorder.next_frame();
+    }
+    recorder.record(&Event::KeyUp(KeyCode::Right));
+    recorder.next_frame();
+    recorder.record(&Event::Pause(true));
+    recorder.record(&Event::FrameStep);
+    recorder.record(&Event::TimeScale(0.5));
+    recorder.next_frame();
+    let replay = recorder.finish();
+    assert_eq!(
+        replay.events,
+        vec![
+            (0, Input::Key(KeyCode::Right, true)),
+            (2, Input::Key(KeyCode::A, true)),
+            (303, Input::Key(KeyCode::Right, false)),
+            (304, Input::Pause(true)),
+            (304, Input::FrameStep),
+            (304, Input::TimeScale(0.5)),
+        ]
+    );
+    assert_eq!(replay.frames, 305);
+
+    let bytes = replay.to_bytes().unwrap();
+    assert_eq!(bytes.len(), 4 + 1 + 8 + 4 + 2 + 1 + 7 + 1 + 2 + 2 + 3 + 2 + 2 + 6);
+    assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+    assert!(matches!(Replay::from_bytes(b"PNG"), Err(ReplayError::BadMagic)));
+    assert!(matches!(Replay::from_bytes(&bytes[..bytes.len() - 1]), Err(ReplayError::Truncated)));
+    let mut bad = bytes.clone();
+    bad[bytes.len() - 5] = 0x0b;
+    assert!(matches!(Replay::from_bytes(&bad), Err(ReplayError::BadEvent(0x0b))));
+
+    let mut player = Player::new(replay);
+    let played: Vec<String> = (0..3).map(|_| format!("{:?}", player.next_frame())).collect();
+    assert_eq!(played, vec!["[KeyDown(Right)]", "[]", "[KeyDown(A)]"]);
+    let mut last = vec![];
+    while !player.done() {
+        last = player.next_frame();
+    }
+    assert_eq!(format!("{:?}", last), "[Pause(true), FrameStep, TimeScale(0.5)]");
+    player.rewind();
+    assert_eq!(player.next_frame().len(), 1);
+
+    assert!(KEYS.iter().enumerate().all(|(i, key)| key_index(*key) == Some(i as u8)));
+    let none = Replay { events: vec![(0, Input::Key(KeyCode::None, true))], ..Default::default() };
+    assert!(matches!(none.to_bytes(), Err(ReplayError::NoIndex(KeyCode::None))));
+}
+
+#[test]
+fn test_replay_overflow() {
+    let header = |frames: &[u8]| {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&20000u32.to_le_bytes());
+        bytes.extend_from_slice(frames);
+        bytes.push(0);
+        bytes
+    };
+    //frame count past u32::MAX
+    let bytes = header(&[0x80, 0x80, 0x80, 0x80, 0x10]);
+    assert!(matches!(Replay::from_bytes(&bytes), Err(ReplayError::Overflow)));
+
+    //two deltas that each fit a u32 but add up past it
+    let mut bytes = header(&[0x05]);
+    bytes.push(2);
+    for _ in 0..2 {
+        bytes.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x0f]);
+        bytes.push(4);
+    }
+    assert!(matches!(Replay::from_bytes(&bytes), Err(ReplayError::Overflow)));
+    //a delta past u32::MAX on its own
+    let mut bytes = header(&[0x05]);
+    bytes.push(1);
+    bytes.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x7f, 4]);
+    assert!(matches!(Replay::from_bytes(&bytes), Err(ReplayError::Overflow)));
+}
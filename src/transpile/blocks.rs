@@ -0,0 +1,357 @@
+//blockly xml of main.blocks to typescript, for the blocks binding.js implements
+//block ids and input names: https://github.com/microsoft/pxt-common-packages/tree/master/libs/game
+use std::collections::BTreeSet;
+
+use roxmltree::{Document, Node};
+
+use super::Diagnostic;
+
+const FILE_NAME: &str = "main.blocks";
+const INDENT: &str = "    ";
+
+//sprite kinds binding.js defines, other kinds are created by the project
+const BUILTIN_SPRITE_KINDS: &[&str] = &["Player", "Projectile"];
+
+enum Kind {
+    Expr,
+    Stmt,
+}
+
+//{name} is the value input or field called name, {name|member} the last dotted part of it,
+//{a,b} the first of a and b the block has, {_} the indent of the block, a statement input is its body.
+//a { ending the line is literal
+static BLOCKS: &[(&str, Kind, &str)] = &[
+    ("math_number", Kind::Expr, "{NUM}"),
+    ("math_integer", Kind::Expr, "{NUM}"),
+    ("math_whole_number", Kind::Expr, "{NUM}"),
+    ("math_positive_number", Kind::Expr, "{NUM}"),
+    ("math_number_minmax", Kind::Expr, "{SLIDER}"),
+    ("logic_negate", Kind::Expr, "!({BOOL})"),
+    ("toggleOnOff", Kind::Expr, "{on}"),
+    ("timePicker", Kind::Expr, "{ms}"),
+    ("colorindexpicker", Kind::Expr, "{index}"),
+    ("screen_image_picker", Kind::Expr, "{img}"),
+    ("variables_get", Kind::Expr, "{VAR}"),
+    ("device_random", Kind::Expr, "randint({min}, {limit})"),
+    ("spritekind", Kind::Expr, "SpriteKind.{MEMBER}"),
+    ("action_enum_shim", Kind::Expr, "ActionKind.{arg}"),
+    ("spritescreate", Kind::Expr, "sprites.create({img}, {kind})"),
+    ("spritescreateprojectilefromside", Kind::Expr, "sprites.createProjectileFromSide({img}, {vx}, {vy})"),
+    ("Sprite_blockCombine_get", Kind::Expr, "{sprite,mySprite}.{property|member}"),
+    ("create_animation", Kind::Expr, "animation.createAnimation({action}, {interval})"),
+    ("scenescreenwidth", Kind::Expr, "scene.screenWidth()"),
+    ("scenescreenheight", Kind::Expr, "scene.screenHeight()"),
+    ("variables_set", Kind::Stmt, "{VAR} = {VALUE}"),
+    ("variables_change", Kind::Stmt, "{VAR} += {VALUE}"),
+    ("controls_repeat_ext", Kind::Stmt, "for (let index = 0; index < {TIMES}; index++) {\n{DO}{_}}"),
+    ("device_while", Kind::Stmt, "while ({COND}) {\n{DO}{_}}"),
+    ("controls_while", Kind::Stmt, "while ({COND}) {\n{DO}{_}}"),
+    ("spritescreatenoset", Kind::Stmt, "sprites.create({img}, {kind})"),
+    ("spritesoverlap", Kind::Stmt, "sprites.onOverlap({kind}, {otherKind}, function (sprite, otherSprite) {\n{HANDLER}{_}})"),
+    ("Sprite_blockCombine_set", Kind::Stmt, "{sprite,mySprite}.{property|member} = {value}"),
+    ("Sprite_blockCombine_change", Kind::Stmt, "{sprite,mySprite}.{property|member} += {value}"),
+    ("spritesetsetflag", Kind::Stmt, "{sprite,mySprite}.setFlag({flag}, {on})"),
+    ("keyonevent", Kind::Stmt, "{button}.onEvent({event}, function () {\n{HANDLER}{_}})"),
+    ("gameupdate", Kind::Stmt, "game.onUpdate(function () {\n{HANDLER}{_}})"),
+    ("gameinterval", Kind::Stmt, "game.onUpdateInterval({period}, function () {\n{HANDLER}{_}})"),
+    ("gameOver", Kind::Stmt, "game.over({win})"),
+    ("gamesetbackgroundcolor", Kind::Stmt, "scene.setBackgroundColor({color})"),
+    ("hudSetScore", Kind::Stmt, "info.setScore({value})"),
+    ("hudChangeScoreBy", Kind::Stmt, "info.changeScoreBy({value})"),
+    ("add_frame", Kind::Stmt, "{this}.addAnimationFrame({frame})"),
+    ("attach_animation", Kind::Stmt, "animation.attachAnimation({sprite}, {set})"),
+    ("set_animation", Kind::Stmt, "animation.setAction({sprite}, {action})"),
+];
+
+struct Compiler<'a, 'input> {
+    doc: &'a Document<'input>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+fn children<'a, 'input>(node: Node<'a, 'input>, tag: &'static str) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |n| n.is_element() && n.tag_name().name() == tag)
+}
+
+fn named<'a, 'input>(node: Node<'a, 'input>, tag: &'static str, name: &str) -> Option<Node<'a, 'input>> {
+    children(node, tag).find(|n| n.attribute("name") == Some(name))
+}
+
+//the block plugged in an input, or the shadow block under it
+fn plugged<'a, 'input>(input: Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+    children(input, "block").next().or_else(|| children(input, "shadow").next())
+}
+
+//block variable names may contain spaces
+fn identifier(name: &str) -> String {
+    let mut id: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '_' || c == '$' { c } else { '_' })
+        .collect();
+    if id.is_empty() || id.starts_with(|c: char| c.is_ascii_digit()) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+impl<'a, 'input> Compiler<'a, 'input> {
+    fn error(&mut self, node: Node, message: String) {
+        let pos = self.doc.text_pos_at(node.range().start);
+        self.diagnostics.push(Diagnostic {
+            file: String::from(FILE_NAME),
+            line: pos.row as usize,
+            column: pos.col as usize,
+            message,
+        });
+    }
+
+    fn field(&self, block: Node, name: &str) -> Option<String> {
+        let text = named(block, "field", name)?.text().unwrap_or("");
+        if name == "VAR" {
+            return Some(identifier(text));
+        }
+        //combined property fields read like Sprite.x@set
+        Some(String::from(text.split('@').next().unwrap_or("")))
+    }
+
+    fn input(&mut self, block: Node, name: &str, indent: usize) -> Option<String> {
+        if let Some(input) = named(block, "value", name) {
+            return match plugged(input) {
+                Some(b) => Some(self.expr(b)),
+                None => None,
+            };
+        }
+        if let Some(input) = named(block, "statement", name) {
+            return Some(match children(input, "block").next() {
+                Some(b) => self.statements(b, indent + 1),
+                None => String::new(),
+            });
+        }
+        self.field(block, name)
+    }
+
+    fn render(&mut self, block: Node, template: &str, indent: usize) -> String {
+        let mut out = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            //a brace ending the line opens a body
+            if rest[start + 1..].starts_with('\n') {
+                out.push('{');
+                rest = &rest[start + 1..];
+                continue;
+            }
+            let end = start + rest[start..].find('}').unwrap();
+            let spec = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+            if spec == "_" {
+                out.push_str(&INDENT.repeat(indent));
+                continue;
+            }
+            let (names, member) = match spec.strip_suffix("|member") {
+                Some(names) => (names, true),
+                None => (spec, false),
+            };
+            let value = names.split(',').find_map(|name| self.input(block, name, indent));
+            match value {
+                Some(v) if member => out.push_str(v.rsplit('.').next().unwrap_or("")),
+                Some(v) => out.push_str(&v),
+                None => {
+                    self.error(block, format!("block {} has no {}", block.attribute("type").unwrap_or(""), names));
+                    out.push_str("undefined");
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn expr(&mut self, block: Node) -> String {
+        let ty = block.attribute("type").unwrap_or("");
+        let binary = |ops: &[(&str, &'static str)], op: Option<String>| -> Option<&'static str> {
+            let op = op?;
+            ops.iter().find(|(name, _)| *name == op).map(|(_, js)| *js)
+        };
+        let op = match ty {
+            "math_arithmetic" => binary(
+                &[("ADD", "+"), ("MINUS", "-"), ("MULTIPLY", "*"), ("DIVIDE", "/"), ("POWER", "**")],
+                self.field(block, "OP"),
+            ),
+            "logic_compare" => binary(
+                &[("EQ", "=="), ("NEQ", "!="), ("LT", "<"), ("LTE", "<="), ("GT", ">"), ("GTE", ">=")],
+                self.field(block, "OP"),
+            ),
+            "logic_operation" => binary(&[("AND", "&&"), ("OR", "||")], self.field(block, "OP")),
+            "logic_boolean" => {
+                return String::from(if self.field(block, "BOOL").as_deref() == Some("TRUE") { "true" } else { "false" });
+            }
+            "text" => return serde_json::Value::from(self.field(block, "TEXT").unwrap_or_default()).to_string(),
+            _ => None,
+        };
+        if let Some(op) = op {
+            return self.render(block, &format!("({{A}} {} {{B}})", op), 0);
+        }
+        match BLOCKS.iter().find(|(id, _, _)| *id == ty) {
+            Some((_, Kind::Expr, template)) => self.render(block, template, 0),
+            _ => {
+                self.error(block, format!("unsupported expression block {}", ty));
+                String::from("undefined")
+            }
+        }
+    }
+
+    fn statement(&mut self, block: Node, indent: usize) -> String {
+        let ty = block.attribute("type").unwrap_or("");
+        let pad = INDENT.repeat(indent);
+        match ty {
+            "controls_if" => {
+                let mutation = children(block, "mutation").next();
+                let attr = |name| mutation.and_then(|m| m.attribute(name)).and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+                let (elseifs, has_else) = (attr("elseif"), attr("else") > 0);
+                let mut out = String::new();
+                for i in 0..=elseifs {
+                    let head = if i == 0 { format!("{}if", pad) } else { String::from(" else if") };
+                    out.push_str(&self.render(block, &format!("{} ({{IF{i}}}) {{\n{{DO{i}}}{{_}}}}", head, i = i), indent));
+                }
+                if has_else {
+                    out.push_str(&self.render(block, " else {\n{ELSE}{_}}", indent));
+                }
+                out + "\n"
+            }
+            "typescript_statement" => {
+                let mutation = children(block, "mutation").next();
+                let lines = mutation.and_then(|m| m.attribute("numlines")).and_then(|n| n.parse().ok()).unwrap_or(0);
+                (0..lines)
+                    .filter_map(|i: usize| mutation.and_then(|m| m.attribute(format!("line{}", i).as_str())))
+                    .map(|line| format!("{}{}\n", pad, line))
+                    .collect()
+            }
+            _ => match BLOCKS.iter().find(|(id, _, _)| *id == ty) {
+                Some((_, Kind::Stmt, template)) => format!("{}{};\n", pad, self.render(block, template, indent)),
+                _ => {
+                    self.error(block, format!("unsupported statement block {}", ty));
+                    String::new()
+                }
+            },
+        }
+    }
+
+    //block and the ones chained after it by <next>
+    fn statements(&mut self, block: Node, indent: usize) -> String {
+        let mut out = String::new();
+        let mut current = Some(block);
+        while let Some(b) = current {
+            if b.attribute("disabled") != Some("true") {
+                out.push_str(&self.statement(b, indent));
+            }
+            current = children(b, "next").next().and_then(|n| children(n, "block").next());
+        }
+        out
+    }
+}
+
+pub fn blocks2ts(xml: &str) -> Result<String, Vec<Diagnostic>> {
+    let doc = Document::parse(xml).map_err(|e| {
+        let pos = e.pos();
+        vec![Diagnostic {
+            file: String::from(FILE_NAME),
+            line: pos.row as usize,
+            column: pos.col as usize,
+            message: e.to_string(),
+        }]
+    })?;
+    let root = doc.root_element();
+    let mut compiler = Compiler { doc: &doc, diagnostics: vec![] };
+    let mut out = String::new();
+
+    //kinds are typed variables, the rest are globals assigned by the blocks
+    let mut sprite_kinds = vec![];
+    let mut action_kinds = vec![];
+    let mut globals = BTreeSet::new();
+    for var in children(root, "variables").flat_map(|v| children(v, "variable")) {
+        let name = identifier(var.text().unwrap_or(""));
+        match var.attribute("type").unwrap_or("") {
+            "KIND_SpriteKind" if !BUILTIN_SPRITE_KINDS.contains(&name.as_str()) => sprite_kinds.push(name),
+            "KIND_ActionKind" => action_kinds.push(name),
+            "" => {
+                globals.insert(name);
+            }
+            _ => {}
+        }
+    }
+    if !action_kinds.is_empty() {
+        out.push_str(&format!("enum ActionKind {{\n{}}}\n", action_kinds.iter().map(|k| format!("{}{},\n", INDENT, k)).collect::<String>()));
+    }
+    if !sprite_kinds.is_empty() {
+        out.push_str("namespace SpriteKind {\n");
+        for k in sprite_kinds.iter() {
+            out.push_str(&format!("{}export const {} = SpriteKind.create()\n", INDENT, k));
+        }
+        out.push_str("}\n");
+    }
+    for name in globals.iter() {
+        out.push_str(&format!("let {}: any = null\n", name));
+    }
+
+    //statements outside "on start" and event blocks never run in makecode either
+    for block in children(root, "block").filter(|b| b.attribute("disabled") != Some("true")) {
+        match block.attribute("type") {
+            Some("pxt-on-start") => {
+                if let Some(first) = named(block, "statement", "HANDLER").and_then(|s| children(s, "block").next()) {
+                    out.push_str(&compiler.statements(first, 0));
+                }
+            }
+            _ if named(block, "statement", "HANDLER").is_some() => out.push_str(&compiler.statement(block, 0)),
+            ty => println!("skip top level block {}", ty.unwrap_or("")),
+        }
+    }
+
+    if !compiler.diagnostics.is_empty() {
+        return Err(compiler.diagnostics);
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_blocks2ts() {
+    let xml = r#"<xml xmlns="https://developers.google.com/blockly/xml">
+<variables><variable id="a">mySprite</variable><variable type="KIND_SpriteKind">Player</variable><variable type="KIND_SpriteKind">Enemy</variable></variables>
+<block type="pxt-on-start" x="0" y="0"><statement name="HANDLER">
+  <block type="variables_set"><field name="VAR" id="a">mySprite</field>
+    <value name="VALUE"><block type="spritescreate">
+      <value name="img"><shadow type="screen_image_picker"><field name="img">img`
+. 1
+`</field></shadow></value>
+      <value name="kind"><shadow type="spritekind"><field name="MEMBER">Player</field></shadow></value>
+    </block></value>
+    <next><block type="gamesetbackgroundcolor"><value name="color"><shadow type="colorindexpicker"><field name="index">9</field></shadow></value></block></next>
+  </block>
+</statement></block>
+<block type="gameupdate" x="0" y="200"><statement name="HANDLER">
+  <block type="controls_if"><mutation else="1"></mutation>
+    <value name="IF0"><block type="logic_compare"><field name="OP">GT</field>
+      <value name="A"><block type="Sprite_blockCombine_get"><field name="property">Sprite.x@get</field>
+        <value name="mySprite"><block type="variables_get"><field name="VAR">mySprite</field></block></value></block></value>
+      <value name="B"><shadow type="math_number"><field name="NUM">150</field></shadow></value></block></value>
+    <statement name="DO0"><block type="hudChangeScoreBy"><value name="value"><shadow type="math_number"><field name="NUM">1</field></shadow></value></block></statement>
+    <statement name="ELSE"><block type="gameOver"><value name="win"><shadow type="toggleOnOff"><field name="on">true</field></shadow></value></block></statement>
+  </block>
+</statement></block>
+<block type="hudSetScore" x="500" y="0"><value name="value"><shadow type="math_number"><field name="NUM">3</field></shadow></value></block>
+</xml>"#;
+    let ts = blocks2ts(xml).unwrap();
+    assert_eq!(
+        ts,
+        "namespace SpriteKind {\n    export const Enemy = SpriteKind.create()\n}\nlet mySprite: any = null\n\
+mySprite = sprites.create(img`\n. 1\n`, SpriteKind.Player);\nscene.setBackgroundColor(9);\n\
+game.onUpdate(function () {\n    if ((mySprite.x > 150)) {\n        info.changeScoreBy(1);\n    } else {\n        game.over(true);\n    }\n});\n"
+    );
+}
+
+#[test]
+fn test_blocks2ts_errors() {
+    let errors = blocks2ts("<xml><block type=\"pxt-on-start\"><statement name=\"HANDLER\">\n<block type=\"music_play\"/></statement></block></xml>").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].line, errors[0].message.as_str()), (2, "unsupported statement block music_play"));
+    assert_eq!(blocks2ts("<xml><block></xml>").unwrap_err()[0].file, FILE_NAME);
+}
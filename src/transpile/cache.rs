@@ -0,0 +1,130 @@
+//transpiled js (and its source map) kept on disk, keyed by a hash of the source,
+//so a big project starts without swc the second time
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::Output;
+
+//part of every key, a new swc or a change of the options must not reuse old output
+const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "/swc-0.190/es2022");
+
+//where the cache goes in the frontend's save directory
+pub fn dir_in(save_dir: &Path) -> PathBuf {
+    save_dir.join("makecode-arcade").join("js-cache")
+}
+
+//64-bit FNV-1a, stable across builds unlike std's hasher
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for b in part.iter() {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        //so that ("ab", "c") and ("a", "bc") differ
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn key(name: &str, src: &str) -> String {
+    format!("{:016x}", fnv1a(&[VERSION.as_bytes(), name.as_bytes(), src.as_bytes()]))
+}
+
+//first word of every entry
+const ENTRY_MAGIC: &str = "mkcache1";
+
+//first line of an entry: the length and hash of the source it was made from, and the lengths of the js
+//and the map that follow, so an entry of another source (the key is only 64 bits) or a damaged one is a miss
+fn header(src: &str, out: &Output) -> String {
+    let map_len = out.source_map.as_ref().map_or(String::from("-"), |m| m.len().to_string());
+    format!("{} {} {:016x} {} {}\n", ENTRY_MAGIC, src.len(), fnv1a(&[src.as_bytes()]), out.code.len(), map_len)
+}
+
+fn path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{}.entry", key))
+}
+
+pub fn get_in(dir: &Path, key: &str, src: &str) -> Option<Output> {
+    let entry = fs::read_to_string(path(dir, key)).ok()?;
+    let (header, body) = entry.split_once('\n')?;
+    let fields: Vec<&str> = header.split(' ').collect();
+    let (code_len, map_len) = match fields[..] {
+        [ENTRY_MAGIC, src_len, src_hash, code_len, map_len]
+            if src_len == src.len().to_string() && src_hash == format!("{:016x}", fnv1a(&[src.as_bytes()])) =>
+        {
+            let map_len = match map_len {
+                "-" => None,
+                n => Some(n.parse::<usize>().ok()?),
+            };
+            (code_len.parse::<usize>().ok()?, map_len)
+        }
+        _ => return None,
+    };
+    if body.len() != code_len.checked_add(map_len.unwrap_or(0))? {
+        return None;
+    }
+    let code = String::from(body.get(..code_len)?);
+    let source_map = map_len.map(|_| String::from(&body[code_len..]));
+    Some(Output { code, source_map })
+}
+
+pub fn put_in(dir: &Path, key: &str, src: &str, out: &Output) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    //js and map in one file written through a rename, a crash never leaves a half written entry
+    let mut entry = header(src, out);
+    entry.push_str(&out.code);
+    if let Some(map) = out.source_map.as_ref() {
+        entry.push_str(map);
+    }
+    let tmp = dir.join(format!("{}.entry.tmp", key));
+    fs::write(&tmp, entry)?;
+    fs::rename(tmp, path(dir, key))
+}
+
+pub fn get(dir: &Path, name: &str, src: &str) -> Option<Output> {
+    get_in(dir, &key(name, src), src)
+}
+
+pub fn put(dir: &Path, name: &str, src: &str, out: &Output) {
+    if let Err(e) = put_in(dir, &key(name, src), src, out) {
+        println!("cache {} in {} failed: {}", name, dir.display(), e);
+    }
+}
+
+#[test]
+fn test_cache() {
+    assert_eq!(key("main.ts", "let a = 1"), key("main.ts", "let a = 1"));
+    assert_ne!(key("main.ts", "let a = 1"), key("main.ts", "let a = 2"));
+    assert_ne!(key("a.ts", "b"), key("a.t", "sb"));
+
+    let dir = std::env::temp_dir().join(format!("makecode-arcade-cache-{}", std::process::id()));
+    let src = "let a: number = 1";
+    let k = key("main.ts", src);
+    assert!(get_in(&dir, &k, src).is_none());
+    let out = Output { code: String::from("let a = 1;\n"), source_map: Some(String::from("{}")) };
+    put_in(&dir, &k, src, &out).unwrap();
+    let cached = get_in(&dir, &k, src).unwrap();
+    assert_eq!(cached.code, out.code);
+    assert_eq!(cached.source_map, out.source_map);
+    //the same key for another source, as on a hash collision
+    assert!(get_in(&dir, &k, "let a: number = 2").is_none());
+
+    let entry = fs::read(path(&dir, &k)).unwrap();
+    //truncated, with a byte too many, or garbage
+    fs::write(path(&dir, &k), &entry[..entry.len() - 1]).unwrap();
+    assert!(get_in(&dir, &k, src).is_none());
+    fs::write(path(&dir, &k), [&entry[..], b"}"].concat()).unwrap();
+    assert!(get_in(&dir, &k, src).is_none());
+    fs::write(path(&dir, &k), "let a = 1;\n").unwrap();
+    assert!(get_in(&dir, &k, src).is_none());
+
+    let no_map = Output { code: String::from("let b = 2;\n"), source_map: None };
+    put_in(&dir, &k, src, &no_map).unwrap();
+    let cached = get_in(&dir, &k, src).unwrap();
+    assert_eq!((cached.code, cached.source_map), (no_map.code, None));
+    fs::remove_dir_all(&dir).unwrap();
+}
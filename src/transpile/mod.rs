@@ -0,0 +1,316 @@
+//typescript (and later other makecode languages) to javascript runnable by v8
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use swc::common::{
+    errors::{DiagnosticBuilder, Emitter, Handler, Level},
+    sync::Lrc,
+    FileName, SourceMap,
+};
+use swc_error_reporters::{GraphicalReportHandler, PrettyEmitter, PrettyEmitterConfig};
+
+mod blocks;
+pub mod cache;
+mod python;
+
+pub use blocks::blocks2ts;
+pub use python::py2js;
+
+//one error reported by swc, located in the original source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    //1-based, 0 if swc gave no location
+    pub line: usize,
+    //1-based, 0 if swc gave no location
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+//PrettyEmitter only produces text, keep the located errors beside it
+struct DiagnosticCollector {
+    file: String,
+    cm: Lrc<SourceMap>,
+    pretty: PrettyEmitter,
+    diagnostics: Arc<Mutex<Vec<Diagnostic>>>,
+}
+
+impl Emitter for DiagnosticCollector {
+    fn emit(&mut self, db: &DiagnosticBuilder<'_>) {
+        self.pretty.emit(db);
+        if !matches!(db.level, Level::Bug | Level::Fatal | Level::PhaseFatal | Level::Error) {
+            return;
+        }
+        let (line, column) = match db.span.primary_span() {
+            Some(span) => {
+                let loc = self.cm.lookup_char_pos(span.lo());
+                (loc.line, loc.col.0 + 1)
+            }
+            None => (0, 0),
+        };
+        self.diagnostics.lock().unwrap().push(Diagnostic {
+            file: self.file.clone(),
+            line,
+            column,
+            message: db.message(),
+        });
+    }
+}
+
+//PrettyEmitter wants to own its writer, share it so the text can be printed afterwards
+#[derive(Clone, Default)]
+struct SharedText(Arc<Mutex<String>>);
+
+impl fmt::Write for SharedText {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.lock().unwrap().push_str(s);
+        Ok(())
+    }
+}
+
+pub struct Output {
+    pub code: String,
+    //source map v3 json, from code back to the typescript source
+    pub source_map: Option<String>,
+}
+
+pub fn ts2js(name: &str, src: &str) -> Result<Output, Vec<Diagnostic>> {
+    use swc::{config::{Options, SourceMapsConfig}, ecmascript::ast::EsVersion};
+    use swc_ecma_parser::Syntax;
+
+    let cm: Lrc<SourceMap> = Default::default();
+    let fm = cm.new_source_file(FileName::Custom(name.into()), src.into());    
+    let mut opt:Options = Default::default();
+    opt.config.jsc.syntax = Some(Syntax::Typescript(Default::default()));
+    opt.config.jsc.target = Some(EsVersion::Es2022);
+    opt.source_maps = Some(SourceMapsConfig::Bool(true));
+    let error_text = SharedText::default();
+    let diagnostics = Arc::new(Mutex::new(vec![]));
+    let result = {
+        let emitter = DiagnosticCollector {
+            file: String::from(name),
+            cm: cm.clone(),
+            pretty: PrettyEmitter::new(
+                cm.clone(),
+                Box::new(error_text.clone()),
+                GraphicalReportHandler::default(),
+                PrettyEmitterConfig {
+                    skip_filename: false
+                },
+            ),
+            diagnostics: diagnostics.clone(),
+        };
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        let compiler = swc::Compiler::new(cm);
+        compiler.process_js_file(fm, &handler, &opt)
+    };
+    match result {
+        Ok(out) => Ok(Output {
+            code: out.code,
+            source_map: out.map,
+        }),
+        Err(e) => {
+            println!("{}", error_text.0.lock().unwrap());
+            let mut diagnostics = diagnostics.lock().unwrap().clone();
+            if diagnostics.is_empty() {
+                diagnostics.push(Diagnostic {
+                    file: String::from(name),
+                    line: 0,
+                    column: 0,
+                    message: e.to_string(),
+                });
+            }
+            Err(diagnostics)
+        }
+    }
+}
+
+//ts2js through the on-disk cache in cache_dir, no cache without one
+pub fn cached_ts2js(name: &str, src: &str, cache_dir: Option<&Path>) -> Result<Output, Vec<Diagnostic>> {
+    let dir = match cache_dir {
+        Some(dir) => dir,
+        None => return ts2js(name, src),
+    };
+    if let Some(out) = cache::get(dir, name, src) {
+        return Ok(out);
+    }
+    let out = ts2js(name, src)?;
+    cache::put(dir, name, src, &out);
+    Ok(out)
+}
+
+//the names the top level of a script declares with var, let or const, true for const.
+//from the parsed script, so destructuring and declarations over several lines count and strings don't.
+//none if it doesn't parse, v8 reports the error when it runs it
+pub fn top_level_names(name: &str, js: &str) -> Vec<(String, bool)> {
+    use swc::ecmascript::ast::{Decl, EsVersion, Stmt, VarDeclKind};
+    use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax};
+
+    let cm: Lrc<SourceMap> = Default::default();
+    let fm = cm.new_source_file(FileName::Custom(name.into()), js.into());
+    let lexer = Lexer::new(Syntax::Es(Default::default()), EsVersion::Es2022, StringInput::from(&*fm), None);
+    let script = match Parser::new_from(lexer).parse_script() {
+        Ok(script) => script,
+        Err(_) => return vec![],
+    };
+    let mut names = vec![];
+    for stmt in script.body.iter() {
+        if let Stmt::Decl(Decl::Var(var)) = stmt {
+            for decl in var.decls.iter() {
+                pat_names(&decl.name, var.kind == VarDeclKind::Const, &mut names);
+            }
+        }
+    }
+    names
+}
+
+fn pat_names(pat: &swc::ecmascript::ast::Pat, is_const: bool, names: &mut Vec<(String, bool)>) {
+    use swc::ecmascript::ast::{ObjectPatProp, Pat};
+
+    match pat {
+        Pat::Ident(ident) => add_name(names, ident.id.sym.to_string(), is_const),
+        Pat::Array(array) => {
+            for elem in array.elems.iter().flatten() {
+                pat_names(elem, is_const, names);
+            }
+        }
+        Pat::Object(object) => {
+            for prop in object.props.iter() {
+                match prop {
+                    ObjectPatProp::KeyValue(kv) => pat_names(&kv.value, is_const, names),
+                    ObjectPatProp::Assign(assign) => add_name(names, assign.key.sym.to_string(), is_const),
+                    ObjectPatProp::Rest(rest) => pat_names(&rest.arg, is_const, names),
+                }
+            }
+        }
+        Pat::Rest(rest) => pat_names(&rest.arg, is_const, names),
+        Pat::Assign(assign) => pat_names(&assign.left, is_const, names),
+        _ => {}
+    }
+}
+
+fn add_name(names: &mut Vec<(String, bool)>, name: String, is_const: bool) {
+    if !names.iter().any(|(n, _)| *n == name) {
+        names.push((name, is_const));
+    }
+}
+
+//source maps of all scripts of a project, keyed by script name
+#[derive(Default)]
+pub struct SourceMaps {
+    maps: HashMap<String, sourcemap::SourceMap>,
+}
+
+impl SourceMaps {
+    pub fn add(&mut self, script: &str, map_json: &str) {
+        match sourcemap::SourceMap::from_slice(map_json.as_bytes()) {
+            Ok(map) => {
+                self.maps.insert(String::from(script), map);
+            }
+            Err(e) => println!("bad source map of {}: {}", script, e),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.maps.clear();
+    }
+
+    //js line is 1-based and column 0-based as v8 reports them, returns the 1-based original line and column
+    pub fn lookup(&self, script: &str, line: usize, column: usize) -> Option<(u32, u32)> {
+        if line == 0 {
+            return None;
+        }
+        let token = self.maps.get(script)?.lookup_token(line as u32 - 1, column as u32)?;
+        Some((token.get_src_line() + 1, token.get_src_col() + 1))
+    }
+
+    //rewrite every "script:line:column" of a v8 stack trace to the original location
+    pub fn rewrite_stack(&self, stack: &str) -> String {
+        let mut ret = String::with_capacity(stack.len());
+        for (i, line) in stack.split('\n').enumerate() {
+            if i > 0 {
+                ret.push('\n');
+            }
+            ret.push_str(self.rewrite_stack_line(line).as_str());
+        }
+        ret
+    }
+
+    fn rewrite_stack_line(&self, line: &str) -> String {
+        for script in self.maps.keys() {
+            let pattern = format!("{}:", script);
+            let start = match line.find(pattern.as_str()) {
+                Some(s) => s + pattern.len(),
+                None => continue,
+            };
+            let rest = &line[start..];
+            let digits = |s: &str| s.bytes().take_while(|b| b.is_ascii_digit()).count();
+            let line_len = digits(rest);
+            if line_len == 0 || !rest[line_len..].starts_with(':') {
+                continue;
+            }
+            let col_len = digits(&rest[line_len + 1..]);
+            if col_len == 0 {
+                continue;
+            }
+            let js_line: usize = rest[..line_len].parse().unwrap_or(0);
+            let js_col: usize = rest[line_len + 1..line_len + 1 + col_len].parse().unwrap_or(0);
+            //stack trace columns are 1-based
+            if let Some((l, c)) = self.lookup(script, js_line, js_col.saturating_sub(1)) {
+                return format!("{}{}:{}{}", &line[..start], l, c, &rest[line_len + 1 + col_len..]);
+            }
+        }
+        String::from(line)
+    }
+}
+
+#[test]
+fn test_ts2js(){
+    use std::io::Read;
+    let mut src = "".to_string();
+    let read_rst = std::fs::File::open("test.ts").unwrap().read_to_string(&mut src);
+    assert!(read_rst.is_ok());
+    println!("final js:{}", ts2js("main.ts", src.as_str()).unwrap().code);
+}
+
+#[test]
+fn test_ts2js_diagnostics(){
+    let diagnostics = ts2js("main.ts", "let a = 1\nlet b = ;\n").unwrap_err();
+    assert_eq!(diagnostics[0].file, "main.ts");
+    assert_eq!(diagnostics[0].line, 2);
+    assert!(diagnostics[0].column > 0);
+}
+
+#[test]
+fn test_source_maps(){
+    let out = ts2js("main.ts", "let a: number = 1\n\ninterface I { x: number }\nthrow new Error('x')\n").unwrap();
+    let js_line = out.code.lines().position(|l| l.contains("throw")).unwrap() + 1;
+    let mut maps = SourceMaps::default();
+    maps.add("main.ts", out.source_map.as_ref().unwrap());
+    assert_eq!(maps.lookup("main.ts", js_line, 0), Some((4, 1)));
+    assert_eq!(
+        maps.rewrite_stack(format!("Error: x\n    at main.ts:{}:1", js_line).as_str()),
+        "Error: x\n    at main.ts:4:1"
+    );
+}
+
+#[test]
+fn test_top_level_names() {
+    let js = "var a, b;\nlet s = f(1, [2, 3]), t = 'x,y';\n  let indented = 1;\nconst list = [];\nlet {\n    x,\n    y: [first, ...rest],\n    z = 2\n} = g();\nconst s2 = `\nlet inside = 1`;\nfunction f() {\n    let local = 1;\n}\n{\n    let block = 1;\n}\n";
+    let vars = top_level_names("main.js", js);
+    let names: Vec<&str> = vars.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(names, vec!["a", "b", "s", "t", "indented", "list", "x", "first", "rest", "z", "s2"]);
+    let consts: Vec<&str> = vars.iter().filter(|(_, is_const)| *is_const).map(|(n, _)| n.as_str()).collect();
+    assert_eq!(consts, vec!["list", "s2"]);
+    assert!(top_level_names("main.js", "let a = ;").is_empty());
+}
@@ -0,0 +1,1345 @@
+//the static python subset of makecode (main.py) to javascript.
+//makecode python spells the api in snake_case (controller.any_button.on_event) and enum members
+//in lower or upper case (SpriteKind.player, ControllerButtonEvent.PRESSED), mapped back here
+use std::collections::BTreeSet;
+
+use super::Diagnostic;
+
+const INDENT: &str = "    ";
+
+//namespaces whose members are enum values, written PascalCase on the js side
+const ENUMS: &[&str] = &["SpriteKind", "ControllerButtonEvent", "SpriteFlag", "ActionKind", "TileScale"];
+
+//python names that must not reach js as is
+const JS_RESERVED: &[&str] = &[
+    "case", "catch", "const", "debugger", "default", "delete", "do", "enum", "export", "extends", "function", "instanceof",
+    "let", "new", "switch", "this", "throw", "typeof", "var", "void", "with", "yield", "arguments", "eval",
+];
+
+//js operator precedence, what decides the parentheses
+const PREC_TERNARY: u8 = 2;
+const PREC_OR: u8 = 3;
+const PREC_AND: u8 = 4;
+const PREC_BIT_OR: u8 = 5;
+const PREC_BIT_XOR: u8 = 6;
+const PREC_BIT_AND: u8 = 7;
+const PREC_EQUALITY: u8 = 8;
+const PREC_RELATIONAL: u8 = 9;
+const PREC_SHIFT: u8 = 10;
+const PREC_ADDITIVE: u8 = 11;
+const PREC_MULTIPLICATIVE: u8 = 12;
+const PREC_POWER: u8 = 13;
+const PREC_UNARY: u8 = 14;
+const PREC_POSTFIX: u8 = 17;
+const PREC_PRIMARY: u8 = 18;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Name(String),
+    Num(String),
+    Str { value: String, fstring: bool },
+    Op(&'static str),
+    Newline,
+    Indent,
+    Dedent,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    line: usize,
+    column: usize,
+}
+
+//longest first
+const OPS: &[&str] = &[
+    "**=", "//=", ">>=", "<<=", "...", "->", "**", "//", "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "%=", "&=", "|=",
+    "^=", ">>", "<<", ":=", "+", "-", "*", "/", "%", "<", ">", "=", "(", ")", "[", "]", "{", "}", ",", ":", ".", ";", "@",
+    "&", "|", "^", "~",
+];
+
+type Error = (usize, usize, String);
+
+fn tokenize(src: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut toks = vec![];
+    let mut indents = vec![0usize];
+    let (mut i, mut line, mut line_start) = (0usize, 1usize, 0usize);
+    let mut depth = 0usize;
+    let mut at_line_start = true;
+    while i < chars.len() {
+        let column = i - line_start + 1;
+        if at_line_start && depth == 0 {
+            //measure the indent, blank and comment lines don't count
+            let mut width = 0;
+            let mut j = i;
+            while j < chars.len() && (chars[j] == ' ' || chars[j] == '\t') {
+                width += if chars[j] == '\t' { 8 - width % 8 } else { 1 };
+                j += 1;
+            }
+            if j >= chars.len() || chars[j] == '\n' || chars[j] == '\r' || chars[j] == '#' {
+                while j < chars.len() && chars[j] != '\n' {
+                    j += 1;
+                }
+                if j < chars.len() {
+                    line += 1;
+                    line_start = j + 1;
+                }
+                i = j + 1;
+                continue;
+            }
+            at_line_start = false;
+            let column = j - line_start + 1;
+            if width > *indents.last().unwrap() {
+                indents.push(width);
+                toks.push(Token { tok: Tok::Indent, line, column });
+            }
+            while width < *indents.last().unwrap() {
+                indents.pop();
+                if width > *indents.last().unwrap() {
+                    return Err((line, column, String::from("unindent does not match any outer indentation level")));
+                }
+                toks.push(Token { tok: Tok::Dedent, line, column });
+            }
+            i = j;
+            continue;
+        }
+        let c = chars[i];
+        match c {
+            '\n' => {
+                if depth == 0 {
+                    toks.push(Token { tok: Tok::Newline, line, column });
+                    at_line_start = true;
+                }
+                line += 1;
+                line_start = i + 1;
+                i += 1;
+            }
+            ' ' | '\t' | '\r' | '\x0c' => i += 1,
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '\\' if chars.get(i + 1) == Some(&'\n') || (chars.get(i + 1) == Some(&'\r') && chars.get(i + 2) == Some(&'\n')) => {
+                line += 1;
+                i += if chars[i + 1] == '\r' { 3 } else { 2 };
+                line_start = i;
+            }
+            _ if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).map_or(false, |d| d.is_ascii_digit())) => {
+                let start = i;
+                let hex = c == '0' && matches!(chars.get(i + 1), Some('x') | Some('X') | Some('b') | Some('B') | Some('o') | Some('O'));
+                if hex {
+                    i += 2;
+                }
+                while i < chars.len() {
+                    let d = chars[i];
+                    let exp_sign = !hex && (d == '+' || d == '-') && matches!(chars[i - 1], 'e' | 'E');
+                    if d.is_ascii_alphanumeric() || d == '_' || (d == '.' && !hex) || exp_sign {
+                        i += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let num: String = chars[start..i].iter().filter(|d| **d != '_').collect();
+                toks.push(Token { tok: Tok::Num(num), line, column });
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let prefix = word.to_lowercase();
+                let quote = chars.get(i).copied();
+                if prefix.len() <= 2 && prefix.chars().all(|p| "rbuf".contains(p)) && (quote == Some('"') || quote == Some('\'')) {
+                    let (value, next, lines, last_start) =
+                        read_string(&chars, i, prefix.contains('r')).ok_or((line, column, String::from("unterminated string")))?;
+                    toks.push(Token { tok: Tok::Str { value, fstring: prefix.contains('f') }, line, column });
+                    i = next;
+                    if lines > 0 {
+                        line += lines;
+                        line_start = last_start;
+                    }
+                } else {
+                    toks.push(Token { tok: Tok::Name(word), line, column });
+                }
+            }
+            '"' | '\'' => {
+                let (value, next, lines, last_start) =
+                    read_string(&chars, i, false).ok_or((line, column, String::from("unterminated string")))?;
+                toks.push(Token { tok: Tok::Str { value, fstring: false }, line, column });
+                i = next;
+                if lines > 0 {
+                    line += lines;
+                    line_start = last_start;
+                }
+            }
+            _ => {
+                let rest: String = chars[i..(i + 3).min(chars.len())].iter().collect();
+                let op = OPS
+                    .iter()
+                    .find(|op| rest.starts_with(**op))
+                    .ok_or((line, column, format!("unexpected character {:?}", c)))?;
+                match *op {
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                toks.push(Token { tok: Tok::Op(op), line, column });
+                i += op.chars().count();
+            }
+        }
+    }
+    let column = i - line_start + 1;
+    if !at_line_start {
+        toks.push(Token { tok: Tok::Newline, line, column });
+    }
+    for _ in 1..indents.len() {
+        toks.push(Token { tok: Tok::Dedent, line, column });
+    }
+    toks.push(Token { tok: Tok::Eof, line, column });
+    Ok(toks)
+}
+
+//the string starting at the quote at i: (value, index after it, newlines in it, start of its last line)
+fn read_string(chars: &[char], i: usize, raw: bool) -> Option<(String, usize, usize, usize)> {
+    let quote = chars[i];
+    let triple = chars.get(i + 1) == Some(&quote) && chars.get(i + 2) == Some(&quote);
+    let mut j = if triple { i + 3 } else { i + 1 };
+    let mut value = String::new();
+    let (mut lines, mut last_start) = (0, 0);
+    loop {
+        let c = *chars.get(j)?;
+        if c == quote && (!triple || (chars.get(j + 1) == Some(&quote) && chars.get(j + 2) == Some(&quote))) {
+            return Some((value, if triple { j + 3 } else { j + 1 }, lines, last_start));
+        }
+        if c == '\n' {
+            if !triple {
+                return None;
+            }
+            lines += 1;
+            last_start = j + 1;
+        }
+        if c == '\\' && !raw {
+            let e = *chars.get(j + 1)?;
+            j += 2;
+            match e {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                '0' => value.push('\0'),
+                '\n' => {
+                    lines += 1;
+                    last_start = j;
+                }
+                'x' | 'u' => {
+                    let n = if e == 'x' { 2 } else { 4 };
+                    let code: String = chars.get(j..j + n)?.iter().collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                    j += n;
+                }
+                _ => value.push(e),
+            }
+            continue;
+        }
+        value.push(c);
+        j += 1;
+    }
+}
+
+//any_button -> anyButton, _private_name -> _privateName
+fn camel_case(name: &str) -> String {
+    let lead = name.len() - name.trim_start_matches('_').len();
+    let mut out = String::from(&name[..lead]);
+    for (i, part) in name[lead..].split('_').enumerate() {
+        if i == 0 || part.is_empty() {
+            out.push_str(part);
+        } else {
+            let mut cs = part.chars();
+            out.extend(cs.next().map(|c| c.to_ascii_uppercase()));
+            out.push_str(cs.as_str());
+        }
+    }
+    if name.ends_with('_') && lead < name.len() {
+        out.push('_');
+    }
+    out
+}
+
+//player -> Player, AUTO_DESTROY -> AutoDestroy, Enemy -> Enemy
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .filter(|p| !p.is_empty())
+        .map(|part| {
+            let upper = part.chars().all(|c| !c.is_lowercase());
+            let mut cs = part.chars();
+            let first = cs.next().map(|c| c.to_ascii_uppercase()).into_iter();
+            let rest: String = if upper { cs.as_str().to_lowercase() } else { String::from(cs.as_str()) };
+            first.chain(rest.chars()).collect::<String>()
+        })
+        .collect()
+}
+
+fn js_name(name: &str) -> String {
+    if JS_RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        String::from(name)
+    }
+}
+
+fn js_string(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
+//tag functions of makecode, img("""...""") is img`...`
+fn template_literal(s: &str) -> String {
+    format!("`{}`", s.replace('\\', "\\\\").replace('`', "\\`").replace("${", "\\${"))
+}
+
+//names assigned in a function (or the module), declared with let at its top
+#[derive(Default)]
+struct Scope {
+    assigned: BTreeSet<String>,
+    globals: BTreeSet<String>,
+    params: BTreeSet<String>,
+    in_class_method: bool,
+}
+
+struct Parser {
+    toks: Vec<Token>,
+    pos: usize,
+    scopes: Vec<Scope>,
+    enums: BTreeSet<String>,
+    diagnostics: Vec<Diagnostic>,
+    file: String,
+    //an `in` test was emitted, the output needs IN_HELPER
+    uses_in: bool,
+}
+
+//python's `in`: membership for lists and substrings for strings, keys for everything else
+const IN_HELPER: &str = "function _py_in(x, y) {
+    return Array.isArray(y) || typeof y == 'string' ? y.indexOf(x) >= 0 : x in y;
+}
+";
+
+type Expr = (String, u8);
+
+fn wrap(e: Expr, prec: u8) -> String {
+    if e.1 < prec {
+        format!("({})", e.0)
+    } else {
+        e.0
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.toks[self.pos].tok
+    }
+
+    fn peek_at(&self, n: usize) -> &Tok {
+        &self.toks[(self.pos + n).min(self.toks.len() - 1)].tok
+    }
+
+    fn next(&mut self) -> Tok {
+        let t = self.toks[self.pos].tok.clone();
+        if self.pos < self.toks.len() - 1 {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Tok::Op(o) if *o == op)
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Tok::Name(n) if n == name)
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if self.is_op(op) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_name(&mut self, name: &str) -> bool {
+        if self.is_name(name) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, Error> {
+        let t = &self.toks[self.pos];
+        Err((t.line, t.column, message))
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), Error> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", op))
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<String, Error> {
+        match self.peek().clone() {
+            Tok::Name(n) => {
+                self.next();
+                Ok(n)
+            }
+            _ => self.error(String::from("expected a name")),
+        }
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn assign_name(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        if !scope.globals.contains(name) && !scope.params.contains(name) {
+            scope.assigned.insert(js_name(name));
+        }
+    }
+
+    //skip the statement a diagnostic was reported on
+    fn recover(&mut self, e: Error) {
+        self.diagnostics.push(Diagnostic { file: self.file.clone(), line: e.0, column: e.1, message: e.2 });
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Tok::Eof => return,
+                Tok::Newline if depth == 0 => {
+                    self.next();
+                    if matches!(self.peek(), Tok::Indent) {
+                        depth += 1;
+                        self.next();
+                        continue;
+                    }
+                    return;
+                }
+                Tok::Dedent if depth > 0 => {
+                    depth -= 1;
+                    self.next();
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                Tok::Dedent => return,
+                _ => {
+                    self.next();
+                }
+            }
+        }
+    }
+
+    //statements until the dedent closing the block, or the end
+    fn statements(&mut self, indent: usize) -> String {
+        let mut out = String::new();
+        loop {
+            match self.peek() {
+                Tok::Eof | Tok::Dedent => return out,
+                Tok::Newline => {
+                    self.next();
+                }
+                _ => match self.statement(indent) {
+                    Ok(s) => out.push_str(&s),
+                    Err(e) => self.recover(e),
+                },
+            }
+        }
+    }
+
+    //after the ':', an indented block or statements on the same line
+    fn block(&mut self, indent: usize) -> Result<String, Error> {
+        self.expect_op(":")?;
+        if matches!(self.peek(), Tok::Newline) {
+            self.next();
+            if !matches!(self.peek(), Tok::Indent) {
+                return self.error(String::from("expected an indented block"));
+            }
+            self.next();
+            let body = self.statements(indent + 1);
+            if matches!(self.peek(), Tok::Dedent) {
+                self.next();
+            }
+            Ok(body)
+        } else {
+            self.simple_statements(indent + 1)
+        }
+    }
+
+    fn end_of_statement(&mut self) -> Result<(), Error> {
+        match self.peek() {
+            Tok::Newline => {
+                self.next();
+                Ok(())
+            }
+            Tok::Eof | Tok::Dedent => Ok(()),
+            _ => self.error(String::from("expected end of statement")),
+        }
+    }
+
+    fn simple_statements(&mut self, indent: usize) -> Result<String, Error> {
+        let mut out = String::new();
+        loop {
+            out.push_str(&self.simple_statement(indent)?);
+            if !self.eat_op(";") || matches!(self.peek(), Tok::Newline | Tok::Eof) {
+                break;
+            }
+        }
+        self.end_of_statement()?;
+        Ok(out)
+    }
+
+    fn statement(&mut self, indent: usize) -> Result<String, Error> {
+        let pad = INDENT.repeat(indent);
+        let keyword = match self.peek() {
+            Tok::Name(n) => n.clone(),
+            Tok::Op("@") => String::from("@"),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "if" => {
+                self.next();
+                let mut out = format!("{}if ({}) {{\n{}{}}}", pad, self.expr()?.0, self.block(indent)?, pad);
+                loop {
+                    if self.eat_name("elif") {
+                        out.push_str(&format!(" else if ({}) {{\n{}{}}}", self.expr()?.0, self.block(indent)?, pad));
+                    } else if self.eat_name("else") {
+                        out.push_str(&format!(" else {{\n{}{}}}", self.block(indent)?, pad));
+                        break;
+                    } else {
+                        break;
+                    }
+                }
+                Ok(out + "\n")
+            }
+            "while" => {
+                self.next();
+                let cond = self.expr()?.0;
+                Ok(format!("{}while ({}) {{\n{}{}}}\n", pad, cond, self.block(indent)?, pad))
+            }
+            "for" => {
+                self.next();
+                let target = self.expect_name()?;
+                self.assign_name(&target);
+                let target = js_name(&target);
+                if !self.eat_name("in") {
+                    return self.error(String::from("expected 'in'"));
+                }
+                let head = if self.is_name("range") && matches!(self.peek_at(1), Tok::Op("(")) {
+                    self.next();
+                    self.next();
+                    let args = self.args()?;
+                    let (start, end, step) = match args.len() {
+                        1 => (String::from("0"), args[0].clone(), None),
+                        2 => (args[0].clone(), args[1].clone(), None),
+                        3 => (args[0].clone(), args[1].clone(), Some(args[2].clone())),
+                        _ => return self.error(String::from("range takes 1 to 3 arguments")),
+                    };
+                    match step {
+                        None => format!("{t} = {}; {t} < {}; {t}++", start, end, t = target),
+                        Some(step) if step.starts_with('-') => format!("{t} = {}; {t} > {}; {t} += {}", start, end, step, t = target),
+                        Some(step) => format!("{t} = {}; {t} < {}; {t} += {}", start, end, step, t = target),
+                    }
+                } else {
+                    format!("{} of {}", target, self.expr()?.0)
+                };
+                Ok(format!("{}for ({}) {{\n{}{}}}\n", pad, head, self.block(indent)?, pad))
+            }
+            "def" => self.function(indent, None, false),
+            "class" => self.class(indent, false),
+            "@" => {
+                self.next();
+                let decorator = self.expect_name()?;
+                self.end_of_statement()?;
+                match decorator.as_str() {
+                    "namespace" if self.is_name("class") => self.class(indent, true),
+                    _ => self.statement(indent),
+                }
+            }
+            _ => self.simple_statements(indent),
+        }
+    }
+
+    fn simple_statement(&mut self, indent: usize) -> Result<String, Error> {
+        let pad = INDENT.repeat(indent);
+        let keyword = match self.peek() {
+            Tok::Name(n) => n.clone(),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "pass" => {
+                self.next();
+                Ok(String::new())
+            }
+            "break" | "continue" => {
+                self.next();
+                Ok(format!("{}{};\n", pad, keyword))
+            }
+            "return" => {
+                self.next();
+                if matches!(self.peek(), Tok::Newline | Tok::Eof | Tok::Dedent | Tok::Op(";")) {
+                    Ok(format!("{}return;\n", pad))
+                } else {
+                    Ok(format!("{}return {};\n", pad, self.expr_list()?.0))
+                }
+            }
+            "global" | "nonlocal" => {
+                self.next();
+                loop {
+                    let name = js_name(&self.expect_name()?);
+                    self.scope().globals.insert(name.clone());
+                    self.scope().assigned.remove(&name);
+                    if keyword == "global" {
+                        self.scopes[0].assigned.insert(name);
+                    }
+                    if !self.eat_op(",") {
+                        break;
+                    }
+                }
+                Ok(String::new())
+            }
+            //the api is global already
+            "import" | "from" => {
+                while !matches!(self.peek(), Tok::Newline | Tok::Eof | Tok::Op(";")) {
+                    self.next();
+                }
+                Ok(String::new())
+            }
+            _ => {
+                let start = self.pos;
+                let (first, _) = self.expr_list()?;
+                //annotated assignment, x: int = 1
+                if self.is_op(":") {
+                    self.next();
+                    self.expr()?;
+                    if !self.is_op("=") {
+                        self.declare_targets(start);
+                        return Ok(String::new());
+                    }
+                }
+                if self.is_op("=") {
+                    let mut targets = vec![first];
+                    let mut value = String::new();
+                    while self.eat_op("=") {
+                        let next_start = self.pos;
+                        value = self.expr_list()?.0;
+                        targets.push(value.clone());
+                        if self.is_op("=") {
+                            self.declare_targets(next_start);
+                        }
+                    }
+                    targets.pop();
+                    self.declare_targets(start);
+                    return Ok(format!("{}{} = {};\n", pad, targets.join(" = "), value));
+                }
+                if let Tok::Op(op) = self.peek().clone() {
+                    if op.len() >= 2 && op.ends_with('=') && !matches!(op, "==" | "!=" | "<=" | ">=") {
+                        self.next();
+                        self.declare_targets(start);
+                        let value = self.expr()?;
+                        return Ok(match op {
+                            "//=" => format!("{}{} = Math.floor({} / {});\n", pad, first, first, wrap(value, PREC_MULTIPLICATIVE + 1)),
+                            _ => format!("{}{} {} {};\n", pad, first, op, value.0),
+                        });
+                    }
+                }
+                Ok(format!("{}{};\n", pad, first))
+            }
+        }
+    }
+
+    //plain names assigned by the statement starting at start
+    fn declare_targets(&mut self, start: usize) {
+        let mut i = start;
+        let mut depth = 0;
+        let mut names = vec![];
+        while i < self.toks.len() {
+            match &self.toks[i].tok {
+                Tok::Op("=") | Tok::Op(":") if depth == 0 => break,
+                Tok::Op(op) if op.len() >= 2 && op.ends_with('=') && depth == 0 && !matches!(*op, "==" | "!=" | "<=" | ">=") => break,
+                Tok::Op("(") | Tok::Op("[") | Tok::Op("{") => depth += 1,
+                Tok::Op(")") | Tok::Op("]") | Tok::Op("}") => depth -= 1,
+                Tok::Name(n) if depth == 0 => {
+                    let after_dot = i > start && matches!(self.toks[i - 1].tok, Tok::Op("."));
+                    let before_access = matches!(self.toks.get(i + 1).map(|t| &t.tok), Some(Tok::Op(".")) | Some(Tok::Op("[")) | Some(Tok::Op("(")));
+                    if !after_dot && !before_access {
+                        names.push(n.clone());
+                    }
+                }
+                Tok::Newline | Tok::Eof => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        for n in names {
+            self.assign_name(&n);
+        }
+    }
+
+    fn params(&mut self, method: bool) -> Result<Vec<String>, Error> {
+        self.expect_op("(")?;
+        let mut params = vec![];
+        while !self.eat_op(")") {
+            let rest = self.eat_op("*");
+            let name = self.expect_name()?;
+            if self.eat_op(":") {
+                self.expr()?;
+            }
+            let default = if self.eat_op("=") { Some(self.expr()?.0) } else { None };
+            if !(method && params.is_empty() && name == "self" && self.scope().in_class_method) {
+                self.scope().params.insert(name.clone());
+            }
+            let name = if method && name == "self" { name } else { js_name(&name) };
+            params.push(match (rest, default) {
+                (true, _) => format!("...{}", name),
+                (false, Some(d)) => format!("{} = {}", name, d),
+                (false, None) => name,
+            });
+            if !self.eat_op(",") {
+                self.expect_op(")")?;
+                break;
+            }
+        }
+        if method && params.first().map(|p| p.as_str()) == Some("self") {
+            params.remove(0);
+        }
+        Ok(params)
+    }
+
+    //class_name is Some for methods
+    fn function(&mut self, indent: usize, class_name: Option<&str>, is_static: bool) -> Result<String, Error> {
+        let pad = INDENT.repeat(indent);
+        self.next();
+        let name = self.expect_name()?;
+        if class_name.is_none() && self.scopes.len() > 1 {
+            self.assign_name(&name);
+        }
+        self.scopes.push(Scope { in_class_method: class_name.is_some() && !is_static, ..Scope::default() });
+        let params = match self.params(class_name.is_some() && !is_static) {
+            Ok(p) => p,
+            Err(e) => {
+                self.scopes.pop();
+                return Err(e);
+            }
+        };
+        if self.eat_op("->") {
+            self.expr()?;
+        }
+        let body = self.block(indent);
+        let scope = self.scopes.pop().unwrap();
+        let body = body?;
+        let locals = if scope.assigned.is_empty() {
+            String::new()
+        } else {
+            format!("{}{}let {};\n", pad, INDENT, scope.assigned.iter().cloned().collect::<Vec<_>>().join(", "))
+        };
+        let head = match class_name {
+            Some(_) if name == "__init__" => String::from("constructor"),
+            Some(_) if is_static => format!("static {}", camel_case(&name)),
+            Some(_) => camel_case(&name),
+            None if self.scopes.len() > 1 => format!("{} = function", js_name(&name)),
+            None => format!("function {}", js_name(&name)),
+        };
+        let end = if class_name.is_none() && self.scopes.len() > 1 { ";" } else { "" };
+        Ok(format!("{}{}({}) {{\n{}{}{}}}{}\n", pad, head, params.join(", "), locals, body, pad, end))
+    }
+
+    //Enum subclasses and @namespace classes add members to a (maybe existing) object,
+    //others become js classes
+    fn class(&mut self, indent: usize, namespace: bool) -> Result<String, Error> {
+        let pad = INDENT.repeat(indent);
+        self.next();
+        let name = self.expect_name()?;
+        let mut base = None;
+        if self.eat_op("(") {
+            if !self.is_op(")") {
+                base = Some(self.expr()?.0);
+            }
+            self.expect_op(")")?;
+        }
+        if self.scopes.len() == 1 {
+            self.scope().assigned.remove(&name);
+        }
+        let is_enum = namespace || base.as_deref().map_or(false, |b| b == "Enum" || b.ends_with(".Enum"));
+        self.expect_op(":")?;
+        if !matches!(self.peek(), Tok::Newline) || !matches!(self.peek_at(1), Tok::Indent) {
+            return self.error(String::from("expected an indented class body"));
+        }
+        self.next();
+        self.next();
+        if is_enum {
+            self.enums.insert(name.clone());
+        }
+        let mut members = String::new();
+        let mut statics = String::new();
+        let mut is_static = false;
+        loop {
+            match self.peek().clone() {
+                Tok::Dedent | Tok::Eof => {
+                    self.next();
+                    break;
+                }
+                Tok::Newline => {
+                    self.next();
+                }
+                Tok::Op("@") => {
+                    self.next();
+                    is_static = self.expect_name()? == "staticmethod";
+                    self.end_of_statement()?;
+                }
+                Tok::Name(n) if n == "def" && !is_enum => {
+                    match self.function(indent + 1, Some(&name), is_static) {
+                        Ok(m) => members.push_str(&m),
+                        Err(e) => self.recover(e),
+                    }
+                    is_static = false;
+                }
+                Tok::Name(n) if n == "pass" => {
+                    self.next();
+                    self.end_of_statement()?;
+                }
+                Tok::Name(member) if matches!(self.peek_at(1), Tok::Op("=") | Tok::Op(":")) => {
+                    self.next();
+                    if self.eat_op(":") {
+                        self.expr()?;
+                    }
+                    self.expect_op("=")?;
+                    let value = self.expr()?.0;
+                    self.end_of_statement()?;
+                    if is_enum {
+                        let member = pascal_case(&member);
+                        statics.push_str(&format!("{}{}.{} = {};\n", pad, name, member, value));
+                        //reverse mapping of numbered members, as typescript enums have
+                        if value.parse::<f64>().is_ok() {
+                            statics.push_str(&format!("{}{}[{}] = {};\n", pad, name, value, js_string(&member)));
+                        }
+                    } else {
+                        statics.push_str(&format!("{}{}.{} = {};\n", pad, name, camel_case(&member), value));
+                    }
+                }
+                _ => {
+                    let e = self.error::<()>(String::from("only methods and fields are supported in a class body"));
+                    self.recover(e.unwrap_err());
+                }
+            }
+        }
+        if is_enum {
+            self.scopes[0].assigned.insert(name.clone());
+            return Ok(format!("{p}{n} = typeof {n} == 'undefined' ? {{}} : {n};\n{}", statics, p = pad, n = name));
+        }
+        let extends = base.map(|b| format!(" extends {}", b)).unwrap_or_default();
+        Ok(format!("{}class {}{} {{\n{}{}}}\n{}", pad, name, extends, members, pad, statics))
+    }
+
+    fn args(&mut self) -> Result<Vec<String>, Error> {
+        let mut args = vec![];
+        while !self.eat_op(")") {
+            if matches!(self.peek(), Tok::Name(_)) && matches!(self.peek_at(1), Tok::Op("=")) {
+                return self.error(String::from("keyword arguments are not supported"));
+            }
+            let spread = self.eat_op("*");
+            let arg = self.expr()?.0;
+            args.push(if spread { format!("...{}", arg) } else { arg });
+            if !self.eat_op(",") {
+                self.expect_op(")")?;
+                break;
+            }
+        }
+        Ok(args)
+    }
+
+    //a, b as a js array, single expressions as is
+    fn expr_list(&mut self) -> Result<(String, bool), Error> {
+        let first = self.expr()?;
+        if !self.is_op(",") {
+            return Ok((first.0, false));
+        }
+        let mut items = vec![first.0];
+        while self.eat_op(",") {
+            if matches!(self.peek(), Tok::Newline | Tok::Eof | Tok::Op("=") | Tok::Op(")")) {
+                break;
+            }
+            items.push(self.expr()?.0);
+        }
+        Ok((format!("[{}]", items.join(", ")), true))
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        if self.eat_name("lambda") {
+            let mut params = vec![];
+            self.scopes.push(Scope::default());
+            while !self.is_op(":") {
+                let name = self.expect_name()?;
+                self.scope().params.insert(name.clone());
+                params.push(js_name(&name));
+                if !self.eat_op(",") {
+                    break;
+                }
+            }
+            let body = self.expect_op(":").and_then(|_| self.expr());
+            self.scopes.pop();
+            return Ok((format!("({}) => {}", params.join(", "), wrap(body?, PREC_TERNARY)), PREC_TERNARY - 1));
+        }
+        let value = self.or_expr()?;
+        if self.eat_name("if") {
+            let cond = self.or_expr()?;
+            if !self.eat_name("else") {
+                return self.error(String::from("expected 'else'"));
+            }
+            let other = self.expr()?;
+            return Ok((
+                format!("{} ? {} : {}", wrap(cond, PREC_OR), wrap(value, PREC_TERNARY), wrap(other, PREC_TERNARY)),
+                PREC_TERNARY,
+            ));
+        }
+        Ok(value)
+    }
+
+    fn or_expr(&mut self) -> Result<Expr, Error> {
+        let mut left = self.and_expr()?;
+        while self.eat_name("or") {
+            let right = self.and_expr()?;
+            left = (format!("{} || {}", wrap(left, PREC_OR), wrap(right, PREC_OR + 1)), PREC_OR);
+        }
+        Ok(left)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, Error> {
+        let mut left = self.not_expr()?;
+        while self.eat_name("and") {
+            let right = self.not_expr()?;
+            left = (format!("{} && {}", wrap(left, PREC_AND), wrap(right, PREC_AND + 1)), PREC_AND);
+        }
+        Ok(left)
+    }
+
+    fn not_expr(&mut self) -> Result<Expr, Error> {
+        if self.eat_name("not") {
+            let e = self.not_expr()?;
+            return Ok((format!("!{}", wrap(e, PREC_UNARY)), PREC_UNARY));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, Error> {
+        let mut left = self.binary(0)?;
+        loop {
+            let (op, prec) = match self.peek() {
+                Tok::Op(op @ ("<" | ">" | "<=" | ">=")) => (*op, PREC_RELATIONAL),
+                Tok::Op("==") => ("===", PREC_EQUALITY),
+                Tok::Op("!=") => ("!==", PREC_EQUALITY),
+                Tok::Name(n) if n == "is" => {
+                    self.next();
+                    let op = if self.eat_name("not") { "!==" } else { "===" };
+                    let right = self.binary(0)?;
+                    left = (format!("{} {} {}", wrap(left, PREC_EQUALITY), op, wrap(right, PREC_EQUALITY + 1)), PREC_EQUALITY);
+                    continue;
+                }
+                Tok::Name(n) if n == "in" || (n == "not" && matches!(self.peek_at(1), Tok::Name(m) if m == "in")) => {
+                    let negate = n == "not";
+                    self.next();
+                    if negate {
+                        self.next();
+                    }
+                    let right = self.binary(0)?;
+                    self.uses_in = true;
+                    let test = format!("_py_in({}, {})", left.0, right.0);
+                    left = if negate { (format!("!{}", test), PREC_UNARY) } else { (test, PREC_POSTFIX) };
+                    continue;
+                }
+                _ => return Ok(left),
+            };
+            self.next();
+            let right = self.binary(0)?;
+            left = (format!("{} {} {}", wrap(left, prec), op, wrap(right, prec + 1)), prec);
+        }
+    }
+
+    //| ^ & << >> + - * / // % by precedence level
+    fn binary(&mut self, level: usize) -> Result<Expr, Error> {
+        const LEVELS: &[(&[&str], u8)] = &[
+            (&["|"], PREC_BIT_OR),
+            (&["^"], PREC_BIT_XOR),
+            (&["&"], PREC_BIT_AND),
+            (&["<<", ">>"], PREC_SHIFT),
+            (&["+", "-"], PREC_ADDITIVE),
+            (&["*", "/", "//", "%"], PREC_MULTIPLICATIVE),
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let (ops, prec) = LEVELS[level];
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Tok::Op(op) if ops.contains(op) => *op,
+                _ => return Ok(left),
+            };
+            self.next();
+            let right = self.binary(level + 1)?;
+            //python rounds down, there is no integer division in js
+            left = if op == "//" {
+                (format!("Math.floor({} / {})", wrap(left, prec), wrap(right, prec + 1)), PREC_POSTFIX)
+            } else {
+                (format!("{} {} {}", wrap(left, prec), op, wrap(right, prec + 1)), prec)
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        let op = match self.peek() {
+            Tok::Op(op @ ("-" | "+" | "~")) => *op,
+            _ => return self.power(),
+        };
+        self.next();
+        let e = self.unary()?;
+        Ok((format!("{}{}", op, wrap(e, PREC_UNARY)), PREC_UNARY))
+    }
+
+    fn power(&mut self) -> Result<Expr, Error> {
+        let base = self.postfix()?;
+        if self.eat_op("**") {
+            let exp = self.unary()?;
+            return Ok((format!("{} ** {}", wrap(base, PREC_POSTFIX), wrap(exp, PREC_POWER)), PREC_POWER));
+        }
+        Ok(base)
+    }
+
+    fn postfix(&mut self) -> Result<Expr, Error> {
+        let mut e = self.atom()?;
+        //what e is, for mapping the builtins and the enums
+        let mut name = match &self.toks[self.pos - 1].tok {
+            Tok::Name(n) => Some(n.clone()),
+            _ => None,
+        };
+        loop {
+            if self.eat_op(".") {
+                let attr = self.expect_name()?;
+                e = match (e.0.as_str(), attr.as_str()) {
+                    ("super", "__init__") => e,
+                    _ if !self.is_op("(") && name.as_deref().map_or(false, |n| self.enums.contains(n) || ENUMS.contains(&n)) => {
+                        (format!("{}.{}", e.0, pascal_case(&attr)), PREC_POSTFIX)
+                    }
+                    (_, "append") => (format!("{}.push", wrap(e, PREC_POSTFIX)), PREC_POSTFIX),
+                    _ => (format!("{}.{}", wrap(e, PREC_POSTFIX), camel_case(&attr)), PREC_POSTFIX),
+                };
+                name = Some(format!("{}.{}", name.unwrap_or_default(), attr));
+            } else if self.eat_op("(") {
+                let start = self.pos;
+                let args = self.args()?;
+                let single_string = self.pos == start + 2 && matches!(&self.toks[start].tok, Tok::Str { fstring: false, .. });
+                e = match (name.as_deref(), args.len()) {
+                    (Some("len"), 1) => (format!("{}.length", args[0]), PREC_POSTFIX),
+                    (Some("str"), 1) => (format!("String({})", args[0]), PREC_POSTFIX),
+                    (Some("int"), 1) => (format!("Math.trunc({})", args[0]), PREC_POSTFIX),
+                    (Some("float"), 1) => (format!("Number({})", args[0]), PREC_POSTFIX),
+                    (Some("abs"), 1) => (format!("Math.abs({})", args[0]), PREC_POSTFIX),
+                    (Some("min"), _) => (format!("Math.min({})", args.join(", ")), PREC_POSTFIX),
+                    (Some("max"), _) => (format!("Math.max({})", args.join(", ")), PREC_POSTFIX),
+                    (Some("print"), _) => (format!("_log([{}].join(' '))", args.join(", ")), PREC_POSTFIX),
+                    (Some("super"), 0) => (String::from("super"), PREC_PRIMARY),
+                    (Some("img" | "hex" | "tilemap" | "assets.image" | "assets.tile" | "assets.animation" | "assets.tilemap"), 1)
+                        if single_string =>
+                    {
+                        let value = match &self.toks[start].tok {
+                            Tok::Str { value, .. } => value.clone(),
+                            _ => unreachable!(),
+                        };
+                        (format!("{}{}", e.0, template_literal(&value)), PREC_POSTFIX)
+                    }
+                    _ => (format!("{}({})", wrap(e, PREC_POSTFIX), args.join(", ")), PREC_POSTFIX),
+                };
+                name = None;
+            } else if self.eat_op("[") {
+                let index = self.expr()?;
+                if self.is_op(":") {
+                    self.next();
+                    let end = if self.is_op("]") { None } else { Some(self.expr()?.0) };
+                    self.expect_op("]")?;
+                    e = (format!("{}.slice({}{})", wrap(e, PREC_POSTFIX), index.0, end.map(|x| format!(", {}", x)).unwrap_or_default()), PREC_POSTFIX);
+                } else {
+                    self.expect_op("]")?;
+                    e = (format!("{}[{}]", wrap(e, PREC_POSTFIX), index.0), PREC_POSTFIX);
+                }
+                name = None;
+            } else {
+                return Ok(e);
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, Error> {
+        if matches!(self.peek(), Tok::Newline | Tok::Indent | Tok::Dedent | Tok::Eof) {
+            return self.error(String::from("expected an expression"));
+        }
+        match self.next() {
+            Tok::Num(n) => Ok((n, PREC_PRIMARY)),
+            Tok::Str { value, fstring } => {
+                let mut s = if fstring { self.fstring(&value)? } else { js_string(&value) };
+                let mut prec = PREC_PRIMARY;
+                //adjacent literals concatenate
+                while let Tok::Str { value, fstring } = self.peek().clone() {
+                    self.next();
+                    s = format!("{} + {}", s, if fstring { self.fstring(&value)? } else { js_string(&value) });
+                    prec = PREC_ADDITIVE;
+                }
+                Ok((s, prec))
+            }
+            Tok::Name(n) => Ok(match n.as_str() {
+                "True" => (String::from("true"), PREC_PRIMARY),
+                "False" => (String::from("false"), PREC_PRIMARY),
+                "None" => (String::from("null"), PREC_PRIMARY),
+                "self" if self.scopes.iter().any(|s| s.in_class_method) => (String::from("this"), PREC_PRIMARY),
+                _ => (js_name(&n), PREC_PRIMARY),
+            }),
+            Tok::Op("(") => {
+                if self.eat_op(")") {
+                    return Ok((String::from("[]"), PREC_PRIMARY));
+                }
+                let e = self.expr()?;
+                if self.is_op(",") {
+                    let mut items = vec![e.0];
+                    while self.eat_op(",") && !self.is_op(")") {
+                        items.push(self.expr()?.0);
+                    }
+                    self.expect_op(")")?;
+                    return Ok((format!("[{}]", items.join(", ")), PREC_PRIMARY));
+                }
+                self.expect_op(")")?;
+                Ok((format!("({})", e.0), PREC_PRIMARY))
+            }
+            Tok::Op("[") => {
+                let mut items = vec![];
+                while !self.eat_op("]") {
+                    items.push(self.expr()?.0);
+                    if self.is_name("for") {
+                        return self.error(String::from("list comprehensions are not supported"));
+                    }
+                    if !self.eat_op(",") {
+                        self.expect_op("]")?;
+                        break;
+                    }
+                }
+                Ok((format!("[{}]", items.join(", ")), PREC_PRIMARY))
+            }
+            Tok::Op("{") => {
+                let mut items = vec![];
+                while !self.eat_op("}") {
+                    let key = self.expr()?.0;
+                    self.expect_op(":")?;
+                    let value = self.expr()?.0;
+                    items.push(format!("[{}]: {}", key, value));
+                    if !self.eat_op(",") {
+                        self.expect_op("}")?;
+                        break;
+                    }
+                }
+                Ok((format!("{{ {} }}", items.join(", ")), PREC_PRIMARY))
+            }
+            _ => {
+                self.pos -= 1;
+                self.error(String::from("expected an expression"))
+            }
+        }
+    }
+
+    //f"score {x}" -> `score ${x}`
+    fn fstring(&mut self, s: &str) -> Result<String, Error> {
+        let (line, column) = (self.toks[self.pos - 1].line, self.toks[self.pos - 1].column);
+        let mut out = String::from("`");
+        let chars: Vec<char> = s.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '{' if chars.get(i + 1) == Some(&'{') => {
+                    out.push('{');
+                    i += 2;
+                }
+                '}' if chars.get(i + 1) == Some(&'}') => {
+                    out.push('}');
+                    i += 2;
+                }
+                '{' => {
+                    let end = i + chars[i..].iter().position(|c| *c == '}').ok_or((line, column, String::from("unclosed { in f-string")))?;
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    //drop the conversion and the format spec
+                    let inner = inner.split(['!', ':']).next().unwrap_or("");
+                    let toks = tokenize(inner).map_err(|e| (line, column, e.2))?;
+                    let mut sub = Parser { toks, pos: 0, scopes: vec![Scope::default()], enums: self.enums.clone(), diagnostics: vec![], file: self.file.clone(), uses_in: false };
+                    sub.scopes[0].in_class_method = self.scopes.iter().any(|s| s.in_class_method);
+                    let e = sub.expr().map_err(|e| (line, column, e.2))?;
+                    self.uses_in |= sub.uses_in;
+                    out.push_str(&format!("${{{}}}", e.0));
+                    i = end + 1;
+                }
+                '`' | '\\' => {
+                    out.push('\\');
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                '$' if chars.get(i + 1) == Some(&'{') => {
+                    out.push_str("\\$");
+                    i += 1;
+                }
+                c => {
+                    out.push(c);
+                    i += 1;
+                }
+            }
+        }
+        out.push('`');
+        Ok(out)
+    }
+}
+
+pub fn py2js(name: &str, src: &str) -> Result<String, Vec<Diagnostic>> {
+    let diagnostic = |e: Error| Diagnostic { file: String::from(name), line: e.0, column: e.1, message: e.2 };
+    let toks = tokenize(src).map_err(|e| vec![diagnostic(e)])?;
+    let mut parser = Parser {
+        toks,
+        pos: 0,
+        scopes: vec![Scope::default()],
+        enums: BTreeSet::new(),
+        diagnostics: vec![],
+        file: String::from(name),
+        uses_in: false,
+    };
+    let mut body = parser.statements(0);
+    while !matches!(parser.peek(), Tok::Eof) {
+        let e = parser.error::<()>(String::from("unexpected unindent"));
+        parser.recover(e.unwrap_err());
+        body.push_str(&parser.statements(0));
+    }
+    if !parser.diagnostics.is_empty() {
+        return Err(parser.diagnostics);
+    }
+    if parser.uses_in {
+        body = format!("{}{}", IN_HELPER, body);
+    }
+    let globals = &parser.scopes[0].assigned;
+    if globals.is_empty() {
+        return Ok(body);
+    }
+    //var, so enum objects merge with the ones binding.js defines
+    Ok(format!("var {};\n{}", globals.iter().cloned().collect::<Vec<_>>().join(", "), body))
+}
+
+#[test]
+fn test_py2js() {
+    let py = r#"
+@namespace
+class SpriteKind:
+    Enemy = SpriteKind.create()
+class ActionKind(Enum):
+    Walking = 0
+
+def on_a_pressed():
+    global score
+    score += 1
+    info.change_score_by(1)
+controller.any_button.on_event(ControllerButtonEvent.PRESSED, on_a_pressed)
+
+score = 0
+my_sprite: Sprite = sprites.create(img("""
+    . 1
+    """), SpriteKind.player)
+enemies = []
+for i in range(3):
+    enemies.append(sprites.create(img("""2"""), SpriteKind.Enemy))
+if len(enemies) > 2 and not my_sprite is None:
+    scene.set_background_color(9)
+elif score // 2 == 1:
+    pass
+else:
+    print(f"score {score + 1}!")
+game.on_update(lambda: game.over(True) if score > 10 else None)
+"#;
+    let js = py2js("main.py", py).unwrap();
+    assert_eq!(
+        js,
+        r#"var ActionKind, SpriteKind, enemies, i, my_sprite, score;
+SpriteKind = typeof SpriteKind == 'undefined' ? {} : SpriteKind;
+SpriteKind.Enemy = SpriteKind.create();
+ActionKind = typeof ActionKind == 'undefined' ? {} : ActionKind;
+ActionKind.Walking = 0;
+ActionKind[0] = "Walking";
+function on_a_pressed() {
+    score += 1;
+    info.changeScoreBy(1);
+}
+controller.anyButton.onEvent(ControllerButtonEvent.Pressed, on_a_pressed);
+score = 0;
+my_sprite = sprites.create(img`
+    . 1
+    `, SpriteKind.Player);
+enemies = [];
+for (i = 0; i < 3; i++) {
+    enemies.push(sprites.create(img`2`, SpriteKind.Enemy));
+}
+if (enemies.length > 2 && !(my_sprite === null)) {
+    scene.setBackgroundColor(9);
+} else if (Math.floor(score / 2) === 1) {
+} else {
+    _log([`score ${score + 1}!`].join(' '));
+}
+game.onUpdate(() => score > 10 ? game.over(true) : null);
+"#
+    );
+}
+
+#[test]
+fn test_py2js_class() {
+    let py = "class Ship(Base):\n    count = 0\n    def __init__(self, speed = 2):\n        super().__init__()\n        self.top_speed = speed\n        n = speed * 2\n    def boost(self, *more):\n        return self.top_speed ** 2\n";
+    let js = py2js("main.py", py).unwrap();
+    assert_eq!(
+        js,
+        "class Ship extends Base {\n    constructor(speed = 2) {\n        let n;\n        super();\n        this.topSpeed = speed;\n        n = speed * 2;\n    }\n    boost(...more) {\n        return this.topSpeed ** 2;\n    }\n}\nShip.count = 0;\n"
+    );
+}
+
+#[test]
+fn test_py2js_floor_division() {
+    let js = py2js("main.py", "a = 7 // (1 + 1)\nb = 2 * a // 3\na //= b + 1\n").unwrap();
+    assert_eq!(
+        js,
+        "var a, b;\na = Math.floor(7 / (1 + 1));\nb = Math.floor(2 * a / 3);\na = Math.floor(a / (b + 1));\n"
+    );
+}
+
+#[test]
+fn test_py2js_in() {
+    let js = py2js("main.py", "d = {\"a\": 1}\nif \"a\" in d and 2 not in [1, 3]:\n    pass\n").unwrap();
+    assert_eq!(
+        js,
+        format!("var d;\n{}d = {{ [\"a\"]: 1 }};\nif (_py_in(\"a\", d) && !_py_in(2, [1, 3])) {{\n}}\n", IN_HELPER)
+    );
+    assert!(!py2js("main.py", "for i in range(3):\n    pass\n").unwrap().contains("_py_in"));
+}
+
+#[test]
+fn test_py2js_line_continuation() {
+    assert_eq!(py2js("main.py", "a = 1 + \\\r\n    2\r\nb = a\r\n").unwrap(), "var a, b;\na = 1 + 2;\nb = a;\n");
+    assert_eq!(py2js("main.py", "a = 1 + \\\n    2\n").unwrap(), "var a;\na = 1 + 2;\n");
+}
+
+#[test]
+fn test_py2js_errors() {
+    let errors = py2js("main.py", "x = [i for i in range(3)]\nif x:\n  y = f(a=1)\n  z = 1\nw = )\n").unwrap_err();
+    let lines: Vec<usize> = errors.iter().map(|d| d.line).collect();
+    assert_eq!(lines, vec![1, 3, 5]);
+    assert!(errors[0].message.contains("comprehensions"));
+    assert_eq!(errors[1].file, "main.py");
+}
@@ -1,133 +1,133 @@
 use core::time;
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
-use v8::Local;
 
 //scene lifecycle managed by rust. it's auto created at game start/restart
 //sprite lifecycle managed by rust. because it is added to scene after creating. refer by usize from js
 //js bitmap is just a js string. after set to scene/sprite/animation, bitmap data(color\width\height) is cloned, not referenced, so the bitmap in js may GCed.
 //animation in rust side. refer by usize from js
+//every Runtime has its own isolate, the game state lives in a slot of it so the js callbacks find
+//the state of the runtime they run in. runtimes sharing a thread must be dropped in reverse creation order
 use crate::{
     emulator::{
         self,
         game::{BMP_HEIGHT, BMP_WIDTH},
         resource::Bitmap,
     },
+    emulator::savestate::{Reader, StateError, Writer},
     engine::{self, Event},
+    transpile::{self, SourceMaps},
 };
 
-//struct DROP in filed declare order
-pub struct Runtime {}
+//game state of one runtime, recreated on reset
+pub struct State {
+    pub scene: emulator::scene::Scene,
+    pub info: emulator::info::Info<'static>,
+    pub game: emulator::game::Game,
+    //assets of the running project, looked up by name from js
+    pub assets: emulator::asset::AssetTable,
+    //behind Math.random and the scene effects, seeded by run_project
+    pub rng: emulator::effect::Rng,
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            scene: emulator::scene::Scene::new(Rc::new(RefCell::new(emulator::resource::FrameMgr {
+                frames: HashMap::new(),
+            }))),
+            info: emulator::info::Info::new(),
+            game: emulator::game::Game::new(),
+            assets: emulator::asset::AssetTable::default(),
+            rng: emulator::effect::Rng::new(emulator::effect::time_seed()),
+        }
+    }
+}
+
+type StateRef = Rc<RefCell<State>>;
+
+//the state of the runtime owning the isolate a callback runs in
+fn state(isolate: &v8::Isolate) -> StateRef {
+    isolate.get_slot::<StateRef>().expect("isolate without runtime state").clone()
+}
+
+//struct DROP in filed declare order, the isolate last
+pub struct Runtime {
+    source_maps: SourceMaps,
+    context: Option<v8::Global<v8::Context>>,
+    template: v8::Global<v8::ObjectTemplate>,
+    state: StateRef,
+    //Some in deterministic mode, every run of a project starts the generator from it
+    seed: Option<u64>,
+    isolate: v8::OwnedIsolate,
+}
 
 static V8_INIT: std::sync::Once = std::sync::Once::new();
-static mut V8_ISOLATE: Option<v8::OwnedIsolate> = None;
-static mut V8_CONTEXT_TMPL: Option<Local<v8::ObjectTemplate>> = None;
-static mut V8_TOP_SCOPE: Option<v8::HandleScope<'static, ()>> = None;
-static mut V8_CONTEXT: Option<Local<v8::Context>> = None;
-static mut V8_CONTEXT_SCOPE: Option<v8::ContextScope<'static, v8::HandleScope<'static>>> = None;
-//TODO: bind SCENE\INFO to a global v8 object?
-static mut SCENE: Option<emulator::scene::Scene> = None;
-static mut INFO: Option<emulator::info::Info> = None;
-static mut GAME: Option<emulator::game::Game> = None;
 static BINDING_SRC: &[u8]=std::include_bytes!("binding.js");
+static STATE_MAGIC: &[u8; 4] = b"MKSS";
+const STATE_VERSION: u32 = 2;
 
 macro_rules! add_fn {
-    ($obj:ident, $fn:ident) => {
-        $obj.set(
-            v8::String::new(V8_TOP_SCOPE.as_mut().unwrap(), stringify!($fn))
-                .unwrap()
-                .into(),
-            v8::FunctionTemplate::new(V8_TOP_SCOPE.as_mut().unwrap(), $fn).into(),
-        );
+    ($scope:ident, $obj:ident, $fn:ident) => {
+        let name = v8::String::new($scope, stringify!($fn)).unwrap();
+        let function = v8::FunctionTemplate::new($scope, $fn);
+        $obj.set(name.into(), function.into());
     };
 }
 
-/*
-module.set(
-    v8::String::new(V8_TOP_SCOPE.as_mut().unwrap(), "sprite_set_x")
-        .unwrap()
-        .into(),
-    v8::FunctionTemplate::new(V8_TOP_SCOPE.as_mut().unwrap(), |scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut _retval: v8::ReturnValue|{
-        let idx = args.get(0).int32_value(scope).unwrap() as usize;
-        let value = args.get(1).number_value(scope).unwrap() as f32;
-        let sprite = SCENE.as_mut().unwrap().get_mut(idx);
-        sprite.x = value.into();
-    }).into(),
-);
-*/
-
 macro_rules! add_sprite_prority {
-    ($obj:ident, $p:ident) => {
-        $obj.set(
-            v8::String::new(
-                V8_TOP_SCOPE.as_mut().unwrap(),
-                std::concat!("sprite_set_", stringify!($p)),
-            )
-            .unwrap()
-            .into(),
-            v8::FunctionTemplate::new(
-                V8_TOP_SCOPE.as_mut().unwrap(),
-                |scope: &mut v8::HandleScope,
-                 args: v8::FunctionCallbackArguments,
-                 mut _retval: v8::ReturnValue| {
-                    let idx = args.get(0).int32_value(scope).unwrap() as usize;
-                    let value = args.get(1).number_value(scope).unwrap() as f32;
-                    println!("set property {} of sprite {idx} to {value}", stringify!($p));
-                    if value == f32::NAN {
-                        //TODO: throw exception
-                        return;
-                    }
-                    let sprite = SCENE.as_mut().unwrap().get_mut(idx);
-                    sprite.$p = value.into();
-                },
-            )
-            .into(),
+    ($scope:ident, $obj:ident, $p:ident) => {
+        let name = v8::String::new($scope, std::concat!("sprite_set_", stringify!($p))).unwrap();
+        let function = v8::FunctionTemplate::new(
+            $scope,
+            |scope: &mut v8::HandleScope,
+             args: v8::FunctionCallbackArguments,
+             mut _retval: v8::ReturnValue| {
+                let idx = args.get(0).int32_value(scope).unwrap() as usize;
+                let value = args.get(1).number_value(scope).unwrap() as f32;
+                println!("set property {} of sprite {idx} to {value}", stringify!($p));
+                if value == f32::NAN {
+                    //TODO: throw exception
+                    return;
+                }
+                let state = state(scope);
+                let mut state = state.borrow_mut();
+                let sprite = state.scene.get_mut(idx);
+                sprite.$p = value.into();
+            },
         );
-
-        $obj.set(
-            v8::String::new(
-                V8_TOP_SCOPE.as_mut().unwrap(),
-                std::concat!("sprite_get_", stringify!($p)),
-            )
-            .unwrap()
-            .into(),
-            v8::FunctionTemplate::new(
-                V8_TOP_SCOPE.as_mut().unwrap(),
-                |scope: &mut v8::HandleScope,
-                 args: v8::FunctionCallbackArguments,
-                 mut _retval: v8::ReturnValue| {
-                    let idx = args.get(0).int32_value(scope).unwrap() as usize;
-                    let sprite = SCENE.as_ref().unwrap().get(idx);
-                    _retval.set(v8::Number::new(scope, sprite.$p as f64).into());
-                },
-            )
-            .into(),
+        $obj.set(name.into(), function.into());
+
+        let name = v8::String::new($scope, std::concat!("sprite_get_", stringify!($p))).unwrap();
+        let function = v8::FunctionTemplate::new(
+            $scope,
+            |scope: &mut v8::HandleScope,
+             args: v8::FunctionCallbackArguments,
+             mut _retval: v8::ReturnValue| {
+                let idx = args.get(0).int32_value(scope).unwrap() as usize;
+                let value = state(scope).borrow().scene.get(idx).$p as f64;
+                _retval.set(v8::Number::new(scope, value).into());
+            },
         );
+        $obj.set(name.into(), function.into());
     };
 }
 
-impl Drop for Runtime {
-    fn drop(&mut self) {
-        println!("drop engine Runtime()");
-        unsafe {
-            SCENE = None;
-            V8_CONTEXT_SCOPE = None;
-            V8_CONTEXT = None;
-        }
-    }
+//a handle scope in the current context of the runtime, named $scope
+macro_rules! context_scope {
+    ($self:ident, $scope:ident) => {
+        let $scope = &mut v8::HandleScope::new(&mut $self.isolate);
+        let context = v8::Local::new($scope, $self.context.as_ref().unwrap());
+        let $scope = &mut v8::ContextScope::new($scope, context);
+        let $scope = &mut v8::HandleScope::new($scope);
+    };
 }
 
-fn v8_get_global<'s>(name: &str) -> v8::Local<'s, v8::Value> {
-    unsafe {
-        let scope = V8_CONTEXT_SCOPE.as_mut().unwrap();
-        match V8_CONTEXT.as_ref().unwrap().global(scope).get(
-            V8_CONTEXT_SCOPE.as_mut().unwrap(),
-            v8::String::new(V8_CONTEXT_SCOPE.as_mut().unwrap(), name)
-                .unwrap()
-                .into(),
-        ) {
-            None => v8::undefined(scope).into(),
-            Some(v) => v,
-        }
+fn v8_get_global<'s>(scope: &mut v8::HandleScope<'s>, name: &str) -> v8::Local<'s, v8::Value> {
+    let global = scope.get_current_context().global(scope);
+    let key = v8::String::new(scope, name).unwrap();
+    match global.get(scope, key.into()) {
+        None => v8::undefined(scope).into(),
+        Some(v) => v,
     }
 }
 
@@ -147,158 +147,340 @@ fn v8_get_i32(scope: &mut v8::HandleScope, obj: v8::Local<v8::Value>) -> i32 {
     }
 }
 
+//the globals of every context: _engine with the rust functions, and _log
+fn context_template<'s>(scope: &mut v8::HandleScope<'s, ()>) -> v8::Local<'s, v8::ObjectTemplate> {
+    let module = v8::ObjectTemplate::new(scope);
+    add_fn!(scope, module, scene_set_effect);
+    add_fn!(scope, module, scene_set_background_color);
+    add_fn!(scope, module, scene_add_sprite);
+    add_fn!(scope, module, scene_add_overlap_check_kinds);
+
+    add_fn!(scope, module, scene_set_tilemap);
+
+    add_fn!(scope, module, sprite_kind_create);
+
+    add_fn!(scope, module, image_of_buffer);
+    add_fn!(scope, module, asset_image);
+    add_fn!(scope, module, asset_animation);
+    add_fn!(scope, module, asset_tilemap);
+
+    add_fn!(scope, module, animation_add);
+    add_fn!(scope, module, animation_add_frame);
+
+    add_fn!(scope, module, info_set_score);
+    add_fn!(scope, module, info_change_score);
+
+    add_fn!(scope, module, sprite_active_action);
+    add_fn!(scope, module, sprite_set_flag);
+    add_fn!(scope, module, sprite_set_bound);
+    add_fn!(scope, module, sprite_get_bound);
+
+    add_fn!(scope, module, game_over);
+    add_fn!(scope, module, random);
+
+    add_sprite_prority!(scope, module, x);
+    add_sprite_prority!(scope, module, y);
+    add_sprite_prority!(scope, module, ax);
+    add_sprite_prority!(scope, module, ay);
+    add_sprite_prority!(scope, module, vx);
+    add_sprite_prority!(scope, module, vy);
+    add_sprite_prority!(scope, module, fx);
+    add_sprite_prority!(scope, module, fy);
+    add_sprite_prority!(scope, module, sx);
+    add_sprite_prority!(scope, module, sy);
+
+    let global = v8::ObjectTemplate::new(scope);
+    let name = v8::String::new(scope, "_engine").unwrap();
+    global.set(name.into(), module.into());
+    let name = v8::String::new(scope, "_log").unwrap();
+    let log = v8::FunctionTemplate::new(scope, js_Log);
+    global.set(name.into(), log.into());
+    global
+}
+
 impl Runtime {
     fn reset_context(&mut self) {
-        unsafe {
-            //drop in reverse order.or panic
-            V8_CONTEXT_SCOPE = None;
-
-            let context_ = v8::Context::new_from_template(
-                V8_TOP_SCOPE.as_mut().unwrap(),
-                V8_CONTEXT_TMPL.unwrap(),
-            );
-            V8_CONTEXT = Some(context_);
-            let context = V8_CONTEXT.as_mut().unwrap();
-
-            let context_scope_ = v8::ContextScope::new(V8_TOP_SCOPE.as_mut().unwrap(), *context);
-            V8_CONTEXT_SCOPE = Some(context_scope_);
+        self.context = None;
+        let scope = &mut v8::HandleScope::new(&mut self.isolate);
+        let template = v8::Local::new(scope, &self.template);
+        let context = v8::Context::new_from_template(scope, template);
+        self.context = Some(v8::Global::new(scope, context));
+    }
+
+    //call the js function of a global, exceptions are reported, not returned
+    fn call_global(&mut self, name: &str, args: &[CallArg]) {
+        if self.context.is_none() {
+            return;
+        }
+        context_scope!(self, scope);
+        let cb = v8_get_global(scope, name);
+        if !cb.is_function() {
+            return;
+        }
+        let cb = v8::Local::<v8::Function>::try_from(cb).unwrap();
+        let try_catch = &mut v8::TryCatch::new(scope);
+        let udf: v8::Local<v8::Value> = v8::undefined(try_catch).into();
+        let args: Vec<v8::Local<v8::Value>> = args
+            .iter()
+            .map(|arg| match arg {
+                CallArg::Number(n) => v8::Number::new(try_catch, *n).into(),
+                CallArg::Str(s) => v8::String::new(try_catch, s).unwrap().into(),
+            })
+            .collect();
+        if cb.call(try_catch, udf, &args).is_none() {
+            println!("{}", report_exceptions(try_catch, &self.source_maps));
         }
     }
 
-    pub fn process_events(&self, evt: engine::Event) {
+    pub fn process_events(&mut self, evt: engine::Event) {
+        if self.state.borrow().game.over {
+            if let Event::KeyDown(key) = evt {
+                self.state.borrow_mut().game.key_down(key);
+            }
+            return;
+        }
         let msg = match evt {
             Event::KeyDown(_) | Event::KeyUp(_) | Event::KeyRepeat(_) => evt.to_string(),
             _ => {
                 return;
             }
         };
-
-        let cb = v8_get_global("_221149842913key_events_cb");
-        if cb.is_function() {
-            let loop_cb = v8::Local::<v8::Function>::try_from(cb).unwrap();
-            let mut scope = v8::HandleScope::new(unsafe { V8_CONTEXT_SCOPE.as_mut().unwrap() });
-            let mut try_catch = v8::TryCatch::new(&mut scope);
-            let udf: v8::Local<v8::Value> = v8::undefined(&mut try_catch).into();
-            let msg_str = v8::String::new(&mut try_catch, msg.as_str()).unwrap();
-            match loop_cb.call(&mut try_catch, udf, &[msg_str.into()]) {
-                None => {
-                    println!("{}", report_exceptions(&mut try_catch));
-                }
-                _ => {}
-            };
-        }
+        self.call_global("_221149842913key_events_cb", &[CallArg::Str(msg)]);
     }
 
     pub fn draw(&self, canvas: &mut emulator::resource::Canvas) {
-        unsafe {
-            SCENE.as_ref().unwrap().draw(canvas);
-            INFO.as_ref().unwrap().draw(canvas);
+        let state = self.state.borrow();
+        state.scene.draw(canvas);
+        state.info.draw(canvas);
+        state.game.draw(canvas);
+    }
+
+    pub fn palette(&self) -> emulator::resource::Palette {
+        self.state.borrow().scene.palette.clone()
+    }
+
+    pub fn score(&self) -> i32 {
+        self.state.borrow().info.score()
+    }
+
+    //A was pressed on the game over screen, the game should start again
+    pub fn restart_requested(&self) -> bool {
+        self.state.borrow().game.restart
+    }
+
+    //Some(win) after game.over()
+    pub fn game_over(&self) -> Option<bool> {
+        let state = self.state.borrow();
+        if state.game.over {
+            Some(state.game.win)
+        } else {
+            None
         }
     }
 
+    //once the game is over only the game over screen moves on.
+    //js gets the milli seconds with their fraction, nothing of micro_sec is dropped
     pub fn update(&mut self, micro_sec: u64) {
-        if unsafe { GAME.as_ref().unwrap().over } {
+        let dt = time::Duration::from_micros(micro_sec);
+        if self.state.borrow().game.over {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+            state.game.update(&dt, &mut state.rng);
             return;
         }
-        {
-            let cb = v8_get_global("_221149842913game_loop");
-            if cb.is_function() {
-                let loop_cb = v8::Local::<v8::Function>::try_from(cb).unwrap();
-                let mut scope = v8::HandleScope::new(unsafe { V8_CONTEXT_SCOPE.as_mut().unwrap() });
-                let mut try_catch = v8::TryCatch::new(&mut scope);
-                let udf: v8::Local<v8::Value> = v8::undefined(&mut try_catch).into();
-                let dt = v8::Number::new(&mut try_catch, (micro_sec / 1000 ) as f64);
-                match loop_cb.call(&mut try_catch, udf, &[dt.into()]) {
-                    None => {
-                        println!("{}", report_exceptions(&mut try_catch));
-                    }
-                    _ => {}
-                };
-            }
-        };
+        self.call_global("_221149842913game_loop", &[CallArg::Number(micro_sec as f64 / 1000.0)]);
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        state.scene.update(&dt, &mut state.rng);
+    }
+
+    //the high score stays, unload forgets it
+    pub fn reset(&mut self) {
+        let high_score = self.state.borrow().game.high_score;
+        *self.state.borrow_mut() = State::new();
+        self.state.borrow_mut().game.high_score = high_score;
+        self.reset_context();
+        self.run_script_as("binding.js", &String::from(std::str::from_utf8(BINDING_SRC).unwrap()));
+    }
+
+    //drop the game: its state, and its context with every object the scripts made. nothing runs
+    //until run_project, which starts from a fresh context
+    pub fn unload(&mut self) {
+        *self.state.borrow_mut() = State::new();
+        self.context = None;
+        self.source_maps.clear();
+        self.isolate.low_memory_notification();
+    }
 
-        unsafe {
-            let dt = time::Duration::from_millis(micro_sec / 1000);
-            SCENE.as_mut().unwrap().update(&dt);
+    pub fn process_overlap_check(&mut self) {
+        let overlaps = self.state.borrow().scene.check_overlaps();
+        for overlap in overlaps {
+            let args: Vec<CallArg> = [overlap.0, overlap.1, overlap.2, overlap.3]
+                .iter()
+                .map(|v| CallArg::Number(*v as u32 as f64))
+                .collect();
+            self.call_global("_221149842913overlap_cb", &args);
         }
     }
 
-    pub fn reset(&mut self) {
-        unsafe {
-            SCENE = None;
-            INFO = None;
-            GAME = None;
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    pub fn run_project(&mut self, prj: &engine::Project) {
+        if self.context.is_none() {
+            self.reset();
+        }
+        {
+            let mut state = self.state.borrow_mut();
+            state.rng = emulator::effect::Rng::new(self.seed.unwrap_or_else(emulator::effect::time_seed));
+            state.assets = prj.assets.clone();
+            state.scene.palette = prj.palette.clone();
+        }
+        self.source_maps.clear();
+        for (name, map) in prj.source_maps.iter() {
+            self.source_maps.add(name, map);
+        }
+        for (name, script) in prj.scripts() {
+            self.run_script_as(name, script);
         }
+        self.register_vars(prj);
+    }
 
-        self.reset_context();
-        //TODO: load binding.js
-        let scene =
-            emulator::scene::Scene::new(Rc::new(RefCell::new(emulator::resource::FrameMgr {
-                frames: HashMap::new(),
-            })));
-        unsafe {
-            GAME = Some(emulator::game::Game::new());
-            INFO = Some(emulator::info::Info::new());
-            SCENE = Some(scene);            
+    //a getter and a setter for every variable the scripts declare at their top level, the data
+    //of the scripts save states keep. binding.js holds them until the context is dropped
+    fn register_vars(&mut self, prj: &engine::Project) {
+        let mut vars: Vec<(String, bool)> = vec![];
+        for (name, script) in prj.scripts() {
+            for var in transpile::top_level_names(name, script) {
+                if !vars.iter().any(|(n, _)| *n == var.0) {
+                    vars.push(var);
+                }
+            }
         }
-        self.run_script(&String::from(std::str::from_utf8(BINDING_SRC).unwrap()));
+        let entries: Vec<String> = vars
+            .iter()
+            .map(|(name, is_const)| {
+                let set = if *is_const {
+                    format!("v => _221149842913assign({}, v)", name)
+                } else {
+                    format!("v => {} = v", name)
+                };
+                format!("{:?}: [() => {}, {}]", name, name, set)
+            })
+            .collect();
+        self.run_script_as("(register state)", &format!("_221149842913register_state({{{}}})", entries.join(", ")));
     }
 
-    pub fn process_overlap_check(&self) {
-        let overlaps = unsafe { SCENE.as_ref().unwrap().check_overlaps() };
-        if overlaps.len() == 0 {
-            return;
+    pub fn run_script(&mut self, script_content: &String) {
+        self.run_script_as("(unknown)", script_content);
+    }
+
+    //name is reported as the script resource name, so exceptions can be mapped back to the source
+    pub fn run_script_as(&mut self, name: &str, script_content: &String) {
+        if let Err(e) = self.eval(name, script_content) {
+            println!("{}", e);
         }
-        let overlap_cb = {
-            let cb = v8_get_global("_221149842913overlap_cb");
-            if cb.is_function() {
-                v8::Local::<v8::Function>::try_from(cb).unwrap()
-            } else {
-                return;
+    }
+
+    //the completion value of the script as a string, or the report of its exception
+    fn eval(&mut self, name: &str, script_content: &str) -> Result<String, String> {
+        if self.context.is_none() {
+            return Err(String::from("no game loaded"));
+        }
+        context_scope!(self, scope);
+        let script = v8::String::new(scope, script_content).unwrap();
+        let resource_name = v8::String::new(scope, name).unwrap();
+        let source_map_url = v8::undefined(scope);
+        let origin = v8::ScriptOrigin::new(
+            scope,
+            resource_name.into(),
+            0,
+            0,
+            false,
+            0,
+            source_map_url.into(),
+            false,
+            false,
+            false,
+        );
+        let try_catch = &mut v8::TryCatch::new(scope);
+        let script = match v8::Script::compile(try_catch, script, Some(&origin)) {
+            Some(s) => s,
+            None => {
+                return Err(format!("compile failed!\n{}", report_exceptions(try_catch, &self.source_maps)));
             }
         };
 
-        let mut scope = v8::HandleScope::new(unsafe { V8_CONTEXT_SCOPE.as_mut().unwrap() });
-        let mut try_catch = v8::TryCatch::new(&mut scope);
-        let udf: v8::Local<v8::Value> = v8::undefined(&mut try_catch).into();
-        for overlap in overlaps {
-            let mut args: Vec<v8::Local<v8::Value>> = vec![];
-            for v in [overlap.0, overlap.1, overlap.2, overlap.3].iter() {
-                args.push(v8::Integer::new_from_unsigned(&mut try_catch, *v as u32).into());
-            }
-            match overlap_cb.call(&mut try_catch, udf, &args) {
-                None => {
-                    println!("{}", report_exceptions(&mut try_catch));
-                }
-                _ => {}
-            };
+        match script.run(try_catch) {
+            None => Err(format!("run failed!\n{}", report_exceptions(try_catch, &self.source_maps))),
+            Some(value) => Ok(value
+                .to_string(try_catch)
+                .map(|v| v.to_rust_string_lossy(try_catch))
+                .unwrap_or_default()),
         }
     }
 
-    pub fn run_script(&self, script_content: &String) {
-        unsafe {
-            let script =
-                v8::String::new(V8_CONTEXT_SCOPE.as_mut().unwrap(), script_content.as_str())
-                    .unwrap();
-            let mut scope = v8::HandleScope::new(V8_CONTEXT_SCOPE.as_mut().unwrap());
-            let mut try_catch = v8::TryCatch::new(&mut scope);
-            let script = match v8::Script::compile(&mut try_catch, script, None) {
-                Some(s) => s,
-                None => {
-                    println!("compile failed!");
-                    println!("{}", report_exceptions(&mut try_catch));
-                    return;
-                }
-            };
+    //a save state: the rust side of the game as is, and the data in the variables of its scripts
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let js = self.eval("(save state)", "_221149842913save_state()").unwrap_or_else(|e| {
+            println!("{}", e);
+            String::from("{}")
+        });
+        let mut w = Writer::default();
+        w.0.extend_from_slice(STATE_MAGIC);
+        w.u32(STATE_VERSION);
+        let state = self.state.borrow();
+        w.u64(state.rng.state());
+        state.scene.save(&mut w);
+        state.info.save(&mut w);
+        state.game.save(&mut w);
+        w.str(&js);
+        w.0
+    }
 
-            match script.run(&mut try_catch) {
-                None => {
-                    println!("run failed!");
-                    println!("{}", report_exceptions(&mut try_catch));
-                    return;
-                }
-                Some(_mod) => (),
-            }
+    //the functions of the game can't be saved, the state goes into the running context whose
+    //scripts made them, nothing of them runs again. the running game is kept if blob is not a valid state
+    pub fn restore_state(&mut self, blob: &[u8]) -> Result<(), StateError> {
+        if self.context.is_none() {
+            return Err(StateError::NoGame);
+        }
+        let mut r = Reader(blob);
+        if r.0.len() < STATE_MAGIC.len() || &r.0[..STATE_MAGIC.len()] != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        r.0 = &r.0[STATE_MAGIC.len()..];
+        let version = r.u32()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let rng = r.u64()?;
+        let scene = emulator::scene::Scene::load(&mut r)?;
+        let info = emulator::info::Info::load(&mut r)?;
+        let game = emulator::game::Game::load(&mut r)?;
+        let js = r.string()?;
+
+        {
+            let mut state = self.state.borrow_mut();
+            state.scene = scene;
+            state.info = info;
+            state.game = game;
+            state.rng = emulator::effect::Rng::new(rng);
+        }
+        let script = format!("_221149842913load_state({})", serde_json::Value::String(js));
+        self.eval("(load state)", &script).map(|_| ()).map_err(StateError::Js)
+    }
+
+    //the global as a string, for tests
+    #[cfg(test)]
+    fn global_string(&mut self, name: &str) -> Option<String> {
+        self.context.as_ref()?;
+        context_scope!(self, scope);
+        let value = v8_get_global(scope, name);
+        if value.is_undefined() {
+            None
+        } else {
+            Some(value.to_string(scope).unwrap().to_rust_string_lossy(scope))
         }
     }
 
@@ -307,70 +489,41 @@ impl Runtime {
             let platform = v8::new_default_platform(0, false).make_shared();
             v8::V8::initialize_platform(platform);
             v8::V8::initialize();
-
-            unsafe {
-                let isolate_ = v8::Isolate::new(v8::CreateParams::default());
-                V8_ISOLATE = Some(isolate_);
-                let isolate = V8_ISOLATE.as_mut().unwrap();
-
-                let top_scope_ = v8::HandleScope::new(isolate);
-                V8_TOP_SCOPE = Some(top_scope_);
-                let top_scope = V8_TOP_SCOPE.as_mut().unwrap();
-
-                let module = v8::ObjectTemplate::new(top_scope);
-                add_fn!(module, scene_set_effect);
-                add_fn!(module, scene_set_background_color);
-                add_fn!(module, scene_add_sprite);
-                add_fn!(module, scene_add_overlap_check_kinds);
-
-                add_fn!(module, sprite_kind_create);
-
-                add_fn!(module, animation_add);
-                add_fn!(module, animation_add_frame);
-
-                add_fn!(module, info_set_score);
-                add_fn!(module, info_change_score);
-
-                add_fn!(module, sprite_active_action);
-                add_fn!(module, sprite_set_flag);
-                add_fn!(module, sprite_set_bound);
-                add_fn!(module, sprite_get_bound);
-
-                add_fn!(module, game_over);
-
-                add_sprite_prority!(module, x);
-                add_sprite_prority!(module, y);
-                add_sprite_prority!(module, ax);
-                add_sprite_prority!(module, ay);
-                add_sprite_prority!(module, vx);
-                add_sprite_prority!(module, vy);
-                add_sprite_prority!(module, fx);
-                add_sprite_prority!(module, fy);
-                add_sprite_prority!(module, sx);
-                add_sprite_prority!(module, sy);
-
-                let global = v8::ObjectTemplate::new(top_scope);
-                global.set(
-                    v8::String::new(top_scope, "_engine").unwrap().into(),
-                    module.into(),
-                );
-                global.set(
-                    v8::String::new(top_scope, "_log").unwrap().into(),
-                    v8::FunctionTemplate::new(V8_TOP_SCOPE.as_mut().unwrap(), js_Log).into(),
-                );
-                V8_CONTEXT_TMPL = Some(global);
-
-                // let context_ = v8::Context::new_from_template(V8_TOP_SCOPE.as_mut().unwrap(), V8_CONTEXT_TMPL.unwrap());
-                // V8_CONTEXT = Some(context_);
-            }
         });
 
-        let mut self_ = Runtime {};
+        let mut isolate = v8::Isolate::new(v8::CreateParams::default());
+        let state = Rc::new(RefCell::new(State::new()));
+        isolate.set_slot(state.clone());
+        let template = {
+            let scope = &mut v8::HandleScope::new(&mut isolate);
+            let template = context_template(scope);
+            v8::Global::new(scope, template)
+        };
+
+        let mut self_ = Runtime {
+            source_maps: SourceMaps::default(),
+            context: None,
+            template,
+            state,
+            seed: None,
+            isolate,
+        };
         self_.reset();
         self_
     }
 }
 
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        println!("drop engine Runtime()");
+    }
+}
+
+enum CallArg {
+    Number(f64),
+    Str(String),
+}
+
 fn scene_add_overlap_check_kinds(
     scope: &mut v8::HandleScope,
     args: v8::FunctionCallbackArguments,
@@ -387,7 +540,7 @@ fn scene_add_overlap_check_kinds(
         v8_get_i32(scope, args.get(0)) as u32,
         v8_get_i32(scope, args.get(1)) as u32,
     );
-    unsafe { SCENE.as_mut().unwrap() }.add_overlap_detection(kind1 as usize, kind2 as usize);
+    state(scope).borrow_mut().scene.add_overlap_detection(kind1 as usize, kind2 as usize);
 }
 fn scene_add_sprite(
     scope: &mut v8::HandleScope,
@@ -406,10 +559,8 @@ fn scene_add_sprite(
     let kind = v8_get_i32(scope, args.get(1)) as usize;
     let bmp = emulator::resource::Bitmap::new_from_string_without_size(bmp_data.as_str());
     let sprite = emulator::sprite::Sprite::new_with_bmp(bmp, kind);
-    unsafe {
-        let sprite_ref = SCENE.as_mut().unwrap().add_sprite(sprite);
-        _retval.set(v8::Integer::new(scope, sprite_ref as i32).into());
-    }
+    let sprite_ref = state(scope).borrow_mut().scene.add_sprite(sprite);
+    _retval.set(v8::Integer::new(scope, sprite_ref as i32).into());
 }
 
 fn animation_add(
@@ -429,7 +580,9 @@ fn animation_add(
         return;
     }
 
-    unsafe { SCENE.as_ref().unwrap() }
+    state(scope)
+        .borrow()
+        .scene
         .frame_mgr
         .borrow_mut()
         .create(name.as_str(), interval);
@@ -449,7 +602,9 @@ fn animation_add_frame(
     }
     let frame = v8_get_string(scope, args.get(1));
     //println!("to add frame to animation {}", name);
-    unsafe { SCENE.as_ref().unwrap() }
+    state(scope)
+        .borrow()
+        .scene
         .frame_mgr
         .borrow_mut()
         .append(
@@ -465,7 +620,9 @@ fn sprite_active_action(
 ) {
     let idx = args.get(0).int32_value(scope).unwrap() as usize;
     let animation_name = v8_get_string(scope, args.get(1));
-    let sprite = unsafe { SCENE.as_mut().unwrap() }.get_mut(idx);
+    let state = state(scope);
+    let mut state = state.borrow_mut();
+    let sprite = state.scene.get_mut(idx);
     sprite.active_animation(animation_name);
 }
 
@@ -485,7 +642,9 @@ fn sprite_set_flag(
     let idx = args.get(0).int32_value(scope).unwrap() as usize;
     let flag = args.get(1).uint32_value(scope).unwrap() as u32;
     let true_false = args.get(1).int32_value(scope).unwrap();
-    let sprite = unsafe { SCENE.as_mut().unwrap() }.get_mut(idx);
+    let state = state(scope);
+    let mut state = state.borrow_mut();
+    let sprite = state.scene.get_mut(idx);
     sprite.set_flag(flag, true_false != 0);
 }
 
@@ -502,9 +661,26 @@ fn game_over(
         return;
     }
 
-    let game = unsafe { GAME.as_mut().unwrap() };
-    game.over = true;
-    game.win = v8_get_i32(scope, args.get(0)) == 1;
+    let win = v8_get_i32(scope, args.get(0)) == 1;
+    //the message and effect the game set for a win or a loss, empty for the default ones
+    let message = v8_get_string(scope, args.get(1));
+    let name = v8_get_string(scope, args.get(2));
+    let state = state(scope);
+    let mut state = state.borrow_mut();
+    let state = &mut *state;
+    let effect = emulator::effect::SceneEffect::by_name(&name, &mut state.rng);
+    let score = state.info.score();
+    state.game.over(win, score, message, effect, &mut state.rng);
+}
+
+//Math.random of the scripts
+fn random(
+    scope: &mut v8::HandleScope,
+    _args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    let value = state(scope).borrow_mut().rng.random();
+    _retval.set(v8::Number::new(scope, value).into());
 }
 
 fn sprite_set_bound(
@@ -522,7 +698,9 @@ fn sprite_set_bound(
     let idx = args.get(0).int32_value(scope).unwrap() as usize;
     let side = v8_get_string(scope, args.get(1));
     let value = args.get(2).number_value(scope).unwrap() as f32;
-    let sprite = unsafe { SCENE.as_mut().unwrap() }.get_mut(idx);
+    let state = state(scope);
+    let mut state = state.borrow_mut();
+    let sprite = state.scene.get_mut(idx);
     match side.as_str() {
         "top" => {
             sprite.set_top(value as i32);
@@ -554,7 +732,9 @@ fn sprite_get_bound(
     }
     let idx = args.get(0).int32_value(scope).unwrap() as usize;
     let side = v8_get_string(scope, args.get(1));
-    let sprite = unsafe { SCENE.as_mut().unwrap() }.get_mut(idx);
+    let state = state(scope);
+    let mut state = state.borrow_mut();
+    let sprite = state.scene.get_mut(idx);
     let value = match side.as_str() {
         "top" => sprite.top(),
         "bottom" => sprite.bottom(),
@@ -570,7 +750,8 @@ fn sprite_kind_create(
     _args: v8::FunctionCallbackArguments,
     mut _retval: v8::ReturnValue,
 ) {
-    _retval.set(v8::Integer::new(scope, emulator::sprite::sprite_kind::create() as i32).into());
+    let kind = state(scope).borrow_mut().scene.create_sprite_kind();
+    _retval.set(v8::Integer::new(scope, kind as i32).into());
 }
 
 fn info_set_score(
@@ -582,7 +763,8 @@ fn info_set_score(
         return;
     }
 
-    unsafe { INFO.as_mut().unwrap() }.set_score(v8_get_i32(scope, args.get(0)) as f32);
+    let score = v8_get_i32(scope, args.get(0));
+    state(scope).borrow_mut().info.set_score(score as f32);
 }
 
 fn info_change_score(
@@ -597,7 +779,7 @@ fn info_change_score(
 
     let v = v8_get_i32(scope, args.get(0));
     //println!("change score delta {}", v);
-    unsafe { INFO.as_mut().unwrap() }.change_score( v as f32);
+    state(scope).borrow_mut().info.change_score( v as f32);
 }
 
 fn js_Log(
@@ -621,15 +803,15 @@ fn scene_set_effect(
     }
 
     let str = v8_get_string(scope, args.get(0));
-    let eff = match str.as_str() {
-        "blizzard" => emulator::effect::SceneEffect::blizzard(),
-        _ => {
+    let state = state(scope);
+    let mut state = state.borrow_mut();
+    let eff = match emulator::effect::SceneEffect::by_name(&str, &mut state.rng) {
+        Some(eff) => eff,
+        None => {
             return;
         }
     };
-    unsafe {
-        SCENE.as_mut().unwrap().active_effect(eff);
-    }
+    state.scene.active_effect(eff);
 }
 
 fn scene_set_background_color(
@@ -651,12 +833,107 @@ fn scene_set_background_color(
         BMP_HEIGHT as usize,
         str.bytes().next().unwrap(),
     );
-    unsafe { SCENE.as_mut().unwrap().set_bgi(bg) }
+    state(scope).borrow_mut().scene.set_bgi(bg)
+}
+
+fn v8_string_array<'s>(scope: &mut v8::HandleScope<'s>, items: &[String]) -> v8::Local<'s, v8::Array> {
+    let elements: Vec<v8::Local<v8::Value>> = items
+        .iter()
+        .map(|s| v8::String::new(scope, s.as_str()).unwrap().into())
+        .collect();
+    v8::Array::new_with_elements(scope, &elements)
+}
+
+fn image_of_buffer(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    if args.length() == 0 || !args.get(0).is_string() {
+        return;
+    }
+    let hex = v8_get_string(scope, args.get(0));
+    if let Some(lit) = emulator::asset::from_hex(hex.as_str()).and_then(|b| emulator::asset::decode_image(&b)) {
+        _retval.set(v8::String::new(scope, lit.as_str()).unwrap().into());
+    }
+}
+
+fn asset_image(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    let name = v8_get_string(scope, args.get(0));
+    let lit = state(scope).borrow().assets.image(name.as_str()).cloned();
+    if let Some(lit) = lit {
+        _retval.set(v8::String::new(scope, lit.as_str()).unwrap().into());
+    }
+}
+
+fn asset_animation(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    let name = v8_get_string(scope, args.get(0));
+    let frames = state(scope).borrow().assets.animation(name.as_str()).map(|a| a.frames.clone());
+    if let Some(frames) = frames {
+        _retval.set(v8_string_array(scope, &frames).into());
+    }
+}
+
+//[data hex, tile images, scale], the arguments of tiles.createTilemap
+fn asset_tilemap(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    let name = v8_get_string(scope, args.get(0));
+    let state = state(scope);
+    let state = state.borrow();
+    let assets = &state.assets;
+    if let Some(tilemap) = assets.tilemap(name.as_str()) {
+        let tiles: Vec<String> = tilemap
+            .tileset
+            .iter()
+            .map(|id| assets.image(id).cloned().unwrap_or_default())
+            .collect();
+        let data = v8::String::new(scope, emulator::asset::to_hex(&tilemap.data()).as_str()).unwrap();
+        let tiles = v8_string_array(scope, &tiles);
+        let scale = v8::Integer::new(scope, tilemap.scale as i32);
+        _retval.set(v8::Array::new_with_elements(scope, &[data.into(), tiles.into(), scale.into()]).into());
+    }
+}
+
+fn scene_set_tilemap(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    if args.length() < 3 || !args.get(0).is_string() || !args.get(1).is_array() || !args.get(2).is_number() {
+        return;
+    }
+    let data = match emulator::asset::from_hex(v8_get_string(scope, args.get(0)).as_str()) {
+        Some(d) if d.len() >= 4 => d,
+        _ => return,
+    };
+    let width = u16::from_le_bytes([data[0], data[1]]) as usize;
+    let height = u16::from_le_bytes([data[2], data[3]]) as usize;
+    let tiles = v8::Local::<v8::Array>::try_from(args.get(1)).unwrap();
+    let mut tileset = vec![];
+    for i in 0..tiles.length() {
+        let tile = tiles.get_index(scope, i).unwrap();
+        tileset.push(Bitmap::new_from_string_without_size(v8_get_string(scope, tile).as_str()));
+    }
+    let tile_size = 1usize << (v8_get_i32(scope, args.get(2)) & 7);
+    if let Err(e) = state(scope).borrow_mut().scene.set_tilemap(width, height, &data[4..], &tileset, tile_size) {
+        println!("set tilemap: {}", e);
+    }
 }
 
 #[test]
 fn test_overlap_with_js() {
-    let runtime = Runtime::new();
+    let mut runtime = Runtime::new();
     runtime.run_script(&String::from(
         r"
 (function(THIZ){
@@ -682,20 +959,12 @@ THIZ.sp2=sp2;
 ",
     ));
     runtime.process_overlap_check();
-    assert!(v8_get_global("_test_result").is_undefined());
+    assert_eq!(runtime.global_string("_test_result"), None);
     runtime.run_script(&String::from(
         r"sp2.y=sp2.y+1;",
     ));
     runtime.process_overlap_check();
-    let result = v8_get_global("_test_result");
-    assert!(result.is_string());
-    let str = unsafe {
-        result
-            .to_string(V8_CONTEXT_SCOPE.as_mut().unwrap())
-            .unwrap()
-            .to_rust_string_lossy(V8_CONTEXT_SCOPE.as_mut().unwrap())
-    };
-    assert_eq!(String::from(str), "true");
+    assert_eq!(runtime.global_string("_test_result").as_deref(), Some("true"));
 }
 
 #[test]
@@ -703,21 +972,101 @@ fn test_runtime() {
     let mut runtime = Runtime::new();
     let mut canvas = emulator::resource::Canvas::new();
     runtime.run_script(&String::from("_engine.scene_set_background_color('b')"));
-    unsafe {
-        let scene = SCENE.as_mut().unwrap();
-        scene.draw(&mut canvas);
-        assert_eq!(canvas.get_pixel(13, 22), emulator::resource::COLORS[0xb]);
-    }
+    runtime.state.borrow().scene.draw(&mut canvas);
+    assert_eq!(canvas.get_pixel(13, 22), 0xb);
+    assert_eq!(canvas.to_xrgb(&runtime.palette())[22 * 160 + 13], emulator::resource::DEFAULT_COLORS[0xb]);
     runtime.reset();
     runtime.run_script(&String::from("_engine.scene_set_background_color('5')"));
-    unsafe {
-        let scene = SCENE.as_mut().unwrap();
-        scene.draw(&mut canvas);
-        assert_eq!(canvas.get_pixel(159, 44), emulator::resource::COLORS[0x5]);
-    }
+    runtime.state.borrow().scene.draw(&mut canvas);
+    assert_eq!(canvas.get_pixel(159, 44), 0x5);
+    let mut prj = crate::loader::load(b"scene.setBackgroundColor(15)", None).unwrap();
+    prj.palette.0[15] = 0x123456;
+    runtime.reset();
+    runtime.run_project(&prj);
+    runtime.state.borrow().scene.draw(&mut canvas);
+    assert_eq!(canvas.to_xrgb(&runtime.palette())[0], 0x123456);
 }
 
-fn report_exceptions(try_catch: &mut v8::TryCatch<v8::HandleScope>) -> String {
+#[test]
+fn test_runtimes_side_by_side() {
+    let mut first = Runtime::new();
+    let mut second = Runtime::new();
+    first.run_script(&String::from("info.setScore(3); globalThis.k = SpriteKind.create()"));
+    second.run_script(&String::from("info.setScore(5); globalThis.k = SpriteKind.create(); SpriteKind.create()"));
+    assert_eq!((first.score(), second.score()), (3, 5));
+    assert_eq!(first.global_string("k"), second.global_string("k"));
+    first.run_script(&String::from("game.over(true)"));
+    assert_eq!((first.game_over(), second.game_over()), (Some(true), None));
+    //dropped in reverse creation order, as they share the thread
+    drop(second);
+    drop(first);
+}
+
+#[test]
+fn test_assets_at_top_level() {
+    let images = "namespace myImages {
+    helpers._registerFactory(\"image\", function(name: string) {
+        switch(helpers.stringTrim(name)) {
+            case \"hero\":return img`
+. 1
+1 1
+`;
+        }
+        return null;
+    })
+}
+";
+    //pxt.json lists main.ts before the .g.ts, it still runs last
+    let prj = crate::loader::project_from_sources(
+        &serde_json::json!({
+            "pxt.json": r#"{"files":["main.ts","images.g.ts"]}"#,
+            "main.ts": "const hero = assets.image`hero`\nscene.setBackgroundColor(hero ? 7 : 2)\nlet sp = sprites.create(hero, SpriteKind.Player)\n",
+            "images.g.ts": images,
+        }),
+        None,
+    )
+    .unwrap();
+    assert_eq!(prj.order, vec!["images.g.ts", "main.ts"]);
+    let mut runtime = Runtime::new();
+    let (mut canvas, mut expected) = (emulator::resource::Canvas::new(), emulator::resource::Canvas::new());
+    runtime.run_project(&prj);
+    runtime.draw(&mut canvas);
+    runtime.reset();
+    runtime.run_script(&String::from("scene.setBackgroundColor(7)"));
+    runtime.draw(&mut expected);
+    assert_eq!(canvas.get_pixel(5, 100), expected.get_pixel(5, 100));
+}
+
+#[test]
+fn test_python_floor_division() {
+    //3 - 5 + 8, python rounds 9 // -2 down
+    let prj = crate::loader::load(b"a = 15\na //= 4\nscene.set_background_color(a + 9 // -2 + 8)\n", Some("main.py")).unwrap();
+    let mut runtime = Runtime::new();
+    let (mut canvas, mut expected) = (emulator::resource::Canvas::new(), emulator::resource::Canvas::new());
+    runtime.run_project(&prj);
+    runtime.draw(&mut canvas);
+    runtime.reset();
+    runtime.run_script(&String::from("scene.setBackgroundColor(6)"));
+    runtime.draw(&mut expected);
+    assert_eq!(canvas.get_pixel(5, 100), expected.get_pixel(5, 100));
+}
+
+#[test]
+fn test_python_in() {
+    //keys of a dict, items of a list, substrings of a string: 4 + 2 + 1
+    let py = "d = {\"a\": 1}\nc = 0\nif \"a\" in d and \"b\" not in d:\n    c += 4\nif 3 in [1, 3]:\n    c += 2\nif \"ell\" in \"hello\":\n    c += 1\nscene.set_background_color(c)\n";
+    let prj = crate::loader::load(py.as_bytes(), Some("main.py")).unwrap();
+    let mut runtime = Runtime::new();
+    let (mut canvas, mut expected) = (emulator::resource::Canvas::new(), emulator::resource::Canvas::new());
+    runtime.run_project(&prj);
+    runtime.draw(&mut canvas);
+    runtime.reset();
+    runtime.run_script(&String::from("scene.setBackgroundColor(7)"));
+    runtime.draw(&mut expected);
+    assert_eq!(canvas.get_pixel(5, 100), expected.get_pixel(5, 100));
+}
+
+fn report_exceptions(try_catch: &mut v8::TryCatch<v8::HandleScope>, source_maps: &SourceMaps) -> String {
     let mut ret: Vec<u8> = vec![];
     let exception = try_catch.exception().unwrap();
     let exception_string = exception
@@ -741,8 +1090,16 @@ fn report_exceptions(try_catch: &mut v8::TryCatch<v8::HandleScope>) -> String {
         },
     );
     let line_number = message.get_line_number(try_catch).unwrap_or_default();
+    let start_column = message.get_start_column();
+    let end_column = message.get_end_column();
 
-    ret.extend(format!("{}:{}: {}\n", filename, line_number, exception_string).as_bytes());
+    //report the typescript location when the script has a source map
+    match source_maps.lookup(filename.as_str(), line_number, start_column) {
+        Some((line, column)) => ret.extend(
+            format!("{}:{}:{}: {}\n", filename, line, column, exception_string).as_bytes(),
+        ),
+        None => ret.extend(format!("{}:{}: {}\n", filename, line_number, exception_string).as_bytes()),
+    }
 
     // Print line of source code.
     let source_line = message
@@ -756,9 +1113,6 @@ fn report_exceptions(try_catch: &mut v8::TryCatch<v8::HandleScope>) -> String {
     ret.extend(format!("{}\n", source_line).as_bytes());
 
     // Print wavy underline (GetUnderline is deprecated).
-    let start_column = message.get_start_column();
-    let end_column = message.get_end_column();
-
     for _ in 0..start_column {
         ret.push(b' ');
     }
@@ -781,7 +1135,188 @@ fn report_exceptions(try_catch: &mut v8::TryCatch<v8::HandleScope>) -> String {
         .map(|s| s.to_rust_string_lossy(try_catch));
 
     if let Some(stack_trace) = stack_trace {
-        ret.extend(format!("{}", stack_trace).as_bytes());
+        ret.extend(source_maps.rewrite_stack(stack_trace.as_str()).as_bytes());
     }
     String::from_utf8(ret).unwrap()
 }
+
+#[test]
+fn test_time_accounting() {
+    let mut runtime = Runtime::new();
+    let prj = crate::loader::load(
+        b"let n = 0; game.onUpdateInterval(50, () => n++);
+let sp = sprites.create(img`1`, SpriteKind.Player); const x0 = sp.x; sp.vx = 60;",
+        None,
+    )
+    .unwrap();
+    runtime.run_project(&prj);
+    //below a milli second a frame
+    for _ in 0..100 {
+        runtime.update(500);
+    }
+    assert_eq!(runtime.eval("t", "n").unwrap(), "1");
+    //60 frames of 16.667ms are a second, 20 intervals and 60 pixels
+    for _ in 0..60 {
+        runtime.update(16667);
+    }
+    assert_eq!(runtime.eval("t", "[n, Math.round(sp.x - x0)].join()").unwrap(), "21,63");
+}
+
+#[test]
+fn test_game_over_screen() {
+    let mut runtime = Runtime::new();
+    runtime.run_script(&String::from("info.setScore(2); info.changeScoreBy(2); game.setGameOverMessage(false, 'bye'); game.over(false)"));
+    assert_eq!((runtime.game_over(), runtime.score()), (Some(false), 4));
+    runtime.process_events(Event::KeyDown(emulator::game::KeyCode::A));
+    assert!(!runtime.restart_requested());
+    runtime.update(1_000_000);
+    runtime.process_events(Event::KeyDown(emulator::game::KeyCode::A));
+    assert!(runtime.restart_requested());
+    runtime.reset();
+    assert_eq!((runtime.game_over(), runtime.state.borrow().game.high_score), (None, 4));
+    runtime.unload();
+    assert_eq!(runtime.state.borrow().game.high_score, 0);
+}
+
+#[test]
+fn test_unload() {
+    let mut runtime = Runtime::new();
+    let first = crate::loader::load(b"globalThis.a = 1; info.setScore(5)", None).unwrap();
+    runtime.run_project(&first);
+    runtime.unload();
+    assert_eq!(runtime.score(), 0);
+    assert!(runtime.eval("t", "1").is_err());
+    runtime.update(20000);
+    runtime.process_events(Event::KeyDown(emulator::game::KeyCode::A));
+
+    let second = crate::loader::load(b"globalThis.b = 2", None).unwrap();
+    runtime.run_project(&second);
+    assert_eq!(runtime.global_string("a"), None);
+    assert_eq!(runtime.global_string("b").as_deref(), Some("2"));
+}
+
+#[test]
+fn test_seeded_runs() {
+    let mut runtime = Runtime::new();
+    let prj = crate::loader::load(b"globalThis.r = [randint(0, 1000), Math.random()].join()", None).unwrap();
+    runtime.set_seed(Some(7));
+    runtime.run_project(&prj);
+    let first = runtime.global_string("r");
+    assert!(first.is_some());
+    runtime.reset();
+    runtime.run_project(&prj);
+    assert_eq!(runtime.global_string("r"), first);
+    runtime.set_seed(Some(8));
+    runtime.reset();
+    runtime.run_project(&prj);
+    assert_ne!(runtime.global_string("r"), first);
+}
+
+#[test]
+fn test_interleaved_seeded_runtimes() {
+    let prj = crate::loader::load(
+        b"globalThis.r = []; effects.blizzard.startScreenEffect(); game.onUpdate(() => r.push(randint(0, 1000)))",
+        None,
+    )
+    .unwrap();
+    let mut alone = Runtime::new();
+    alone.set_seed(Some(7));
+    alone.run_project(&prj);
+    for _ in 0..5 {
+        alone.update(20000);
+    }
+    let (r, state) = (alone.global_string("r"), alone.save_state());
+    drop(alone);
+
+    //each runtime draws from its own generator, taking turns changes nothing
+    let mut first = Runtime::new();
+    let mut second = Runtime::new();
+    first.set_seed(Some(7));
+    second.set_seed(Some(7));
+    first.run_project(&prj);
+    second.run_project(&prj);
+    for _ in 0..5 {
+        first.update(20000);
+        second.update(20000);
+    }
+    assert_eq!((first.global_string("r"), second.global_string("r")), (r.clone(), r));
+    assert!(first.save_state() == state && second.save_state() == state);
+    drop(second);
+    drop(first);
+}
+
+#[test]
+fn test_save_state() {
+    let mut runtime = Runtime::new();
+    let prj = crate::loader::load(
+        br"let ticks = 0;
+const seen = [];
+let sp = sprites.create(img`1`, SpriteKind.Player);
+sp.vx = 50;
+game.onUpdate(() => { ticks += 1; seen.push(ticks) });
+game.onUpdateInterval(100, () => info.changeScoreBy(1));",
+        None,
+    )
+    .unwrap();
+    runtime.run_project(&prj);
+    for _ in 0..7 {
+        runtime.update(20000);
+    }
+    let state = runtime.save_state();
+    let x = runtime.eval("t", "sp.x").unwrap();
+    let score = runtime.score();
+    for _ in 0..10 {
+        runtime.update(20000);
+    }
+    assert_ne!(runtime.eval("t", "sp.x").unwrap(), x);
+
+    runtime.restore_state(&state).unwrap();
+    assert_eq!(runtime.eval("t", "[ticks, seen.length, sp.x].join()").unwrap(), format!("7,7,{}", x));
+    assert_eq!(runtime.score(), score);
+    assert_eq!(runtime.save_state(), state);
+    //the handlers run again, on the restored data
+    runtime.update(20000);
+    assert_eq!(runtime.eval("t", "ticks").unwrap(), "8");
+
+    assert!(matches!(runtime.restore_state(b"MKSS"), Err(StateError::Truncated)));
+    assert!(matches!(runtime.restore_state(b"not a state"), Err(StateError::BadMagic)));
+    assert_eq!(runtime.eval("t", "ticks").unwrap(), "8");
+}
+
+#[test]
+fn test_restore_state_runs_nothing_again() {
+    //the top level makes a sprite, scores, draws from the generator and registers a handler
+    let mut runtime = Runtime::new();
+    runtime.set_seed(Some(7));
+    let prj = crate::loader::load(
+        br"let presses = 0;
+let [roll, hero] = [randint(0, 1 << 30), sprites.create(img`1`, SpriteKind.Player)];
+info.changeScoreBy(5);
+globalThis.topLevelRuns = (globalThis.topLevelRuns || 0) + 1;
+controller.anyButton.onEvent(ControllerButtonEvent.Pressed, () => { presses += 1 });",
+        None,
+    )
+    .unwrap();
+    runtime.run_project(&prj);
+    let state = runtime.save_state();
+    let saved = runtime.eval("t", "[roll, hero.x].join()").unwrap();
+    let next_roll = runtime.eval("t", "randint(0, 1 << 30)").unwrap();
+    let (mut before, mut after) = (emulator::resource::Canvas::new(), emulator::resource::Canvas::new());
+    runtime.draw(&mut before);
+
+    runtime.process_events(Event::KeyDown(emulator::game::KeyCode::A));
+    runtime.run_script(&String::from("hero.x += 10; roll = -1; sprites.create(img`2`, SpriteKind.Player); info.changeScoreBy(1)"));
+    runtime.restore_state(&state).unwrap();
+
+    assert_eq!(runtime.eval("t", "[roll, hero.x].join()").unwrap(), saved);
+    assert_eq!(runtime.eval("t", "[presses, topLevelRuns].join()").unwrap(), "0,1");
+    assert_eq!(runtime.score(), 5);
+    runtime.draw(&mut after);
+    assert_eq!(before.0, after.0);
+    //the generator goes on from the save, the top level didn't draw from it again
+    assert_eq!(runtime.eval("t", "randint(0, 1 << 30)").unwrap(), next_roll);
+    //one handler, not a second one from running the top level again
+    runtime.process_events(Event::KeyDown(emulator::game::KeyCode::A));
+    assert_eq!(runtime.eval("t", "presses").unwrap(), "1");
+}
+